@@ -0,0 +1,178 @@
+use std::sync::mpsc::{channel, Receiver};
+
+use crate::{format_score, from_fen, get_legal_move_list, mate_in, queen_promotion, rank_variations, CancelToken, DropMove, Event, GameState, Handler, Move};
+use crate::{SearchLimits, TaskHandle, ThreadPool, Variation, MAX_DEPTH};
+
+pub const DEFAULT_ANALYSIS_LINES: usize = 3;
+
+// the best lines once a depth has finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalysisUpdate {
+  pub depth: u8,
+  pub nodes: u64,
+  pub lines: Vec<Variation>,
+}
+
+// an endless search on a copy of a position, it keeps going deeper until stopped or dropped
+pub struct Analysis {
+  // the position being looked at, for turning the lines into moves
+  pub position: GameState,
+  // how many of the best lines are kept
+  pub lines: usize,
+  task: TaskHandle<()>,
+  updates: Receiver<AnalysisUpdate>,
+  latest: Option<AnalysisUpdate>,
+  // the latest lines in SAN, worked out once per update rather than every time theyre drawn
+  described: Vec<String>,
+}
+
+impl Analysis {
+  pub fn start(threads: &ThreadPool, state: &GameState, lines: usize) -> Self {
+    let position = sandbox(state);
+    let searched = position.clone();
+    let (sender, updates) = channel();
+    let task = threads.spawn(move |token: &CancelToken| {
+      let limits = SearchLimits { depth: MAX_DEPTH, nodes: None, time: None };
+      rank_variations(&searched, limits, token, |depth, nodes, variations| {
+        // nobody listening means the analysis was dropped, the token says so too
+        let _ = sender.send(AnalysisUpdate { depth, nodes, lines: variations.iter().take(lines.max(1)).cloned().collect() });
+      });
+    });
+    Analysis { position, lines, task, updates, latest: None, described: Vec::new() }
+  }
+
+  // the newest update, anything older that came in meanwhile is skipped
+  pub fn latest(&mut self) -> Option<&AnalysisUpdate> {
+    if let Some(update) = self.updates.try_iter().last() {
+      self.described = update.lines.iter().map(|variation| describe_variation(&self.position, variation)).collect();
+      self.latest = Some(update);
+    }
+    self.latest.as_ref()
+  }
+
+  // each line as its score from white's side and the moves in SAN
+  pub fn describe(&mut self) -> &[String] {
+    self.latest();
+    &self.described
+  }
+}
+
+impl Drop for Analysis {
+  fn drop(&mut self) {
+    self.task.cancel();
+  }
+}
+
+// a copy that can be played on without anyone hearing about it
+fn sandbox(state: &GameState) -> GameState {
+  let mut copy = state.clone();
+  copy.publisher = None;
+  copy.correspondence = None;
+  copy.render.silent = true;
+  copy
+}
+
+// the game as it was after the first ply moves, for stepping back and forth through it
+pub fn position_at(state: &GameState, ply: usize) -> Option<GameState> {
+  if ply > state.move_history.len() {
+    return None;
+  }
+  if ply == state.move_history.len() {
+    return Some(sandbox(state));
+  }
+  let mut position = match &state.start_fen {
+    Some(fen) => from_fen(fen).ok()?,
+    None => GameState::new_variant(state.variant),
+  };
+  position.variant = state.variant;
+  position.render = state.render;
+  position.render.silent = true;
+  get_legal_move_list(&mut position);
+  for record in &state.move_history[..ply] {
+    let event = match record.drop {
+      Some(piece) => Event::Drop(DropMove { piece, square: record.translation.1 }),
+      None => Event::Move { translation: record.translation, promotion: record.promotion },
+    };
+    position.handle_mut(event);
+  }
+  (position.move_history.len() == ply).then_some(position)
+}
+
+// "+0.35  1. e4 e5 2. Nf3", the line stops early if the engine wandered into a move the rules dont allow
+pub fn describe_variation(position: &GameState, variation: &Variation) -> String {
+  let white = if position.player_turn == 1 { 1 } else { -1 };
+  let mut board = sandbox(position);
+  let mut text = format_score(variation.score * white, mate_in(variation.score));
+  text.push(' ');
+  for (index, translation) in variation.pv.iter().enumerate() {
+    let Some(san) = play(&mut board, *translation) else {
+      break;
+    };
+    let move_number = board.turn_counter / 2;
+    match board.player_turn {
+      // black is to move, so white just played
+      2 => text.push_str(&format!(" {}. {}", move_number + 1, san)),
+      _ if index == 0 => text.push_str(&format!(" {}... {}", move_number, san)),
+      _ => text.push_str(&format!(" {}", san)),
+    }
+  }
+  text
+}
+
+fn play(board: &mut GameState, translation: Move) -> Option<String> {
+  let promotion = queen_promotion(&board.board, translation);
+  let played = board.move_history.len();
+  board.handle_mut(Event::Move { translation, promotion });
+  (board.move_history.len() > played).then(|| board.san_history.last().cloned().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::{Duration, Instant};
+  use crate::Coordinates;
+
+  #[test]
+  fn streams_lines_until_dropped() {
+    let pool = ThreadPool::new(1).unwrap();
+    // Ra8 mates, the other lines are all worse
+    let state = from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    let mut analysis = Analysis::start(&pool, &state, 2);
+    let started = Instant::now();
+    while analysis.latest().is_none_or(|update| update.depth < 2) {
+      assert!(started.elapsed() < Duration::from_secs(30));
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    let update = analysis.latest().unwrap().clone();
+    assert_eq!(update.lines.len(), 2);
+    assert!(update.lines[0].score >= update.lines[1].score);
+    assert_eq!(analysis.describe()[0], "White mates in 1  1. Ra8#");
+    // the mate is played on a silent copy, so the game ending isnt printed and the position is untouched
+    let mut board = sandbox(&analysis.position);
+    assert_eq!(play(&mut board, update.lines[0].pv[0]).as_deref(), Some("Ra8#"));
+    assert!(board.game_over && board.render.silent && !analysis.position.game_over);
+    drop(analysis);
+
+    // the pool is free again once the search has seen the token
+    let next = pool.spawn(|_| 1);
+    assert_eq!(next.join().ok(), Some(1));
+  }
+
+  #[test]
+  fn steps_back_through_a_game() {
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    state.render.silent = true;
+    for (origin, destination) in [(12, 28), (52, 36), (6, 21)] {
+      state.handle_mut(Event::move_from_indices(origin, destination, None).unwrap());
+    }
+    let position = position_at(&state, 1).unwrap();
+    assert_eq!(position.san_history, vec!["e4"]);
+    assert_eq!(position.player_turn, 2);
+    assert_eq!(position_at(&state, 3).unwrap().board, state.board);
+    assert!(position_at(&state, 4).is_none());
+
+    let line = Variation { score: 20, pv: vec![(Coordinates::from(52), Coordinates::from(36)), (Coordinates::from(6), Coordinates::from(21))] };
+    assert_eq!(describe_variation(&position, &line), "-0.20  1... e5 2. Nf3");
+  }
+}
@@ -0,0 +1,123 @@
+use crate::PieceColour::*;
+use crate::{announce, game_end_by_repetition, game_end_by_time, get_valid_moves_for_piece, is_square_occupied};
+use crate::{BoardRep, GameEnd, GameState, MoveList, Pawn, PieceColour, PlayerValidMoves, VariantRules};
+use crate::PAWN;
+
+pub struct AntichessRules;
+
+// if any capture is possible only captures are allowed
+pub fn remove_non_captures(list: MoveList, board: &BoardRep) -> MoveList {
+  let is_capture = |translation: &(_, _)| {
+    let (occupied, same_colour) = is_square_occupied(*translation, board);
+    // pawns moving sideways onto an empty square are taking en passant
+    (occupied && !same_colour) || (board.0[usize::from(translation.0)] == PAWN && translation.0.x != translation.1.x)
+  };
+  if !list.iter().any(is_capture) {
+    return list;
+  }
+  list.into_iter().filter(is_capture).collect()
+}
+
+impl VariantRules for AntichessRules {
+  fn castling_allowed(&self) -> bool {
+    false
+  }
+
+  // the king is just another piece, moving into or staying in check is fine
+  fn legal_moves(&self, state: &mut GameState) -> PlayerValidMoves {
+    let mut move_list = get_valid_moves_for_piece(&state.board);
+    if let Some(last_move) = state.last_move {
+      move_list = Pawn::append_en_passant_moves(state, move_list, last_move);
+    }
+    PlayerValidMoves {
+      white: remove_non_captures(move_list.white, &state.board),
+      black: remove_non_captures(move_list.black, &state.board),
+      ..Default::default()
+    }
+  }
+
+  fn in_check(&self, _board: &BoardRep, _colour: PieceColour) -> bool {
+    false
+  }
+
+  // runs before the turn passes, so the player about to move is the one who might have just won
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    let next_player = if state.player_turn == 1 { Black } else { White };
+    let white_lost = next_player == Black;
+
+    if !state.board.1.contains(&next_player) {
+      announce(state, &format!("{:?} Wins by losing every piece", next_player));
+      return Some(GameEnd::AllPiecesLost(white_lost));
+    }
+    if state.move_list.is_empty_for(next_player) {
+      announce(state, &format!("{:?} Wins by being stalemated", next_player));
+      return Some(GameEnd::StalemateWin(white_lost));
+    }
+    // the player who just moved being stuck doesnt matter, only the one about to move
+    if state.last_capture_or_pawn_move >= 100 {
+      announce(state, "Draw by 50 move rule");
+      return Some(GameEnd::FiftyMoveRuleDraw);
+    }
+    if game_end_by_repetition(state) {
+      announce(state, "Draw by threefold repetition");
+      return Some(GameEnd::RepetitionDraw);
+    }
+    game_end_by_time(state)
+  }
+
+  fn insufficient_material(&self, _state: &GameState) -> (bool, bool) {
+    (false, false)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{position, square};
+  use crate::{from_fen, get_legal_move_list, take_turn, Variant};
+
+  #[test]
+  fn captures_are_forced() {
+    let mut state = from_fen("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1").unwrap();
+    state.variant = Variant::Antichess;
+    get_legal_move_list(&mut state);
+    assert_eq!(state.move_list.white, vec![(square("e4"), square("d5"))]);
+    assert!(!state.white_in_check && !state.white_can_castle_kingside);
+  }
+
+  #[test]
+  fn losing_everything_wins() {
+    // the white king has to take black's last piece, which wins the game for black
+    let mut state = from_fen("8/8/8/8/8/8/1k6/K7 w - - 0 1").unwrap();
+    state.variant = Variant::Antichess;
+    get_legal_move_list(&mut state);
+    assert_eq!(state.move_list.white, vec![(square("a1"), square("b2"))]);
+    take_turn(&mut state, (square("a1"), square("b2")));
+    assert!(state.game_over);
+    assert!(!state.board.1.contains(&Black));
+  }
+
+  #[test]
+  fn only_the_player_to_move_can_be_stalemated() {
+    // after a3 white is stuck, but its black's turn and black can still move
+    let mut state = from_fen("r3k3/8/8/7p/p6P/8/P7/8 w - - 0 1").unwrap();
+    state.variant = Variant::Antichess;
+    get_legal_move_list(&mut state);
+    take_turn(&mut state, (square("a2"), square("a3")));
+    assert!(state.move_list.white.is_empty() && !state.move_list.black.is_empty());
+    assert!(!state.game_over);
+    assert_eq!(state.result, None);
+  }
+
+  #[test]
+  fn flags_fall_and_en_passant_counts_as_a_capture() {
+    let mut state = position("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+    state.variant = Variant::Antichess;
+    get_legal_move_list(&mut state);
+    // taking en passant is the only capture, so its forced
+    assert_eq!(state.move_list.white, vec![(square("e5"), square("d6"))]);
+
+    state.black_timer = std::time::Duration::ZERO;
+    assert_eq!(AntichessRules.game_end(&mut state), Some(GameEnd::TimeOut(false)));
+  }
+}
@@ -0,0 +1,198 @@
+use crate::PieceColour::*;
+use crate::{announce, boardrep_to_bitboard, get_valid_moves_for_piece, is_in_check, make_move, standard_game_end};
+use crate::{BoardRep, Coordinates, GameEnd, GameState, King, Move, MoveList, Pawn, PieceColour, PlayerValidMoves, VariantRules};
+use crate::{EMPTY, PAWN, KING};
+
+pub struct AtomicRules;
+
+// the capture square and the 8 around it
+pub fn blast_radius(square: Coordinates) -> Vec<usize> {
+  let mut squares = Vec::new();
+  for y in square.y.saturating_sub(1)..=(square.y + 1).min(7) {
+    for x in square.x.saturating_sub(1)..=(square.x + 1).min(7) {
+      squares.push(y * 8 + x);
+    }
+  }
+  squares
+}
+
+// a pawn moving sideways onto an empty square took en passant
+fn is_capture(board: &BoardRep, translation: Move) -> bool {
+  let mover = board.1[usize::from(translation.0)];
+  let target = board.1[usize::from(translation.1)];
+  (target != Empty && target != mover)
+  || (board.0[usize::from(translation.0)] == PAWN && target == Empty && translation.0.x != translation.1.x)
+}
+
+// the capturing piece goes too, pawns next to the blast survive unless they were part of the capture
+pub fn atomic_make_move(board: &BoardRep, translation: Move) -> BoardRep {
+  let capture = is_capture(board, translation);
+  let mut board = make_move(board, translation);
+  if !capture {
+    return board;
+  }
+  for index in blast_radius(translation.1) {
+    if board.0[index] != PAWN || index == usize::from(translation.1) {
+      board.0[index] = EMPTY;
+      board.1[index] = Empty;
+    }
+  }
+  board
+}
+
+fn has_king(board: &BoardRep, colour: PieceColour) -> bool {
+  (0..64).any(|index| board.0[index] == KING && board.1[index] == colour)
+}
+
+// touching kings cant be taken, any capture of one would blow up the other king as well
+pub fn atomic_in_check(board: &BoardRep, colour: PieceColour) -> bool {
+  let king_square = |colour| (0..64).find(|index| board.0[*index] == KING && board.1[*index] == colour).map(Coordinates::from);
+  let opponent = if colour == White { Black } else { White };
+  if let (Some(king), Some(enemy_king)) = (king_square(colour), king_square(opponent)) {
+    if blast_radius(king).contains(&usize::from(enemy_king)) {
+      return false;
+    }
+  }
+  is_in_check(board, colour)
+}
+
+fn legal_for(board: &BoardRep, list: MoveList, colour: PieceColour) -> MoveList {
+  let opponent = if colour == White { Black } else { White };
+  list
+    .into_iter()
+    .filter(|translation| {
+      // kings cant capture, they would explode themselves
+      if board.0[usize::from(translation.0)] == KING && is_capture(board, *translation) {
+        return false;
+      }
+      let simulated_board = atomic_make_move(board, *translation);
+      if !has_king(&simulated_board, colour) {
+        return false;
+      }
+      // blowing up the enemy king wins even if your own king is left in check
+      !has_king(&simulated_board, opponent) || !atomic_in_check(&simulated_board, colour)
+    })
+    .collect()
+}
+
+impl VariantRules for AtomicRules {
+  fn legal_moves(&self, state: &mut GameState) -> PlayerValidMoves {
+    let moves = get_valid_moves_for_piece(&state.board);
+    let mut output_move_list = PlayerValidMoves {
+      white: legal_for(&state.board, moves.white, White),
+      black: legal_for(&state.board, moves.black, Black),
+      ..Default::default()
+    };
+    output_move_list = King::append_castle_moves(output_move_list, state);
+    // en passant explodes too, so it needs the same check as any other capture
+    if let Some(last_move) = state.last_move {
+      let en_passant = Pawn::append_en_passant_moves(state, PlayerValidMoves::default(), last_move);
+      output_move_list.white.extend(legal_for(&state.board, en_passant.white, White));
+      output_move_list.black.extend(legal_for(&state.board, en_passant.black, Black));
+    }
+    output_move_list
+  }
+
+  fn in_check(&self, board: &BoardRep, colour: PieceColour) -> bool {
+    atomic_in_check(board, colour)
+  }
+
+  // take_turn has already moved the piece and counted the captured one, this removes everything else in the blast
+  fn after_move(&self, state: &mut GameState, translation: Move, premove_board: &BoardRep) {
+    if King::is_move_a_castle(translation, premove_board) || !is_capture(premove_board, translation) {
+      return;
+    }
+    let destination = usize::from(translation.1);
+    for index in blast_radius(translation.1) {
+      let piece = state.board.0[index];
+      if piece == EMPTY || (piece == PAWN && index != destination) {
+        continue;
+      }
+      match state.board.1[index] {
+        White => state.white_pieces.remove_piece(piece),
+        Black => state.black_pieces.remove_piece(piece),
+        Empty => {},
+      }
+      state.board.0[index] = EMPTY;
+      state.board.1[index] = Empty;
+    }
+
+    // rooks can be blown up without moving
+    King::check_to_disable_castling(state);
+    state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board)];
+  }
+
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    if !has_king(&state.board, White) {
+      announce(state, "Black Wins by exploding the king");
+      return Some(GameEnd::KingExploded(true));
+    }
+    if !has_king(&state.board, Black) {
+      announce(state, "White Wins by exploding the king");
+      return Some(GameEnd::KingExploded(false));
+    }
+    standard_game_end(state)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{is_legal, position, square};
+  use crate::{from_fen, take_turn, Variant, KNIGHT, QUEEN};
+
+  #[test]
+  fn capture_explodes_neighbours() {
+    let mut state = from_fen("4k3/8/8/2pnb3/8/8/8/3RK3 w - - 0 1").unwrap();
+    state.variant = Variant::Atomic;
+    crate::get_legal_move_list(&mut state);
+    take_turn(&mut state, (square("d1"), square("d5")));
+
+    for name in ["d1", "d5", "e5"] {
+      assert_eq!(state.board.0[usize::from(square(name))], EMPTY);
+    }
+    // pawns next to the blast survive
+    assert_eq!(state.board.0[usize::from(square("c5"))], PAWN);
+    assert_eq!(state.white_pieces.count(crate::ROOK), 0);
+    assert_eq!(state.black_pieces.count(KNIGHT), 0);
+  }
+
+  #[test]
+  fn exploding_the_king_wins() {
+    // queen takes the knight next to the king, even though it leaves the queen's own king in check
+    let mut state = from_fen("r3k3/3n4/8/8/8/8/8/K2Q4 w - - 0 1").unwrap();
+    state.variant = Variant::Atomic;
+    crate::get_legal_move_list(&mut state);
+    let blast = (square("d1"), square("d7"));
+    assert!(state.move_list.white.contains(&blast));
+    take_turn(&mut state, blast);
+    assert!(state.game_over);
+    assert_eq!(state.white_pieces.count(QUEEN), 0);
+
+    // touching kings are never in check
+    let touching = from_fen("8/8/8/8/8/3kK2r/8/8 w - - 0 1").unwrap().board;
+    assert!(!atomic_in_check(&touching, White));
+
+    // a capture next to your own king blows it up too, so it isnt offered
+    let mut own_king = position("4k3/8/8/8/8/8/3n4/3QK3 w - - 0 1");
+    own_king.variant = Variant::Atomic;
+    crate::get_legal_move_list(&mut own_king);
+    assert!(is_legal(&own_king, "d1", "c2"));
+    assert!(!is_legal(&own_king, "d1", "d2"));
+  }
+
+  #[test]
+  fn en_passant_explosions_are_checked() {
+    let atomic = |fen| {
+      let mut state = position(fen);
+      state.variant = Variant::Atomic;
+      crate::get_legal_move_list(&mut state);
+      state
+    };
+    assert!(is_legal(&atomic("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1"), "e5", "d6"));
+    // the blast on d6 reaches the king on c7
+    assert!(!is_legal(&atomic("7k/2K5/8/3pP3/8/8/8/8 w - d6 0 1"), "e5", "d6"));
+    // both pawns are gone afterwards, opening the rank for the rook
+    assert!(!is_legal(&atomic("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1"), "e5", "d6"));
+  }
+}
@@ -0,0 +1,11 @@
+use cheess::{GameServer, DEFAULT_PORT};
+
+// most clients connected at once, each one holds two threads and any more are turned away
+const MAX_CLIENTS: usize = 64;
+
+fn main() {
+    let port = std::env::args().nth(1).and_then(|port| port.parse::<u16>().ok()).unwrap_or(DEFAULT_PORT);
+    let server = GameServer::bind(("0.0.0.0", port), MAX_CLIENTS).expect("Error starting server");
+    println!("Chess server listening on port {}", port);
+    server.run();
+}
@@ -0,0 +1,7 @@
+use cheess::UciEngine;
+
+// for chess GUIs, they start this and talk to it over stdin and stdout
+fn main() {
+    let engine = UciEngine::new(std::io::stdout()).expect("Error creating threads");
+    engine.run(std::io::stdin().lock());
+}
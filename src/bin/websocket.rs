@@ -0,0 +1,12 @@
+use cheess::{WebSocketApi, DEFAULT_WEBSOCKET_PORT};
+
+// most browser tabs connected at once, each one holds a thread
+const MAX_CONNECTIONS: usize = 16;
+
+fn main() {
+    let port = std::env::args().nth(1).and_then(|port| port.parse::<u16>().ok()).unwrap_or(DEFAULT_WEBSOCKET_PORT);
+    // local only, theres no authentication
+    let api = WebSocketApi::bind(("127.0.0.1", port), MAX_CONNECTIONS).expect("Error starting WebSocket API");
+    println!("WebSocket API listening on ws://127.0.0.1:{}", port);
+    api.run();
+}
@@ -0,0 +1,201 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::{to_fen, write_message, Clocks, Dispatcher, Event, EventKind, GameEnd, GameState, Handler, HandlerId, MoveRecord};
+
+pub const DEFAULT_BROADCAST_PORT: u16 = 7879;
+
+// what spectators are sent, one JSON object per line for the TCP and file observers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Broadcast {
+  // the position when an observer joins part way through
+  Snapshot { fen: String, clocks: Clocks },
+  MoveMade { record: MoveRecord, fen: String, clocks: Clocks },
+  ClockUpdate { clocks: Clocks, white_to_move: bool },
+  GameEnded { result: Option<GameEnd> },
+  Chat { from: String, text: String },
+}
+
+pub const BROADCAST_EVENTS: [EventKind; 4] = [EventKind::MoveMade, EventKind::ClockTick, EventKind::GameEnded, EventKind::Chat];
+
+impl Broadcast {
+  pub fn snapshot(state: &GameState) -> Self {
+    Broadcast::Snapshot { fen: to_fen(state), clocks: Clocks::live(state) }
+  }
+
+  // None for the events spectators dont see
+  pub fn from_event(event: Event) -> Option<Self> {
+    match event {
+      Event::MoveMade { record, fen, clocks } => Some(Broadcast::MoveMade { record, fen, clocks }),
+      Event::ClockTick { clocks, white_to_move } => Some(Broadcast::ClockUpdate { clocks, white_to_move }),
+      Event::GameEnded(result) => Some(Broadcast::GameEnded { result }),
+      Event::Chat { from, text } => Some(Broadcast::Chat { from, text }),
+      Event::Move { .. } | Event::Drop(_) | Event::Surrender(_) | Event::UserInput | Event::DrawOffer | Event::RemoteInput(_) => None,
+    }
+  }
+}
+
+impl Event {
+  pub fn clock_tick(state: &GameState) -> Self {
+    Event::ClockTick { clocks: Clocks::live(state), white_to_move: state.player_turn == 1 }
+  }
+}
+
+impl GameState {
+  // nothing happens until a publisher is set, spectators are optional
+  pub fn publish(&self, event: Event) {
+    if let Some(publisher) = &self.publisher {
+      let _ = publisher.send(event);
+    }
+  }
+
+  pub fn publish_turn(&self) {
+    if let Some(record) = self.move_history.last() {
+      self.publish(Event::MoveMade { record: *record, fen: to_fen(self), clocks: Clocks::of(self) });
+    }
+    if self.game_over {
+      self.publish_game_end();
+    }
+  }
+
+  pub fn publish_game_end(&self) {
+    self.publish(Event::GameEnded(self.result));
+  }
+}
+
+// the ids are for unregistering the observer again
+pub fn register_observer(dispatcher: &mut Dispatcher, observer: Arc<Mutex<dyn Handler>>) -> Vec<HandlerId> {
+  BROADCAST_EVENTS.iter().map(|kind| dispatcher.register_handler(*kind, observer.clone())).collect()
+}
+
+// any number of read only watchers over TCP, eg. nc host 7879
+pub struct TcpBroadcaster {
+  // each watcher has a writer thread on the other end, so a stalled one never holds up the game
+  watchers: Arc<Mutex<Vec<Sender<Broadcast>>>>,
+}
+
+// ends when the watcher goes away, its sender is dropped on the next broadcast
+fn write_broadcasts(mut stream: TcpStream, queued: Receiver<Broadcast>) {
+  for message in queued {
+    if write_message(&mut stream, &message).is_err() {
+      return;
+    }
+  }
+}
+
+impl TcpBroadcaster {
+  // the accept loop runs on its own detached thread, blocked in accept until the program exits
+  pub fn start(listener: TcpListener, game: Arc<Mutex<GameState>>) -> Self {
+    let watchers = Arc::new(Mutex::new(Vec::new()));
+    let accepted = watchers.clone();
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+          continue;
+        };
+        let (outbox, queued) = channel();
+        let _ = outbox.send(Broadcast::snapshot(&game.lock().unwrap()));
+        accepted.lock().unwrap().push(outbox);
+        std::thread::spawn(move || write_broadcasts(stream, queued));
+      }
+    });
+    TcpBroadcaster { watchers }
+  }
+
+  pub fn watcher_count(&self) -> usize {
+    self.watchers.lock().unwrap().len()
+  }
+}
+
+impl Handler for TcpBroadcaster {
+  fn handle_mut(&mut self, event: Event) {
+    let Some(message) = Broadcast::from_event(event) else {
+      return;
+    };
+    // watchers that went away are dropped
+    self.watchers.lock().unwrap().retain(|watcher| watcher.send(message.clone()).is_ok());
+  }
+}
+
+// appends to a file so it can be followed with tail -f
+pub struct FileBroadcaster {
+  file: File,
+}
+
+impl FileBroadcaster {
+  pub fn create(path: &Path) -> io::Result<Self> {
+    Ok(FileBroadcaster { file: OpenOptions::new().create(true).append(true).open(path)? })
+  }
+}
+
+impl Handler for FileBroadcaster {
+  fn handle_mut(&mut self, event: Event) {
+    let Some(message) = Broadcast::from_event(event) else {
+      return;
+    };
+    if let Err(e) = write_message(&mut self.file, &message).and_then(|_| self.file.flush()) {
+      println!("Error writing broadcast: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{BufRead, BufReader};
+  use std::time::Duration;
+  use crate::{get_legal_move_list, ThreadPool};
+
+  #[test]
+  fn observers_follow_the_game() {
+    let pool = ThreadPool::new(1).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    state.publisher = Some(dispatcher.sender());
+    let game = Arc::new(Mutex::new(state));
+
+    // handlers run in order, so the file is written by the time the watcher hears about it
+    let file = std::env::temp_dir().join(format!("cheess_broadcast_{}.jsonl", std::process::id()));
+    register_observer(&mut dispatcher, Arc::new(Mutex::new(FileBroadcaster::create(&file).unwrap())));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let tcp = TcpBroadcaster::start(listener, game.clone());
+    let watcher = TcpStream::connect(address).unwrap();
+    watcher.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut watcher = BufReader::new(watcher);
+    let mut line = String::new();
+    watcher.read_line(&mut line).unwrap();
+    assert!(matches!(serde_json::from_str(&line).unwrap(), Broadcast::Snapshot { .. }));
+    while tcp.watcher_count() == 0 {
+      std::thread::yield_now();
+    }
+    register_observer(&mut dispatcher, Arc::new(Mutex::new(tcp)));
+    dispatcher.start();
+
+    game.lock().unwrap().handle_mut(Event::move_from_indices(12, 28, None).unwrap());
+    game.lock().unwrap().handle_mut(Event::Surrender(None));
+
+    let mut next = || {
+      line.clear();
+      watcher.read_line(&mut line).unwrap();
+      serde_json::from_str::<Broadcast>(&line).unwrap()
+    };
+    let Broadcast::MoveMade { fen, .. } = next() else {
+      panic!("first broadcast should be the move");
+    };
+    assert_eq!(fen, to_fen(&game.lock().unwrap()));
+    assert_eq!(next(), Broadcast::GameEnded { result: Some(GameEnd::Resignation(false)) });
+    assert_eq!(std::fs::read_to_string(&file).unwrap().lines().count(), 2);
+
+    std::fs::remove_file(&file).unwrap();
+    game.lock().unwrap().publisher = None;
+  }
+}
@@ -0,0 +1,142 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::PieceColour::*;
+use crate::{boardrep_to_bitboard, BoardRep, CastlingFiles, GameState, PieceSet};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+pub const CHESS960_POSITIONS: u16 = 960;
+// the normal chess starting position in Scharnagl numbering
+pub const STANDARD_CHESS960_NUMBER: u16 = 518;
+
+// where the two knights go among the 5 squares left after bishops and queen
+const KNIGHT_PLACEMENTS: [(usize, usize); 10] = [
+  (0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4),
+];
+
+/// White back rank, a file first, for Chess960 start position `number` (0..960).
+///
+/// Uses Scharnagl numbering so position 518 is RNBQKBNR.
+///
+/// # Panics
+///
+/// Panics if `number` is 960 or more.
+pub fn chess960_back_rank(number: u16) -> [u8; 8] {
+  assert!(number < CHESS960_POSITIONS, "Chess960 position {} out of range", number);
+  let mut rank = [EMPTY; 8];
+  let number = number as usize;
+
+  //light squared bishop on b d f h, dark squared on a c e g
+  rank[(number % 4) * 2 + 1] = BISHOP;
+  let number = number / 4;
+  rank[(number % 4) * 2] = BISHOP;
+  let number = number / 4;
+
+  let nth_empty = |rank: &[u8; 8], n: usize| {
+    rank.iter().enumerate().filter(|(_, piece)| **piece == EMPTY).nth(n).map(|(file, _)| file).unwrap()
+  };
+
+  let queen = nth_empty(&rank, number % 6);
+  rank[queen] = QUEEN;
+  let number = number / 6;
+
+  //both knights picked before placing either so the indices dont shift
+  let (first, second) = KNIGHT_PLACEMENTS[number];
+  let first = nth_empty(&rank, first);
+  let second = nth_empty(&rank, second);
+  rank[first] = KNIGHT;
+  rank[second] = KNIGHT;
+
+  //king always ends up between the rooks
+  for piece in [ROOK, KING, ROOK] {
+    let file = nth_empty(&rank, 0);
+    rank[file] = piece;
+  }
+  rank
+}
+
+pub fn chess960_board(number: u16) -> BoardRep {
+  let back_rank = chess960_back_rank(number);
+  let mut piece_type = vec![EMPTY; 64];
+  let mut piece_colour = vec![Empty; 64];
+
+  for file in 0..8 {
+    piece_type[file] = back_rank[file];
+    piece_type[8 + file] = PAWN;
+    piece_type[48 + file] = PAWN;
+    piece_type[56 + file] = back_rank[file];
+    piece_colour[file] = White;
+    piece_colour[8 + file] = White;
+    piece_colour[48 + file] = Black;
+    piece_colour[56 + file] = Black;
+  }
+  (piece_type, piece_colour)
+}
+
+// not cryptographic, just needs to be different each game
+pub fn random_chess960_number() -> u16 {
+  let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+  (nanos % CHESS960_POSITIONS as u32) as u16
+}
+
+impl GameState {
+  pub fn new_chess960(number: u16) -> Self {
+    let board = chess960_board(number);
+    let back_rank = &board.0[0..8];
+    let rook_files = back_rank.iter().enumerate().filter(|(_, piece)| **piece == ROOK).map(|(file, _)| file).collect::<Vec<_>>();
+
+    let mut state = GameState::new();
+    state.castling_files = CastlingFiles {
+      king: back_rank.iter().position(|piece| *piece == KING).unwrap(),
+      queenside_rook: rook_files[0],
+      kingside_rook: rook_files[1],
+    };
+    state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&board)];
+    state.white_pieces = PieceSet::from_board(&board, White);
+    state.black_pieces = PieceSet::from_board(&board, Black);
+    state.board = board;
+    state.chess960 = true;
+    state
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{get_legal_move_list, take_turn, Coordinates, GameState};
+
+  #[test]
+  fn all_positions_valid_and_distinct() {
+    assert_eq!(chess960_board(STANDARD_CHESS960_NUMBER), GameState::new().board);
+
+    let mut seen = std::collections::HashSet::new();
+    for number in 0..CHESS960_POSITIONS {
+      let rank = chess960_back_rank(number);
+      let files_of = |piece| (0..8).filter(|file| rank[*file] == piece).collect::<Vec<_>>();
+      let bishops = files_of(BISHOP);
+      let rooks = files_of(ROOK);
+      let king = files_of(KING)[0];
+
+      assert_ne!(bishops[0] % 2, bishops[1] % 2);
+      assert!(rooks[0] < king && king < rooks[1]);
+      assert_eq!(files_of(KNIGHT).len(), 2);
+      assert!(seen.insert(rank));
+    }
+  }
+
+  #[test]
+  fn castling_onto_rook() {
+    // position 0 is BBQNNRKR, king on g with nothing to cross kingside
+    let mut state = GameState::new_chess960(0);
+    state.board.0[5] = EMPTY;
+    state.board.1[5] = Empty;
+    get_legal_move_list(&mut state);
+
+    let castle = (Coordinates { x: 6, y: 0 }, Coordinates { x: 7, y: 0 });
+    assert!(state.move_list.white.contains(&castle));
+    take_turn(&mut state, castle);
+
+    assert_eq!((state.board.0[6], state.board.0[5], state.board.0[7]), (KING, ROOK, EMPTY));
+    assert!(!state.white_can_castle_kingside && !state.white_can_castle_queenside);
+    assert!(state.black_can_castle_kingside);
+  }
+}
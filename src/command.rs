@@ -0,0 +1,215 @@
+use std::str::FromStr;
+
+use crate::{parse_drop, parse_long_algebraic, parse_move_from_coordinates, parse_move_from_index, parse_san};
+use crate::{drop_to_san, move_to_san, queen_promotion, Coordinates, Event, GameMode, GameState, Move};
+
+pub const HELP: &str = "\
+Moves: e4, Nf3, O-O, e7e8q, e2e4, 12 28, (4,1) (4,3), N@f3 for drops
+Commands:
+  help               this list
+  moves [square]     legal moves, or just the ones from a square
+  undo               take back the last move, not in network games
+  fen                the position as FEN
+  pgn                the game so far as PGN
+  flip               show the board from the other side
+  board [style]      show the board, style is unicode, ascii, colour or plain
+  clock              both clocks and whose turn it is
+  eval               how the position looks
+  hint               a suggested move
+  draw               offer a draw, or accept one
+  resign             give up the game
+  save <file>        save the game
+  load <file>        load a saved game
+  mode [name]        show or change the mode: default, blitz, rapid, classical, daily
+  reset              start again
+  say <text>         chat to the opponent and spectators
+  broadcast [port]   let spectators watch over TCP
+  broadcast-file <file>  write the game to a file as it goes
+  record <file>      log every event to replay later
+  replay <file>      play back a recorded game
+  tui                full screen board, esc comes back here
+  exit               quit";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+  Help,
+  Moves(Option<Coordinates>),
+  Undo,
+  Fen,
+  Pgn,
+  Flip,
+  // None just shows the board
+  Board(Option<BoardSetting>),
+  Clock,
+  Eval,
+  Hint,
+  Draw,
+  Resign,
+  Save(String),
+  Load(String),
+  // None shows the current mode
+  Mode(Option<GameMode>),
+  Reset,
+  Say(String),
+  Broadcast(Option<u16>),
+  BroadcastFile(String),
+  Record(String),
+  Replay(String),
+  // full screen mode
+  Tui,
+  Exit,
+  // anything that isnt a command is tried as a move
+  Play(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardSetting {
+  Unicode,
+  Ascii,
+  Colour,
+  Plain,
+}
+
+impl FromStr for BoardSetting {
+  type Err = String;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input.trim().to_lowercase().as_str() {
+      "unicode" => Ok(BoardSetting::Unicode),
+      "ascii" => Ok(BoardSetting::Ascii),
+      "colour" | "color" => Ok(BoardSetting::Colour),
+      "plain" => Ok(BoardSetting::Plain),
+      _ => Err(format!("Unknown board style: {}, try unicode, ascii, colour or plain", input)),
+    }
+  }
+}
+
+fn required(argument: &str, usage: &str) -> Result<String, String> {
+  if argument.is_empty() {
+    Err(format!("Usage: {}", usage))
+  } else {
+    Ok(argument.to_owned())
+  }
+}
+
+impl Command {
+  pub fn parse(input: &str) -> Result<Command, String> {
+    let input = input.trim();
+    let (word, argument) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let argument = argument.trim();
+    let no_argument = |command: Command| if argument.is_empty() { Ok(command) } else { Err(format!("{} doesnt take anything after it", word)) };
+    match word.to_lowercase().as_str() {
+      "help" | "?" => no_argument(Command::Help),
+      "moves" if argument.is_empty() => Ok(Command::Moves(None)),
+      "moves" => Coordinates::from_algebraic(&argument.to_ascii_lowercase())
+        .map(|square| Command::Moves(Some(square)))
+        .ok_or_else(|| format!("Invalid square: {}", argument)),
+      "undo" | "takeback" => no_argument(Command::Undo),
+      "fen" => no_argument(Command::Fen),
+      "pgn" => no_argument(Command::Pgn),
+      "flip" => no_argument(Command::Flip),
+      "board" if argument.is_empty() => Ok(Command::Board(None)),
+      "board" => argument.parse::<BoardSetting>().map(|setting| Command::Board(Some(setting))),
+      "clock" | "time" => no_argument(Command::Clock),
+      "eval" => no_argument(Command::Eval),
+      "hint" => no_argument(Command::Hint),
+      "draw" => no_argument(Command::Draw),
+      "resign" => no_argument(Command::Resign),
+      "save" => required(argument, "save <file>").map(Command::Save),
+      "load" => required(argument, "load <file>").map(Command::Load),
+      "mode" if argument.is_empty() => Ok(Command::Mode(None)),
+      "mode" => argument.parse::<GameMode>().map(|mode| Command::Mode(Some(mode))),
+      "reset" => no_argument(Command::Reset),
+      "say" => required(argument, "say <text>").map(Command::Say),
+      "broadcast" if argument.is_empty() => Ok(Command::Broadcast(None)),
+      "broadcast" => argument.parse::<u16>()
+        .map(|port| Command::Broadcast(Some(port)))
+        .map_err(|_| format!("Invalid port: {}", argument)),
+      "broadcast-file" => required(argument, "broadcast-file <file>").map(Command::BroadcastFile),
+      "record" => required(argument, "record <file>").map(Command::Record),
+      "replay" => required(argument, "replay <file>").map(Command::Replay),
+      "tui" => no_argument(Command::Tui),
+      "exit" | "quit" => no_argument(Command::Exit),
+      _ => Ok(Command::Play(input.to_owned())),
+    }
+  }
+}
+
+// every move format the game understands, tried from the most to the least specific
+pub fn parse_move(state: &GameState, input: &str) -> Result<Event, String> {
+  if let Some(drop) = parse_drop(input) {
+    return Ok(Event::Drop(drop));
+  }
+  if let Some(translation) = parse_move_from_index(input).or_else(|| parse_move_from_coordinates(input)) {
+    return Ok(Event::Move { translation, promotion: None });
+  }
+  if let Some((translation, promotion)) = parse_long_algebraic(input) {
+    return Ok(Event::Move { translation, promotion });
+  }
+  match parse_san(state, input) {
+    Ok((translation, promotion)) => Ok(Event::Move { translation, promotion }),
+    // only words that look like a move get the move error
+    Err(e) if input.contains(|c: char| ('1'..='8').contains(&c)) || input.to_uppercase().starts_with("O-O") => Err(e),
+    Err(_) => Err(format!("Unknown command or move: {}, type help for the list", input.trim())),
+  }
+}
+
+pub fn hint_to_san(state: &GameState, translation: Move) -> String {
+  move_to_san(state, translation, queen_promotion(&state.board, translation))
+}
+
+// legal moves for the player to move, optionally only the ones from one square
+pub fn legal_moves_san(state: &GameState, from: Option<Coordinates>) -> Vec<String> {
+  let (moves, drops) = if state.player_turn == 1 {
+    (&state.move_list.white, &state.move_list.white_drops)
+  } else {
+    (&state.move_list.black, &state.move_list.black_drops)
+  };
+  let mut legal = moves.iter()
+    .filter(|(origin, _)| from.is_none_or(|square| square == *origin))
+    .map(|translation| hint_to_san(state, *translation))
+    .collect::<Vec<_>>();
+  if from.is_none() {
+    legal.extend(drops.iter().map(|drop| drop_to_san(*drop)));
+  }
+  legal.sort();
+  legal
+}
+
+// pawns from white's side, or how far away mate is
+pub fn format_score(white_score: i32, mate_in: Option<i32>) -> String {
+  match mate_in {
+    Some(moves) => {
+      let winner = if white_score > 0 { "White" } else { "Black" };
+      format!("{} mates in {}", winner, moves.abs())
+    },
+    None => format!("{:+.2}", white_score as f64 / 100.0),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::get_legal_move_list;
+
+  #[test]
+  fn commands_and_moves_parse() {
+    assert_eq!(Command::parse(" moves e2 "), Ok(Command::Moves(Coordinates::from_algebraic("e2"))));
+    assert_eq!(Command::parse("mode blitz"), Ok(Command::Mode(Some(GameMode::Blitz))));
+    assert_eq!(Command::parse("save"), Err("Usage: save <file>".to_owned()));
+    assert!(Command::parse("moves z9").is_err());
+    assert_eq!(Command::parse("Nf3"), Ok(Command::Play("Nf3".to_owned())));
+
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    for input in ["e4", "e2e4", "12 28", "(4,1) (4,3)"] {
+      let Ok(Event::Move { translation, .. }) = parse_move(&state, input) else {
+        panic!("{} should be a move", input);
+      };
+      assert_eq!(translation, (Coordinates { x: 4, y: 1 }, Coordinates { x: 4, y: 3 }));
+    }
+    assert_eq!(parse_move(&state, "e5").unwrap_err(), "Illegal move: e5");
+    assert!(parse_move(&state, "banana").unwrap_err().starts_with("Unknown command or move"));
+    assert_eq!(legal_moves_san(&state, Coordinates::from_algebraic("g1")), vec!["Nf3", "Nh3"]);
+  }
+}
@@ -0,0 +1,206 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::PieceColour::*;
+use crate::{queen_promotion, rank_moves, search, CancelToken, Event, GameState, Move, PieceColour, SearchLimits};
+
+// the range the strength limit covers, roughly a new player up to where the full search plays
+pub const MIN_ELO: u16 = 400;
+pub const MAX_ELO: u16 = 2200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Difficulty {
+  Beginner,
+  Easy,
+  #[default]
+  Medium,
+  Hard,
+  Maximum,
+  // plays like a player of about this rating instead of a weaker search
+  Elo(u16),
+}
+
+pub const DIFFICULTIES: [Difficulty; 5] = [Difficulty::Beginner, Difficulty::Easy, Difficulty::Medium, Difficulty::Hard, Difficulty::Maximum];
+
+impl Difficulty {
+  // the easy levels make human mistakes, the hard ones are held back by thinking time
+  pub fn elo(&self) -> Option<u16> {
+    match self {
+      Difficulty::Beginner => Some(800),
+      Difficulty::Easy => Some(1200),
+      Difficulty::Elo(elo) => Some(*elo),
+      Difficulty::Medium | Difficulty::Hard | Difficulty::Maximum => None,
+    }
+  }
+
+  pub fn limits(&self) -> SearchLimits {
+    match self {
+      Difficulty::Medium => SearchLimits { depth: 3, nodes: None, time: Some(Duration::from_secs(1)) },
+      Difficulty::Hard => SearchLimits { depth: 4, nodes: None, time: Some(Duration::from_secs(3)) },
+      Difficulty::Maximum => SearchLimits { depth: 6, nodes: None, time: Some(Duration::from_secs(10)) },
+      _ => Weakening::for_elo(self.elo().unwrap_or(MAX_ELO)).limits(),
+    }
+  }
+}
+
+impl FromStr for Difficulty {
+  type Err = String;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input.trim().to_lowercase().as_str() {
+      "1" | "beginner" => Ok(Difficulty::Beginner),
+      "2" | "easy" => Ok(Difficulty::Easy),
+      "3" | "" | "medium" => Ok(Difficulty::Medium),
+      "4" | "hard" => Ok(Difficulty::Hard),
+      "5" | "max" | "maximum" => Ok(Difficulty::Maximum),
+      elo => match elo.trim_start_matches("elo").trim().parse::<u16>() {
+        Ok(elo) if (MIN_ELO..=MAX_ELO).contains(&elo) => Ok(Difficulty::Elo(elo)),
+        Ok(_) => Err(format!("Elo has to be between {} and {}", MIN_ELO, MAX_ELO)),
+        Err(_) => Err(format!("Unknown difficulty: {}, try beginner, easy, medium, hard, maximum or an elo like 1500", input)),
+      },
+    }
+  }
+}
+
+// "white", "black" or "random" for the side the human plays
+pub fn parse_player_colour(input: &str) -> Result<PieceColour, String> {
+  match input.trim().to_lowercase().as_str() {
+    "w" | "white" => Ok(White),
+    "b" | "black" => Ok(Black),
+    "" | "r" | "random" => Ok(random_colour()),
+    _ => Err(format!("Unknown colour: {}, try white, black or random", input)),
+  }
+}
+
+// the clock alone can be too coarse for its last bit to change, the shuffle mixes the higher bits in
+pub fn random_colour() -> PieceColour {
+  if Rng::from_time().below(2) == 0 { White } else { Black }
+}
+
+// xorshift, plenty for picking moves without another dependency
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+  pub fn seeded(seed: u64) -> Self {
+    // xorshift never leaves zero
+    Rng(seed.max(1))
+  }
+
+  pub fn from_time() -> Self {
+    Rng::seeded(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64)
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  // 0 up to but not including below
+  pub fn below(&mut self, below: u64) -> u64 {
+    if below == 0 { 0 } else { self.next_u64() % below }
+  }
+}
+
+// how a strength limited engine goes wrong on purpose, worked out from the rating it should play like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weakening {
+  // how far ahead it looks
+  pub depth: u8,
+  // any move within this many centipawns of the best might be played, closer ones more often
+  pub margin: i32,
+  // chances in a thousand of a move picked without looking, hanging pieces and all
+  pub blunders: u64,
+}
+
+impl Weakening {
+  // MIN_ELO looks one ply ahead, blunders about one move in five and settles for moves two pawns worse
+  // MAX_ELO is the plain search at depth 5
+  pub fn for_elo(elo: u16) -> Self {
+    let elo = elo.clamp(MIN_ELO, MAX_ELO);
+    let weakness = (MAX_ELO - elo) as i32;
+    Weakening {
+      depth: 1 + ((elo - MIN_ELO) / 450) as u8,
+      margin: weakness / 9,
+      blunders: weakness as u64 / 9,
+    }
+  }
+
+  pub fn limits(&self) -> SearchLimits {
+    SearchLimits { depth: self.depth, nodes: None, time: Some(Duration::from_secs(3)) }
+  }
+
+  // the search ranks every move, then one of the near best is picked at random
+  pub fn choose(&self, state: &GameState, limits: SearchLimits, token: &CancelToken, rng: &mut Rng) -> Option<Move> {
+    let limits = SearchLimits { depth: limits.depth.min(self.depth), ..limits };
+    let ranked = rank_moves(state, limits, token);
+    if rng.below(1000) < self.blunders {
+      return ranked.get(rng.below(ranked.len() as u64) as usize).map(|(translation, _)| *translation);
+    }
+    let best = ranked.first()?.1;
+    let weights = ranked.iter()
+      .map(|(_, score)| (self.margin + 1 - (best - score)).max(0) as u64)
+      .collect::<Vec<_>>();
+    let mut pick = rng.below(weights.iter().sum());
+    for ((translation, _), weight) in ranked.iter().zip(weights) {
+      if pick < weight {
+        return Some(*translation);
+      }
+      pick -= weight;
+    }
+    Some(ranked[0].0)
+  }
+}
+
+// the other side of the board from a human player
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Computer {
+  pub colour: PieceColour,
+  pub difficulty: Difficulty,
+}
+
+impl Computer {
+  pub fn against(human: PieceColour, difficulty: Difficulty) -> Self {
+    Computer { colour: if human == White { Black } else { White }, difficulty }
+  }
+
+  pub fn to_move(&self, state: &GameState) -> bool {
+    !state.game_over && state.player_turn == if self.colour == White { 1 } else { 2 }
+  }
+
+  // the same event a human's move would be, None when theres nothing to play
+  pub fn choose_move(&self, state: &GameState, token: &CancelToken) -> Option<Event> {
+    let translation = match self.difficulty.elo() {
+      Some(elo) => {
+        let weakening = Weakening::for_elo(elo);
+        weakening.choose(state, weakening.limits(), token, &mut Rng::from_time())?
+      },
+      None => search(state, self.difficulty.limits(), token).best_move?,
+    };
+    Some(Event::Move { translation, promotion: queen_promotion(&state.board, translation) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{get_legal_move_list, Handler};
+
+  #[test]
+  fn computer_replies_on_its_turn() {
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    let computer = Computer::against(White, "easy".parse().unwrap());
+    assert!(!computer.to_move(&state));
+    state.handle_mut(Event::move_from_indices(12, 28, None).unwrap());
+    assert!(computer.to_move(&state));
+
+    let reply = computer.choose_move(&state, &CancelToken::new()).unwrap();
+    let played = state.move_history.len();
+    state.handle_mut(reply);
+    assert_eq!(state.move_history.len(), played + 1);
+    assert_eq!(state.player_turn, 1);
+  }
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+use crate::PieceColour::*;
+use crate::{boardrep_to_bitboard, drop_to_san, finish_turn, is_in_check, standard_legal_moves, to_fen, BoardRep, Coordinates, GameState};
+use crate::{Move, MoveRecord, PieceColour, PieceSet, PlayerValidMoves, VariantRules};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN};
+
+// order pockets are written in, same as lichess
+pub const DROPPABLE_PIECES: [u8; 5] = [QUEEN, ROOK, BISHOP, KNIGHT, PAWN];
+
+// a piece from the pocket placed on an empty square
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DropMove {
+  pub piece: u8,
+  pub square: Coordinates,
+}
+
+pub struct CrazyhouseRules;
+
+impl GameState {
+  pub fn pocket(&self, colour: PieceColour) -> &PieceSet {
+    match colour {
+      White => &self.white_pocket,
+      Black => &self.black_pocket,
+      Empty => panic!("Empty colour has no pocket"),
+    }
+  }
+
+  pub fn pocket_mut(&mut self, colour: PieceColour) -> &mut PieceSet {
+    match colour {
+      White => &mut self.white_pocket,
+      Black => &mut self.black_pocket,
+      Empty => panic!("Empty colour has no pocket"),
+    }
+  }
+
+  pub fn is_promoted(&self, square: usize) -> bool {
+    self.promoted_squares & (1 << square) != 0
+  }
+}
+
+// every drop from the pocket that doesnt leave your own king in check
+pub fn drop_moves(state: &GameState, colour: PieceColour) -> Vec<DropMove> {
+  let pocket = state.pocket(colour);
+  let mut drops = Vec::new();
+
+  for piece in DROPPABLE_PIECES {
+    if pocket.count(piece) == 0 {
+      continue;
+    }
+    for index in 0..64 {
+      let square = Coordinates::from(index);
+      if state.board.1[index] != Empty {
+        continue;
+      }
+      // pawns never go on the first or last rank
+      if piece == PAWN && (square.y == 0 || square.y == 7) {
+        continue;
+      }
+      let mut simulated_board = state.board.clone();
+      simulated_board.0[index] = piece;
+      simulated_board.1[index] = colour;
+      if !is_in_check(&simulated_board, colour) {
+        drops.push(DropMove { piece, square });
+      }
+    }
+  }
+  drops
+}
+
+impl VariantRules for CrazyhouseRules {
+  fn legal_moves(&self, state: &mut GameState) -> PlayerValidMoves {
+    let mut moves = standard_legal_moves(state);
+    moves.white_drops = drop_moves(state, White);
+    moves.black_drops = drop_moves(state, Black);
+    moves
+  }
+
+  fn after_move(&self, state: &mut GameState, translation: Move, premove_board: &BoardRep) {
+    let origin = usize::from(translation.0);
+    let destination = usize::from(translation.1);
+    let mover = premove_board.1[origin];
+    let captured_colour = premove_board.1[destination];
+
+    // promoted pieces go back in the pocket as pawns
+    let captured_piece = if captured_colour != Empty && captured_colour != mover {
+      if state.is_promoted(destination) { PAWN } else { premove_board.0[destination] }
+    } else if premove_board.0[origin] == PAWN && translation.0.x != translation.1.x {
+      // en passant, the pawn wasnt on the destination square
+      PAWN
+    } else {
+      EMPTY
+    };
+    if captured_piece != EMPTY {
+      state.pocket_mut(mover).add_piece(captured_piece);
+    }
+
+    let was_promoted = state.is_promoted(origin);
+    let promoted_now = premove_board.0[origin] == PAWN && state.board.0[destination] != PAWN;
+    state.promoted_squares &= !(1 << origin) & !(1 << destination);
+    if was_promoted || promoted_now {
+      state.promoted_squares |= 1 << destination;
+    }
+  }
+
+  // a piece can always be dropped back in, so nobody runs out of mating material
+  fn insufficient_material(&self, _state: &GameState) -> (bool, bool) {
+    (false, false)
+  }
+}
+
+pub fn take_drop(state: &mut GameState, drop: DropMove) {
+  if state.move_history.is_empty() && state.start_fen.is_none() {
+    state.start_fen = Some(to_fen(state));
+  }
+  let colour = match state.player_turn {
+    1 => White,
+    2 => Black,
+    _ => panic!("Invalid player turn number"),
+  };
+  let index = usize::from(drop.square);
+  state.board.0[index] = drop.piece;
+  state.board.1[index] = colour;
+  state.pocket_mut(colour).remove_piece(drop.piece);
+  match colour {
+    White => state.white_pieces.add_piece(drop.piece),
+    _ => state.black_pieces.add_piece(drop.piece),
+  }
+
+  state.move_history.push(MoveRecord { translation: (drop.square, drop.square), promotion: None, drop: Some(drop.piece) });
+  if state.san_history.len() + 1 == state.move_history.len() {
+    state.san_history.push(drop_to_san(drop));
+  }
+  // no pawn moved two squares so nothing can be taken en passant
+  state.last_move = None;
+
+  if drop.piece == PAWN {
+    state.last_capture_or_pawn_move = 0;
+    state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board)];
+  } else {
+    state.last_capture_or_pawn_move += 1;
+    state.table_states_since_last_capture_or_pawn_move.push(boardrep_to_bitboard(&state.board));
+  }
+
+  finish_turn(state);
+}
+
+// N@e4 style, a bare @e4 drops a pawn
+pub fn parse_drop(input: &str) -> Option<DropMove> {
+  let (piece, square) = input.trim().split_once('@')?;
+  let piece = match piece.to_ascii_lowercase().as_str() {
+    "" | "p" => PAWN,
+    "n" => KNIGHT,
+    "b" => BISHOP,
+    "r" => ROOK,
+    "q" => QUEEN,
+    _ => return None,
+  };
+  let square = Coordinates::from_algebraic(&square.to_ascii_lowercase())?;
+  Some(DropMove { piece, square })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::square;
+  use crate::{from_fen, to_fen, take_turn, Variant, KING};
+
+  #[test]
+  fn capture_then_drop() {
+    let mut state = from_fen("4k3/8/8/3p4/4P3/8/8/4K3[] w - - 0 1").unwrap();
+    assert_eq!(state.variant, Variant::Crazyhouse);
+    take_turn(&mut state, (square("e4"), square("d5")));
+    assert_eq!(state.white_pocket.count(PAWN), 1);
+    assert!(state.move_list.black_drops.is_empty());
+
+    take_turn(&mut state, (square("e8"), square("d7")));
+    let drop = parse_drop("@c6").unwrap();
+    assert!(state.move_list.white_drops.contains(&drop));
+    // no pawns on the back ranks
+    assert!(!state.move_list.white_drops.contains(&parse_drop("p@a8").unwrap()));
+
+    take_drop(&mut state, drop);
+    assert_eq!(state.board.0[usize::from(drop.square)], PAWN);
+    assert_eq!(state.white_pocket.count(PAWN), 0);
+    assert_eq!(to_fen(&state), "8/3k4/2P5/3P4/8/8/8/4K3[] b - - 0 2");
+    assert_eq!(state.board.0[usize::from(square("d7"))], KING);
+  }
+
+  #[test]
+  fn promoted_piece_returns_as_pawn() {
+    let mut state = from_fen("3rk3/8/8/8/8/8/8/3QK3/Nb w - - 0 1").unwrap();
+    state.promoted_squares = 1 << usize::from(square("d1"));
+    assert_eq!(to_fen(&state), "3rk3/8/8/8/8/8/8/3Q~K3[Nb] w - - 0 1");
+
+    take_turn(&mut state, (square("d1"), square("d7")));
+    take_turn(&mut state, (square("d8"), square("d7")));
+    assert_eq!(state.black_pocket.count(PAWN), 1);
+    assert_eq!(state.black_pocket.count(QUEEN), 0);
+  }
+}
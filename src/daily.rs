@@ -0,0 +1,266 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+use crate::{get_legal_move_list, load_game, save_game, GameEnd, GameMode, GameState, SaveError, TimeControl, TimingMethod};
+
+pub const DAILY_GAMES_DIR: &str = "daily_games";
+pub const SECONDS_PER_DAY: u64 = 86400;
+
+// correspondence game info, the deadline is wall clock time so it keeps counting while the program is closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Correspondence {
+  pub id: String,
+  pub white: String,
+  pub black: String,
+  pub days_per_move: u64,
+  pub deadline: SystemTime,
+  // where the game lives on disk, set when created or loaded
+  #[serde(skip)]
+  pub file: PathBuf,
+}
+
+impl Correspondence {
+  pub fn per_move(&self) -> Duration {
+    Duration::from_secs(self.days_per_move * SECONDS_PER_DAY)
+  }
+
+  pub fn time_left(&self) -> Duration {
+    self.deadline.duration_since(SystemTime::now()).unwrap_or_default()
+  }
+
+  // name of the player who has to move for the given player_turn
+  pub fn player_to_move(&self, player_turn: u8) -> &str {
+    if player_turn == 1 {
+      &self.white
+    } else {
+      &self.black
+    }
+  }
+
+  pub fn opponent_of(&self, player: &str) -> &str {
+    if self.white == player {
+      &self.black
+    } else {
+      &self.white
+    }
+  }
+}
+
+impl TimeControl {
+  // the clock shows time left until the deadline, refilled to a full allowance every move
+  pub fn daily(days_per_move: u64) -> Self {
+    TimeControl::new(Duration::from_secs(days_per_move * SECONDS_PER_DAY), TimingMethod::SuddenDeath)
+  }
+}
+
+// the id names the game's file, so names typed in cant reach outside the games directory
+fn file_safe(name: &str) -> String {
+  name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+pub fn new_daily_game(directory: &Path, white: &str, black: &str, days_per_move: u64) -> Result<GameState, SaveError> {
+  let created = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+  let base = format!("{}-{}-{}", file_safe(white), file_safe(black), created);
+  // another game between the same players started in the same second would share the file
+  let mut id = base.clone();
+  let mut copy = 1;
+  while directory.join(format!("{}.json", id)).exists() {
+    copy += 1;
+    id = format!("{}-{}", base, copy);
+  }
+
+  let mut state = GameState::new();
+  state.mode = GameMode::Daily;
+  state.set_time_control(TimeControl::daily(days_per_move));
+  state.correspondence = Some(Correspondence {
+    file: directory.join(format!("{}.json", id)),
+    id,
+    white: white.to_owned(),
+    black: black.to_owned(),
+    days_per_move,
+    deadline: SystemTime::now() + Duration::from_secs(days_per_move * SECONDS_PER_DAY),
+  });
+  get_legal_move_list(&mut state);
+
+  save_daily_game(&state)?;
+  Ok(state)
+}
+
+pub fn save_daily_game(state: &GameState) -> Result<(), SaveError> {
+  let Some(correspondence) = &state.correspondence else {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a daily game").into());
+  };
+  save_game(&correspondence.file, state)
+}
+
+pub fn load_daily_game(file: &Path) -> Result<GameState, SaveError> {
+  let mut state = load_game(file)?;
+
+  match &mut state.correspondence {
+    Some(correspondence) => correspondence.file = file.to_path_buf(),
+    None => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a daily game").into()),
+  }
+  check_daily_deadline(&mut state);
+  Ok(state)
+}
+
+// brings the clock of the player to move up to date with the wall clock, ends the game if the deadline passed
+pub fn check_daily_deadline(state: &mut GameState) {
+  let Some(correspondence) = &state.correspondence else {
+    return;
+  };
+  let time_left = correspondence.time_left();
+
+  match state.player_turn {
+    1 => state.white_timer = time_left,
+    2 => state.black_timer = time_left,
+    _ => panic!("Invalid player turn"),
+  }
+
+  if time_left.is_zero() && !state.game_over {
+    println!("{} missed the move deadline", correspondence.player_to_move(state.player_turn));
+    state.game_over = true;
+    state.result = Some(GameEnd::TimeOut(state.player_turn == 1));
+  }
+}
+
+// games in the directory waiting on a move from player, most urgent first
+pub fn pending_games(directory: &Path, player: &str) -> io::Result<Vec<GameState>> {
+  let mut games = Vec::new();
+  if !directory.exists() {
+    return Ok(games);
+  }
+
+  for entry in fs::read_dir(directory)? {
+    let path = entry?.path();
+    if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+      continue;
+    }
+    // one broken file shouldnt hide the rest
+    let Ok(state) = load_daily_game(&path) else {
+      continue;
+    };
+    let Some(correspondence) = &state.correspondence else {
+      continue;
+    };
+    if !state.game_over && correspondence.player_to_move(state.player_turn) == player {
+      games.push(state);
+    }
+  }
+
+  games.sort_by_key(|state| state.correspondence.as_ref().map(|correspondence| correspondence.deadline));
+  Ok(games)
+}
+
+fn read_line() -> String {
+  let mut input = String::new();
+  std::io::stdin()
+    .read_line(&mut input)
+    .expect("Error reading input");
+  input.trim().to_owned()
+}
+
+// picks or starts a correspondence game from the command line
+pub fn select_daily_game() -> GameState {
+  let directory = Path::new(DAILY_GAMES_DIR);
+  println!("Enter your name");
+  let player = read_line();
+
+  loop {
+    let games = pending_games(directory, &player).unwrap_or_else(|e| {
+      println!("Error reading daily games: {}", e);
+      Vec::new()
+    });
+
+    if games.is_empty() {
+      println!("No games waiting on your move");
+    }
+    for (number, state) in games.iter().enumerate() {
+      if let Some(correspondence) = &state.correspondence {
+        let time_left = correspondence.time_left().as_secs();
+        println!(
+          "{}: vs {} move {}, {}h {}m left",
+          number,
+          correspondence.opponent_of(&player),
+          state.turn_counter / 2 + 1,
+          time_left / 3600,
+          time_left % 3600 / 60,
+        );
+      }
+    }
+    println!("Pick a game number or start one with: new <opponent> <white|black> [days per move]");
+
+    let input = read_line();
+    if let Ok(number) = input.parse::<usize>() {
+      if let Some(state) = games.into_iter().nth(number) {
+        return state;
+      }
+      println!("No game number {}", number);
+      continue;
+    }
+
+    let words = input.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+      ["new", opponent, colour, rest @ ..] => {
+        let days_per_move = rest.first().and_then(|days| days.parse::<u64>().ok()).unwrap_or(3);
+        let (white, black) = match *colour {
+          "white" => (player.as_str(), *opponent),
+          "black" => (*opponent, player.as_str()),
+          _ => {
+            println!("Colour must be white or black");
+            continue;
+          },
+        };
+        match new_daily_game(directory, white, black, days_per_move) {
+          Ok(state) => return state,
+          Err(e) => println!("Error creating daily game: {}", e),
+        }
+      },
+      _ => println!("Invalid input: {}", input),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Coordinates, Handler, Event};
+
+  #[test]
+  fn survives_restart() {
+    let directory = std::env::temp_dir().join(format!("cheess_daily_{}", std::process::id()));
+    let mut state = new_daily_game(&directory, "alice", "bob", 2).unwrap();
+    assert_eq!(pending_games(&directory, "alice").unwrap().len(), 1);
+    assert!(pending_games(&directory, "bob").unwrap().is_empty());
+
+    let e2e4 = (Coordinates { x: 4, y: 1 }, Coordinates { x: 4, y: 3 });
+    state.handle_mut(Event::Move { translation: e2e4, promotion: None });
+
+    let pending = pending_games(&directory, "bob").unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].last_move, Some(e2e4));
+    assert!(pending[0].black_timer > Duration::from_secs(SECONDS_PER_DAY));
+
+    // deadline passed while nobody was looking
+    let mut expired = pending[0].clone();
+    if let Some(correspondence) = &mut expired.correspondence {
+      correspondence.deadline = SystemTime::now() - Duration::from_secs(1);
+    }
+    check_daily_deadline(&mut expired);
+    assert!(expired.game_over);
+    assert_eq!(expired.result, Some(GameEnd::TimeOut(false)));
+
+    let sneaky = new_daily_game(&directory, "../../alice", "bob", 2).unwrap();
+    assert_eq!(sneaky.correspondence.unwrap().file.parent(), Some(directory.as_path()));
+
+    let rematch = new_daily_game(&directory, "carol", "dave", 1).unwrap().correspondence.unwrap();
+    let again = new_daily_game(&directory, "carol", "dave", 1).unwrap().correspondence.unwrap();
+    assert_ne!(rematch.file, again.file);
+    assert_eq!(pending_games(&directory, "carol").unwrap().len(), 2);
+
+    fs::remove_dir_all(&directory).unwrap();
+  }
+}
@@ -0,0 +1,288 @@
+use std::time::{Duration, Instant};
+
+use crate::PieceColour::*;
+use crate::{get_valid_moves_for_piece, is_in_check, make_move, BoardRep, CancelToken, GameState, Move, MoveList, PieceColour};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+// more than any amount of material, mates found sooner score higher
+pub const MATE_SCORE: i32 = 100_000;
+
+pub const DEFAULT_HINT_DEPTH: u8 = 3;
+
+// deeper than any search will finish, endless searches are ended by the token
+pub const MAX_DEPTH: u8 = 64;
+
+pub fn piece_value(piece: u8) -> i32 {
+  match piece {
+    PAWN => 100,
+    KNIGHT => 320,
+    BISHOP => 330,
+    ROOK => 500,
+    QUEEN => 900,
+    _ => 0,
+  }
+}
+
+// a small bonus for being near the middle, the king is left alone
+fn centre_bonus(piece: u8, index: usize) -> i32 {
+  if piece == KING || piece == EMPTY {
+    return 0;
+  }
+  let (x, y) = ((index % 8) as i32, (index / 8) as i32);
+  let distance = (2 * x - 7).abs().max((2 * y - 7).abs());
+  let weight = if piece == PAWN { 2 } else { 4 };
+  (7 - distance) * weight
+}
+
+// centipawns from white's side, material plus a little for piece placement
+pub fn evaluate(board: &BoardRep) -> i32 {
+  board.0.iter().zip(&board.1).enumerate()
+    .map(|(index, (piece, colour))| {
+      let score = piece_value(*piece) + centre_bonus(*piece, index);
+      match colour {
+        White => score,
+        Black => -score,
+        Empty => 0,
+      }
+    })
+    .sum()
+}
+
+fn evaluate_for(board: &BoardRep, colour: PieceColour) -> i32 {
+  if colour == White { evaluate(board) } else { -evaluate(board) }
+}
+
+fn opponent(colour: PieceColour) -> PieceColour {
+  if colour == White { Black } else { White }
+}
+
+// the search always promotes to a queen, so anything playing its moves does too
+pub fn queen_promotion(board: &BoardRep, translation: Move) -> Option<u8> {
+  let pawn = board.0[usize::from(translation.0)] == PAWN;
+  (pawn && (translation.1.y == 0 || translation.1.y == 7)).then_some(QUEEN)
+}
+
+fn apply(board: &BoardRep, translation: Move) -> BoardRep {
+  let promotion = queen_promotion(board, translation);
+  let mut board = make_move(board, translation);
+  if let Some(piece) = promotion {
+    board.0[usize::from(translation.1)] = piece;
+  }
+  board
+}
+
+// captures of the biggest pieces first so alpha beta cuts off sooner
+fn order_moves(board: &BoardRep, moves: &mut MoveList) {
+  moves.sort_by_key(|translation| -piece_value(board.0[usize::from(translation.1)]));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+  pub depth: u8,
+  pub nodes: Option<u64>,
+  pub time: Option<Duration>,
+}
+
+impl SearchLimits {
+  pub fn depth(depth: u8) -> Self {
+    SearchLimits { depth, nodes: None, time: None }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+  pub best_move: Option<Move>,
+  // centipawns for the player to move
+  pub score: i32,
+  // the deepest iteration that finished
+  pub depth: u8,
+  pub nodes: u64,
+  pub pv: Vec<Move>,
+}
+
+// how many moves until mate, negative when the player to move is getting mated
+pub fn mate_in(score: i32) -> Option<i32> {
+  if score.abs() < MATE_SCORE - 100 {
+    return None;
+  }
+  let plies = MATE_SCORE - score.abs();
+  Some(if score > 0 { (plies + 1) / 2 } else { -(plies + 1) / 2 })
+}
+
+impl SearchResult {
+  pub fn mate_in(&self) -> Option<i32> {
+    mate_in(self.score)
+  }
+}
+
+// one root move and the line expected after it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variation {
+  // centipawns for the player to move at the root
+  pub score: i32,
+  pub pv: Vec<Move>,
+}
+
+struct Search<'a> {
+  nodes: u64,
+  limits: SearchLimits,
+  deadline: Option<Instant>,
+  token: &'a CancelToken,
+  stopped: bool,
+}
+
+impl Search<'_> {
+  fn out_of_time(&mut self) -> bool {
+    if !self.stopped {
+      let over_nodes = self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes);
+      let over_time = self.nodes.is_multiple_of(256) && self.deadline.is_some_and(|deadline| Instant::now() >= deadline);
+      self.stopped = over_nodes || over_time || self.token.is_cancelled();
+    }
+    self.stopped
+  }
+
+  // negamax on pseudo legal moves, a move that leaves the king en prise loses it on the next ply
+  fn alpha_beta(&mut self, board: &BoardRep, colour: PieceColour, depth: u8, ply: i32, (mut alpha, beta): (i32, i32), pv: &mut Vec<Move>) -> i32 {
+    self.nodes += 1;
+    let all_moves = get_valid_moves_for_piece(board);
+    let mut moves = if colour == White { all_moves.white } else { all_moves.black };
+    if moves.iter().any(|translation| board.0[usize::from(translation.1)] == KING) {
+      return MATE_SCORE - ply;
+    }
+    if depth == 0 || self.out_of_time() {
+      return evaluate_for(board, colour);
+    }
+
+    order_moves(board, &mut moves);
+    let mut best = -MATE_SCORE;
+    let mut line = Vec::new();
+    for translation in moves {
+      line.clear();
+      let score = -self.alpha_beta(&apply(board, translation), opponent(colour), depth - 1, ply + 1, (-beta, -alpha), &mut line);
+      if self.stopped {
+        return best;
+      }
+      if score > best {
+        best = score;
+        pv.clear();
+        pv.push(translation);
+        pv.extend_from_slice(&line);
+      }
+      alpha = alpha.max(score);
+      if alpha >= beta {
+        break;
+      }
+    }
+    // every move hangs the king, thats only mate if it's already in check
+    if best <= -(MATE_SCORE - ply - 1) && !is_in_check(board, colour) {
+      pv.clear();
+      return 0;
+    }
+    best
+  }
+}
+
+fn root_moves(state: &GameState) -> (PieceColour, MoveList) {
+  let colour = if state.player_turn == 1 { White } else { Black };
+  (colour, if colour == White { state.move_list.white.clone() } else { state.move_list.black.clone() })
+}
+
+// iterative deepening over the legal moves of the player to move
+// a stopped search keeps the last iteration that finished, or the first move if none did
+pub fn search(state: &GameState, limits: SearchLimits, token: &CancelToken) -> SearchResult {
+  let (colour, mut root_moves) = root_moves(state);
+  let mut result = SearchResult { best_move: root_moves.first().copied(), score: 0, depth: 0, nodes: 0, pv: Vec::new() };
+  if root_moves.is_empty() {
+    result.score = if is_in_check(&state.board, colour) { -MATE_SCORE } else { 0 };
+    return result;
+  }
+
+  order_moves(&state.board, &mut root_moves);
+  let mut search = Search { nodes: 0, limits, deadline: limits.time.map(|time| Instant::now() + time), token, stopped: false };
+  for depth in 1..=limits.depth.max(1) {
+    let mut best = (-MATE_SCORE - 1, Vec::new());
+    let mut alpha = -MATE_SCORE - 1;
+    let mut line = Vec::new();
+    for translation in &root_moves {
+      line.clear();
+      let score = -search.alpha_beta(&apply(&state.board, *translation), opponent(colour), depth - 1, 1, (-MATE_SCORE - 1, -alpha), &mut line);
+      if search.stopped {
+        break;
+      }
+      if score > best.0 {
+        best = (score, std::iter::once(*translation).chain(line.iter().copied()).collect());
+        alpha = score;
+      }
+    }
+    result.nodes = search.nodes;
+    if search.stopped {
+      break;
+    }
+    result.score = best.0;
+    result.best_move = best.1.first().copied();
+    result.pv = best.1;
+    result.depth = depth;
+    // the best move so far goes first next time
+    if let Some(position) = root_moves.iter().position(|translation| Some(*translation) == result.best_move) {
+      root_moves[..=position].rotate_right(1);
+    }
+  }
+  result
+}
+
+// every legal move with its line, best first, report hears about each depth that finishes along with the nodes so far
+// each root move gets the full window so the scores can be compared, which makes it slower than search
+// a stopped search keeps the last iteration that finished, if none did the moves come back unscored
+pub fn rank_variations(state: &GameState, limits: SearchLimits, token: &CancelToken, mut report: impl FnMut(u8, u64, &[Variation])) -> Vec<Variation> {
+  let (colour, mut root_moves) = root_moves(state);
+  order_moves(&state.board, &mut root_moves);
+  let mut ranked = root_moves.into_iter().map(|translation| Variation { score: 0, pv: vec![translation] }).collect::<Vec<_>>();
+  let mut search = Search { nodes: 0, limits, deadline: limits.time.map(|time| Instant::now() + time), token, stopped: false };
+  for depth in 1..=limits.depth.max(1) {
+    let mut scored = Vec::with_capacity(ranked.len());
+    for variation in &ranked {
+      let translation = variation.pv[0];
+      let mut line = Vec::new();
+      let score = -search.alpha_beta(&apply(&state.board, translation), opponent(colour), depth - 1, 1, (-MATE_SCORE - 1, MATE_SCORE + 1), &mut line);
+      if search.stopped {
+        return ranked;
+      }
+      line.insert(0, translation);
+      scored.push(Variation { score, pv: line });
+    }
+    // stable, so equal moves keep the capture first order
+    scored.sort_by_key(|variation| -variation.score);
+    ranked = scored;
+    report(depth, search.nodes, &ranked);
+  }
+  ranked
+}
+
+// every legal move with its score for the player to move, best first
+pub fn rank_moves(state: &GameState, limits: SearchLimits, token: &CancelToken) -> Vec<(Move, i32)> {
+  rank_variations(state, limits, token, |_, _, _| {}).into_iter()
+    .map(|variation| (variation.pv[0], variation.score))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::square;
+  use crate::from_fen;
+
+  #[test]
+  fn finds_mate_and_free_material() {
+    // Ra8 is mate in one
+    let state = from_fen("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1").unwrap();
+    let result = search(&state, SearchLimits::depth(2), &CancelToken::new());
+    assert_eq!(result.best_move, Some((square("a1"), square("a8"))));
+    assert_eq!(result.mate_in(), Some(1));
+
+    // the knight on d5 is hanging
+    let state = from_fen("4k3/8/8/3n4/8/8/3R4/4K3 w - - 0 1").unwrap();
+    let result = search(&state, SearchLimits::depth(3), &CancelToken::new());
+    assert_eq!(result.best_move, Some((square("d2"), square("d5"))));
+    assert!(result.score > 300);
+  }
+}
@@ -0,0 +1,174 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{get_legal_move_list, to_fen, write_message, Event, GameState, Handler, NetMessage, SaveError};
+
+// bump when the log lines change in a way old logs cant be read
+pub const EVENT_LOG_VERSION: u32 = 1;
+
+// ahead of every other handler so moves are logged before the game plays them
+pub const RECORDER_PRIORITY: i32 = 100;
+
+// one JSON object per line, the game as it was when recording started comes first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LogLine {
+  Start { version: u32, started: SystemTime, game: Box<GameState> },
+  // at is since recording started, thinking is how long the player to move had been on the clock
+  Event { at: Duration, thinking: Duration, event: Event },
+}
+
+// append only, every event the dispatcher sees goes in
+pub struct EventLog {
+  file: File,
+  game: Arc<Mutex<GameState>>,
+  started: Instant,
+}
+
+impl EventLog {
+  pub fn create(path: &Path, game: Arc<Mutex<GameState>>) -> io::Result<Self> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let snapshot = game.lock().unwrap().clone();
+    write_message(&mut file, &LogLine::Start { version: EVENT_LOG_VERSION, started: SystemTime::now(), game: Box::new(snapshot) })?;
+    file.flush()?;
+    Ok(EventLog { file, game, started: Instant::now() })
+  }
+}
+
+impl Handler for EventLog {
+  fn handle_mut(&mut self, event: Event) {
+    let thinking = self.game.lock().unwrap().clock.elapsed();
+    let line = LogLine::Event { at: self.started.elapsed(), thinking, event };
+    if let Err(e) = write_message(&mut self.file, &line).and_then(|_| self.file.flush()) {
+      println!("Error writing event log: {}", e);
+    }
+  }
+}
+
+pub struct Replay {
+  pub state: GameState,
+  pub events: usize,
+  // where the replayed game disagrees with what was recorded
+  pub mismatches: Vec<String>,
+}
+
+impl Replay {
+  // the move is played with the clock set back by the recorded thinking time
+  fn play(&mut self, event: Event, thinking: Duration) {
+    self.state.clock = Instant::now().checked_sub(thinking).unwrap_or_else(Instant::now);
+    self.state.handle_mut(event);
+  }
+
+  fn apply(&mut self, event: Event, thinking: Duration) {
+    match event {
+      Event::Move { .. } | Event::Drop(_) => self.play(event, thinking),
+      Event::RemoteInput(Some(NetMessage::Move { origin, destination, promotion, .. })) => {
+        if let Some(event) = Event::move_from_indices(origin, destination, promotion) {
+          self.play(event, thinking);
+        }
+      },
+      Event::RemoteInput(Some(NetMessage::Drop { piece, square, .. })) => {
+        if let Some(event) = Event::drop_from_indices(piece, square) {
+          self.play(event, thinking);
+        }
+      },
+      // the recorded clocks are exact, the replayed ones are off by however long handling took
+      Event::MoveMade { record, fen, clocks } => {
+        let ply = self.state.move_history.len();
+        if self.state.move_history.last() != Some(&record) {
+          self.mismatches.push(format!("Ply {}: recorded {:?}, replayed {:?}", ply, record, self.state.move_history.last()));
+        } else if to_fen(&self.state) != fen {
+          self.mismatches.push(format!("Ply {}: recorded {}, replayed {}", ply, fen, to_fen(&self.state)));
+        }
+        clocks.apply(&mut self.state);
+      },
+      // resignations, draws and disconnects all end up here, whoever handled them
+      Event::GameEnded(result) => {
+        if self.state.game_over && self.state.result != result {
+          self.mismatches.push(format!("Recorded result {:?}, replayed {:?}", result, self.state.result));
+        }
+        self.state.game_over = true;
+        self.state.result = result;
+      },
+      Event::Surrender(_) | Event::UserInput | Event::DrawOffer | Event::RemoteInput(_) => {},
+      Event::ClockTick { .. } | Event::Chat { .. } => {},
+    }
+  }
+}
+
+// daily games use the wall clock for their deadlines, so only the moves replay for those
+pub fn replay_log(path: &Path) -> Result<Replay, SaveError> {
+  let mut lines = BufReader::new(File::open(path)?).lines();
+  let first = lines.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Event log is empty"))??;
+  let LogLine::Start { version, game, .. } = serde_json::from_str(&first)? else {
+    return Err(io::Error::new(io::ErrorKind::InvalidData, "Event log doesnt start with a game").into());
+  };
+  if version > EVENT_LOG_VERSION {
+    return Err(SaveError::UnsupportedVersion(version));
+  }
+
+  let mut state = *game;
+  get_legal_move_list(&mut state);
+  let mut replay = Replay { state, events: 0, mismatches: Vec::new() };
+  for line in lines {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+    match serde_json::from_str(&line)? {
+      LogLine::Event { thinking, event, .. } => replay.apply(event, thinking),
+      // recording again into the same file starts over from that position
+      LogLine::Start { game, .. } => {
+        replay.state = *game;
+        get_legal_move_list(&mut replay.state);
+      },
+    }
+    replay.events += 1;
+  }
+  Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Clocks, Dispatcher, EventKind, GameEnd, ThreadPool};
+
+  #[test]
+  fn recorded_game_replays_with_its_clocks() {
+    let file = std::env::temp_dir().join(format!("cheess_events_{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let pool = ThreadPool::new(1).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+
+    let mut state = GameState::new();
+    state.blitz_mode();
+    get_legal_move_list(&mut state);
+    state.publisher = Some(dispatcher.sender());
+    let game = Arc::new(Mutex::new(state));
+    dispatcher.subscribe_all(RECORDER_PRIORITY, Arc::new(Mutex::new(EventLog::create(&file, game.clone()).unwrap())));
+    for kind in [EventKind::Move, EventKind::Surrender] {
+      dispatcher.register_handler(kind, game.clone());
+    }
+    dispatcher.start();
+
+    for (origin, destination) in [(12, 28), (52, 36), (6, 21)] {
+      std::thread::sleep(Duration::from_millis(20));
+      dispatcher.request_blocking(Event::move_from_indices(origin, destination, None).unwrap());
+    }
+    dispatcher.request_blocking(Event::Surrender(None));
+    game.lock().unwrap().publisher = None;
+    dispatcher.shutdown(crate::ShutdownMode::Drain, Duration::from_secs(5)).unwrap();
+
+    let replay = replay_log(&file).unwrap();
+    let played = game.lock().unwrap();
+    assert!(replay.mismatches.is_empty(), "{:?}", replay.mismatches);
+    assert_eq!(to_fen(&replay.state), to_fen(&played));
+    assert_eq!(Clocks::of(&replay.state), Clocks::of(&played));
+    assert_eq!(replay.state.result, Some(GameEnd::Resignation(false)));
+    std::fs::remove_file(&file).unwrap();
+  }
+}
@@ -0,0 +1,373 @@
+use crate::PieceColour::*;
+use crate::{boardrep_to_bitboard, get_legal_move_list, CastlingFiles, Coordinates, GameState, PieceColour, PieceSet, Variant};
+use crate::DROPPABLE_PIECES;
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FenError(pub String);
+
+impl std::fmt::Display for FenError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Invalid FEN: {}", self.0)
+  }
+}
+
+pub fn piece_to_char(piece: u8, colour: PieceColour) -> char {
+  let letter = match piece {
+    PAWN => 'p',
+    ROOK => 'r',
+    KNIGHT => 'n',
+    BISHOP => 'b',
+    QUEEN => 'q',
+    KING => 'k',
+    _ => return '.',
+  };
+  if colour == White {
+    letter.to_ascii_uppercase()
+  } else {
+    letter
+  }
+}
+
+pub fn char_to_piece(letter: char) -> Option<(u8, PieceColour)> {
+  let piece = match letter.to_ascii_lowercase() {
+    'p' => PAWN,
+    'r' => ROOK,
+    'n' => KNIGHT,
+    'b' => BISHOP,
+    'q' => QUEEN,
+    'k' => KING,
+    _ => return None,
+  };
+  let colour = if letter.is_ascii_uppercase() { White } else { Black };
+  Some((piece, colour))
+}
+
+fn file_letter(file: usize, colour: PieceColour) -> char {
+  let letter = (b'a' + file as u8) as char;
+  if colour == White {
+    letter.to_ascii_uppercase()
+  } else {
+    letter
+  }
+}
+
+// X-FEN by default, KQkq unless another rook sits further out on the same side
+// shredder FEN always names the rook file, HAha for the normal setup
+fn castling_field(state: &GameState, shredder: bool) -> String {
+  let files = state.castling_files;
+  let rights = [
+    (White, true, state.white_can_castle_kingside),
+    (White, false, state.white_can_castle_queenside),
+    (Black, true, state.black_can_castle_kingside),
+    (Black, false, state.black_can_castle_queenside),
+  ];
+
+  let mut field = String::new();
+  for (colour, kingside, has_right) in rights {
+    if !has_right {
+      continue;
+    }
+    let rank = if colour == White { 0 } else { 7 };
+    let rook_file = if kingside { files.kingside_rook } else { files.queenside_rook };
+    let outer_files = if kingside { rook_file + 1..8 } else { 0..rook_file };
+    let is_outermost = !outer_files
+      .into_iter()
+      .any(|file| state.board.0[rank * 8 + file] == ROOK && state.board.1[rank * 8 + file] == colour);
+
+    let letter = if shredder || !is_outermost {
+      file_letter(rook_file, colour)
+    } else {
+      match (colour, kingside) {
+        (White, true) => 'K',
+        (White, false) => 'Q',
+        (_, true) => 'k',
+        (_, false) => 'q',
+      }
+    };
+    field.push(letter);
+  }
+
+  if field.is_empty() {
+    field.push('-');
+  }
+  field
+}
+
+fn placement_field(state: &GameState) -> String {
+  let crazyhouse = state.variant == Variant::Crazyhouse;
+  let mut ranks = Vec::new();
+  for y in (0..8).rev() {
+    let mut rank = String::new();
+    let mut empty_squares = 0;
+    for x in 0..8 {
+      let index = y * 8 + x;
+      if state.board.1[index] == Empty {
+        empty_squares += 1;
+        continue;
+      }
+      if empty_squares > 0 {
+        rank.push_str(&empty_squares.to_string());
+        empty_squares = 0;
+      }
+      rank.push(piece_to_char(state.board.0[index], state.board.1[index]));
+      if crazyhouse && state.is_promoted(index) {
+        rank.push('~');
+      }
+    }
+    if empty_squares > 0 {
+      rank.push_str(&empty_squares.to_string());
+    }
+    ranks.push(rank);
+  }
+  let mut placement = ranks.join("/");
+  if crazyhouse {
+    placement.push_str(&format!("[{}]", pocket_field(state)));
+  }
+  placement
+}
+
+// crazyhouse pocket, white pieces first
+fn pocket_field(state: &GameState) -> String {
+  let mut pocket = String::new();
+  for colour in [White, Black] {
+    for piece in DROPPABLE_PIECES {
+      for _ in 0..state.pocket(colour).count(piece) {
+        pocket.push(piece_to_char(piece, colour));
+      }
+    }
+  }
+  pocket
+}
+
+// square behind a pawn that just moved two squares
+fn en_passant_field(state: &GameState) -> String {
+  match state.last_move {
+    Some((origin, destination))
+      if state.board.0[usize::from(destination)] == PAWN
+      && origin.x == destination.x
+      && origin.y.abs_diff(destination.y) == 2 =>
+    {
+      Coordinates { x: origin.x, y: (origin.y + destination.y) / 2 }.to_algebraic()
+    },
+    _ => "-".to_owned(),
+  }
+}
+
+fn write_fen(state: &GameState, shredder: bool) -> String {
+  format!(
+    "{} {} {} {} {} {}",
+    placement_field(state),
+    if state.player_turn == 1 { 'w' } else { 'b' },
+    castling_field(state, shredder),
+    en_passant_field(state),
+    state.last_capture_or_pawn_move,
+    state.turn_counter / 2 + 1,
+  )
+}
+
+pub fn to_fen(state: &GameState) -> String {
+  write_fen(state, false)
+}
+
+pub fn to_shredder_fen(state: &GameState) -> String {
+  write_fen(state, true)
+}
+
+// reads standard FEN, X-FEN and Shredder-FEN, plus crazyhouse pockets, chess960 is switched on when the castling rooks or king arent on their usual files
+pub fn from_fen(fen: &str) -> Result<GameState, FenError> {
+  let error = |reason: &str| FenError(format!("{} ({})", reason, fen));
+  let fields = fen.split_whitespace().collect::<Vec<_>>();
+  if fields.len() < 4 {
+    return Err(error("expected at least 4 fields"));
+  }
+
+  let mut state = GameState::new();
+  let mut piece_board = vec![EMPTY; 64];
+  let mut colour_board = vec![Empty; 64];
+
+  // crazyhouse pockets come either in brackets after the board or as a 9th rank
+  let (placement, pocket) = match fields[0].split_once('[') {
+    Some((placement, pocket)) => (placement, Some(pocket.strip_suffix(']').ok_or_else(|| error("unclosed pocket"))?)),
+    None => (fields[0], None),
+  };
+  let mut ranks = placement.split('/').collect::<Vec<_>>();
+  let pocket = match (ranks.len(), pocket) {
+    (8, pocket) => pocket,
+    (9, None) => ranks.pop(),
+    _ => return Err(error("expected 8 ranks")),
+  };
+  for (rank_number, rank) in ranks.iter().enumerate() {
+    let y = 7 - rank_number;
+    let mut x = 0;
+    for letter in rank.chars() {
+      if letter == '~' && x > 0 {
+        state.promoted_squares |= 1 << (y * 8 + x - 1);
+        continue;
+      }
+      if let Some(skip) = letter.to_digit(10) {
+        x += skip as usize;
+        continue;
+      }
+      let (piece, colour) = char_to_piece(letter).ok_or_else(|| error("unknown piece"))?;
+      if x > 7 {
+        return Err(error("rank too long"));
+      }
+      piece_board[y * 8 + x] = piece;
+      colour_board[y * 8 + x] = colour;
+      x += 1;
+    }
+    if x != 8 {
+      return Err(error("rank has the wrong number of squares"));
+    }
+  }
+
+  for colour in [White, Black] {
+    let kings = (0..64).filter(|index| piece_board[*index] == KING && colour_board[*index] == colour).count();
+    if kings > 1 {
+      return Err(error("each side can only have one king"));
+    }
+  }
+  // horde leaves white without a king, but someone has to have one
+  if !piece_board.contains(&KING) {
+    return Err(error("no kings on the board"));
+  }
+
+  state.player_turn = match fields[1] {
+    "w" => 1,
+    "b" => 2,
+    _ => return Err(error("side to move must be w or b")),
+  };
+
+  state.white_can_castle_kingside = false;
+  state.white_can_castle_queenside = false;
+  state.black_can_castle_kingside = false;
+  state.black_can_castle_queenside = false;
+  let mut files = CastlingFiles::default();
+
+  if fields[2] != "-" {
+    for letter in fields[2].chars() {
+      let colour = if letter.is_ascii_uppercase() { White } else { Black };
+      let rank = if colour == White { 0 } else { 7 };
+      let rook_files = (0..8)
+        .filter(|file| piece_board[rank * 8 + file] == ROOK && colour_board[rank * 8 + file] == colour)
+        .collect::<Vec<_>>();
+      let king_file = (0..8)
+        .find(|file| piece_board[rank * 8 + file] == KING && colour_board[rank * 8 + file] == colour)
+        .ok_or_else(|| error("castling right without a king on the back rank"))?;
+
+      let rook_file = match letter.to_ascii_lowercase() {
+        'k' => rook_files.iter().rev().find(|file| **file > king_file).copied(),
+        'q' => rook_files.iter().find(|file| **file < king_file).copied(),
+        file @ 'a'..='h' => rook_files.iter().find(|rook| **rook == file as usize - 'a' as usize).copied(),
+        _ => None,
+      }
+      .ok_or_else(|| error("castling right without a matching rook"))?;
+
+      files.king = king_file;
+      if rook_file > king_file {
+        files.kingside_rook = rook_file;
+        match colour {
+          White => state.white_can_castle_kingside = true,
+          _ => state.black_can_castle_kingside = true,
+        }
+      } else {
+        files.queenside_rook = rook_file;
+        match colour {
+          White => state.white_can_castle_queenside = true,
+          _ => state.black_can_castle_queenside = true,
+        }
+      }
+    }
+  }
+  state.chess960 = files != CastlingFiles::default();
+  state.castling_files = files;
+
+  // only the pawn move that allowed it is remembered, en passant works off last_move
+  if fields[3] != "-" {
+    let target = Coordinates::from_algebraic(fields[3]).ok_or_else(|| error("bad en passant square"))?;
+    let (origin_y, destination_y) = match target.y {
+      2 => (1, 3),
+      5 => (6, 4),
+      _ => return Err(error("en passant square must be on rank 3 or 6")),
+    };
+    state.last_move = Some((Coordinates { x: target.x, y: origin_y }, Coordinates { x: target.x, y: destination_y }));
+  }
+
+  state.last_capture_or_pawn_move = match fields.get(4) {
+    Some(halfmoves) => halfmoves.parse().map_err(|_| error("bad halfmove clock"))?,
+    None => 0,
+  };
+  let fullmoves: u16 = match fields.get(5) {
+    Some(fullmoves) => fullmoves.parse().map_err(|_| error("bad fullmove number"))?,
+    None => 1,
+  };
+  state.turn_counter = fullmoves.saturating_sub(1) * 2 + if state.player_turn == 2 { 1 } else { 0 };
+
+  if let Some(pocket) = pocket {
+    state.variant = Variant::Crazyhouse;
+    for letter in pocket.chars() {
+      match char_to_piece(letter) {
+        Some((piece, colour)) if DROPPABLE_PIECES.contains(&piece) => state.pocket_mut(colour).add_piece(piece),
+        _ => return Err(error("pockets can only hold pawns, knights, bishops, rooks and queens")),
+      }
+    }
+  }
+
+  state.board = (piece_board, colour_board);
+  state.white_pieces = PieceSet::from_board(&state.board, White);
+  state.black_pieces = PieceSet::from_board(&state.board, Black);
+  state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board)];
+  get_legal_move_list(&mut state);
+  Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{is_legal, position, translation};
+
+  #[test]
+  fn round_trips() {
+    assert_eq!(to_fen(&GameState::new()), STARTING_FEN);
+    assert_eq!(to_shredder_fen(&from_fen(STARTING_FEN).unwrap()), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1");
+
+    let after_e4 = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+    assert_eq!(to_fen(&from_fen(after_e4).unwrap()), after_e4);
+
+    // chess960 with an extra rook outside the castling rook, X-FEN has to name the file
+    let xfen = "rk1r4/8/8/8/8/8/8/RK1R3R w DAd - 0 1";
+    let state = from_fen(xfen).unwrap();
+    assert!(state.chess960);
+    assert_eq!(state.castling_files, CastlingFiles { king: 1, kingside_rook: 3, queenside_rook: 0 });
+    assert_eq!(to_fen(&state), "rk1r4/8/8/8/8/8/8/RK1R3R w DQk - 0 1");
+    assert_eq!(to_shredder_fen(&state), xfen);
+
+    assert!(from_fen("8/8/8/8/8/8/8/8 w - - 0 1").is_err());
+  }
+
+  #[test]
+  fn en_passant_square_allows_the_capture() {
+    let mut state = position("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 2");
+    assert!(is_legal(&state, "e5", "d6"));
+
+    crate::take_turn(&mut state, translation("e5", "d6"));
+    assert_eq!(to_fen(&state), "4k3/8/3P4/8/8/8/8/4K3 b - - 0 2");
+    assert_eq!(state.san_history, vec!["exd6"]);
+  }
+
+  #[test]
+  fn en_passant_cant_leave_the_king_in_check() {
+    // both pawns leave the fifth rank, opening it for the rook
+    let pinned = position("7k/8/8/KPp4r/8/8/8/8 w - c6 0 1");
+    assert!(!is_legal(&pinned, "b5", "c6"));
+    assert!(is_legal(&pinned, "b5", "b6"));
+
+    // the bishop on b4 still gives check afterwards
+    let checked = position("4k3/8/8/3pP3/1b6/8/8/4K3 w - d6 0 1");
+    assert!(!is_legal(&checked, "e5", "d6"));
+    assert!(is_legal(&checked, "e1", "f2"));
+  }
+}
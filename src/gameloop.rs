@@ -1,71 +1,295 @@
 #![allow(dead_code)]
 
-use std::{collections::HashMap, sync::{mpsc::{channel, Receiver, Sender}, Arc, Mutex}, thread};
-use crate::threadpool::ThreadPool;
+use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{channel, Receiver, Sender}, Arc, Condvar, Mutex}, thread};
+use std::time::{Duration, Instant};
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+use futures::executor::block_on;
+use futures::Future;
+use crate::threadpool::{ShutdownTimeout, ThreadPool};
+use crate::{Clocks, Coordinates, DropMove, GameEnd, Move, MoveRecord, NetMessage};
 
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Event {
-  MoveInput,
+// what an event is about, handlers are registered per kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventKind {
+  Move,
+  Drop,
   Surrender,
   UserInput,
+  DrawOffer,
+  RemoteInput,
+  MoveMade,
+  ClockTick,
+  GameEnded,
+  Chat,
 }
 
-pub type Payload = Vec<u8>;
+// serializable so the event log can write it out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+  // promotion is the piece to promote to, for moves that didnt come from this terminal
+  Move { translation: Move, promotion: Option<u8> },
+  Drop(DropMove),
+  // the player_turn number of who gave up, the player to move if None
+  Surrender(Option<u8>),
+  UserInput,
+  DrawOffer,
+  // a message from the other player over the network, None when they disconnect
+  RemoteInput(Option<NetMessage>),
+  // published for spectators
+  MoveMade { record: MoveRecord, fen: String, clocks: Clocks },
+  ClockTick { clocks: Clocks, white_to_move: bool },
+  GameEnded(Option<GameEnd>),
+  Chat { from: String, text: String },
+}
+
+impl Event {
+  pub fn kind(&self) -> EventKind {
+    match self {
+      Event::Move { .. } => EventKind::Move,
+      Event::Drop(_) => EventKind::Drop,
+      Event::Surrender(_) => EventKind::Surrender,
+      Event::UserInput => EventKind::UserInput,
+      Event::DrawOffer => EventKind::DrawOffer,
+      Event::RemoteInput(_) => EventKind::RemoteInput,
+      Event::MoveMade { .. } => EventKind::MoveMade,
+      Event::ClockTick { .. } => EventKind::ClockTick,
+      Event::GameEnded(_) => EventKind::GameEnded,
+      Event::Chat { .. } => EventKind::Chat,
+    }
+  }
+
+  // board indices from the network or a client, None if either is off the board
+  pub fn move_from_indices(origin: u8, destination: u8, promotion: Option<u8>) -> Option<Self> {
+    if origin >= 64 || destination >= 64 {
+      return None;
+    }
+    let translation = (Coordinates::from(origin as usize), Coordinates::from(destination as usize));
+    Some(Event::Move { translation, promotion })
+  }
+
+  pub fn drop_from_indices(piece: u8, square: u8) -> Option<Self> {
+    if square >= 64 {
+      return None;
+    }
+    Some(Event::Drop(DropMove { piece, square: Coordinates::from(square as usize) }))
+  }
+}
+
+// what a handler answers to a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reply {
+  Line(String),
+  // the event wasnt acted on, eg. an illegal move
+  Rejected(String),
+}
+
+// an event and, for requests, where to send the reply once every handler is done
+// Stop ends the one loop that receives it
+enum Envelope {
+  Event { event: Event, reply: Option<oneshot::Sender<Option<Reply>>> },
+  Stop,
+}
+
+// what happens to events still queued when the dispatcher shuts down
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+  Drain,
+  Discard,
+}
+
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+// how many loops are running, waited on by shutdown
+#[derive(Default)]
+struct LoopCount {
+  running: Mutex<usize>,
+  stopped: Condvar,
+}
+
+// counts the loop as stopped however it ends, panics included
+struct LoopGuard(Arc<LoopCount>);
+
+impl Drop for LoopGuard {
+  fn drop(&mut self) {
+    *self.0.running.lock().unwrap() -= 1;
+    self.0.stopped.notify_all();
+  }
+}
+
+// for threads that need to trigger events without borrowing the dispatcher
+#[derive(Debug, Clone)]
+pub struct EventSender(Sender<Envelope>);
+
+impl EventSender {
+  pub fn send(&self, event: Event) -> Result<(), Event> {
+    self.0.send(Envelope::Event { event, reply: None }).map_err(|e| match e.0 {
+      Envelope::Event { event, .. } => event,
+      Envelope::Stop => unreachable!(),
+    })
+  }
+}
 
 pub trait Handler: Send + Sync {
-  fn handle_mut(&mut self, event: Event, payload: Payload);
-  fn handle(&self, event: Event, payload: Payload) {}
+  fn handle_mut(&mut self, event: Event);
+  fn handle(&self, event: Event) {}
+  // handlers with an answer override this, the rest just handle the event
+  fn respond(&mut self, event: Event) -> Option<Reply> {
+    self.handle_mut(event);
+    None
+  }
 }
 
+pub type HandlerId = u64;
+
+// None listens to every event, higher priorities are handled first
 #[derive(Clone)]
 struct Listener {
-  event: Event,
+  id: HandlerId,
+  event: Option<EventKind>,
+  priority: i32,
   handler: Arc<Mutex<dyn Handler>>,
 }
 
 pub struct Dispatcher<'a> {
-  tx: Sender<(Event, Payload)>,
-  rx: Arc<Mutex<Receiver<(Event, Payload)>>>,
-  handler_registry: Arc<Mutex<HashMap<Event, Vec<Arc<Mutex<dyn Handler>>>>>>,
+  tx: Sender<Envelope>,
+  rx: Arc<Mutex<Receiver<Envelope>>>,
+  handler_registry: Arc<Mutex<Vec<Listener>>>,
+  next_handler_id: HandlerId,
+  loops: Arc<LoopCount>,
+  discarding: Arc<AtomicBool>,
   threadpool: Option<&'a ThreadPool>,
 } 
 
 impl<'a> Dispatcher<'a> {
   pub fn new(threadpool: &'a ThreadPool) -> Self {
     let (tx, rx) = channel();
-    Dispatcher {tx, rx: Arc::new(Mutex::new(rx)), handler_registry: Arc::new(Mutex::new(HashMap::new())), threadpool: Some(threadpool)}
+    Dispatcher {tx, rx: Arc::new(Mutex::new(rx)), handler_registry: Arc::new(Mutex::new(Vec::new())), next_handler_id: 0,
+      loops: Arc::new(LoopCount::default()), discarding: Arc::new(AtomicBool::new(false)), threadpool: Some(threadpool)}
+  }
+
+  pub fn register_handler(&mut self, kind: EventKind, handler: Arc<Mutex<dyn Handler>>) -> HandlerId {
+    self.add_listener(Some(kind), 0, handler)
+  }
+
+  pub fn register_handler_with_priority(&mut self, kind: EventKind, priority: i32, handler: Arc<Mutex<dyn Handler>>) -> HandlerId {
+    self.add_listener(Some(kind), priority, handler)
+  }
+
+  // for loggers and recorders that want to see everything
+  pub fn subscribe_all(&mut self, priority: i32, handler: Arc<Mutex<dyn Handler>>) -> HandlerId {
+    self.add_listener(None, priority, handler)
+  }
+
+  // false if the id was already removed
+  pub fn unregister_handler(&mut self, id: HandlerId) -> bool {
+    let mut registry = self.handler_registry.lock().unwrap();
+    let registered = registry.len();
+    registry.retain(|listener| listener.id != id);
+    registry.len() != registered
   }
 
-  pub fn register_handler(&mut self, event: Event, handler: Arc<Mutex<dyn Handler>>) {
+  fn add_listener(&mut self, event: Option<EventKind>, priority: i32, handler: Arc<Mutex<dyn Handler>>) -> HandlerId {
+    self.next_handler_id += 1;
+    let id = self.next_handler_id;
     let mut registry = self.handler_registry.lock().unwrap();
-    registry.entry(event).or_insert_with(Vec::new).push(handler);
+    // after every listener with the same or a higher priority, so equal ones keep the order they came in
+    let position = registry.partition_point(|listener| listener.priority >= priority);
+    registry.insert(position, Listener { id, event, priority, handler });
+    id
+  }
+
+  pub fn trigger_event(&self, event: Event) {
+    self.tx.send(Envelope::Event { event, reply: None }).unwrap();
+  }
+
+  // resolves once every handler has run, to the first reply any of them gave
+  // None as well if no loop is running to handle it
+  pub fn request(&self, event: Event) -> impl Future<Output = Option<Reply>> {
+    let (reply, receiver) = oneshot::channel();
+    let sent = self.tx.send(Envelope::Event { event, reply: Some(reply) });
+    async move {
+      match sent {
+        Ok(()) => receiver.await.ok().flatten(),
+        Err(_) => None,
+      }
+    }
   }
 
-  pub fn trigger_event(&self, event: Event, payload: Payload) {
-    self.tx.send((event, payload)).unwrap();
+  pub fn request_blocking(&self, event: Event) -> Option<Reply> {
+    block_on(self.request(event))
+  }
+
+  pub fn sender(&self) -> EventSender {
+    EventSender(self.tx.clone())
+  }
+
+  // stops every loop, other EventSenders keeping the channel open dont matter
+  // a loop stuck in a handler (like one waiting on stdin) is left running once the timeout is up
+  pub fn shutdown(&self, mode: ShutdownMode, timeout: Duration) -> Result<(), ShutdownTimeout> {
+    self.discarding.store(mode == ShutdownMode::Discard, Ordering::SeqCst);
+    let running = *self.loops.running.lock().unwrap();
+    // queued behind anything already sent, so draining loops get to it last
+    for _ in 0..running {
+      let _ = self.tx.send(Envelope::Stop);
+    }
+
+    let deadline = Instant::now() + timeout;
+    let mut running = self.loops.running.lock().unwrap();
+    while *running > 0 {
+      let left = deadline.saturating_duration_since(Instant::now());
+      if left.is_zero() {
+        return Err(ShutdownTimeout { still_running: *running });
+      }
+      running = self.loops.stopped.wait_timeout(running, left).unwrap().0;
+    }
+    self.discarding.store(false, Ordering::SeqCst);
+    Ok(())
   }
 
   pub fn start(&self) {
     if let Some(threadpool) = self.threadpool {
       let rx = self.rx.clone();
       let handler_registry = self.handler_registry.clone();
+      let discarding = self.discarding.clone();
+      *self.loops.running.lock().unwrap() += 1;
+      let guard = LoopGuard(self.loops.clone());
       
       //should check for new events 
-      let f = move || loop {
-        match rx.lock().unwrap().recv() {
-          Ok((event, payload)) => {
-            let handler_registry = handler_registry.lock().unwrap();
-            if let Some(handler_list) = handler_registry.get(&event) {
+      // locks are let go before handling so start can be called again for another loop
+      let f = move || {
+        let _guard = guard;
+        loop {
+          let message = rx.lock().unwrap().recv();
+          match message {
+            Ok(Envelope::Event { reply, .. }) if discarding.load(Ordering::SeqCst) => {
+              if let Some(reply) = reply {
+                let _ = reply.send(None);
+              }
+            },
+            Ok(Envelope::Event { event, reply }) => {
+              let kind = event.kind();
+              let handler_list = handler_registry.lock().unwrap().iter()
+                .filter(|listener| listener.event.is_none_or(|event| event == kind))
+                .map(|listener| listener.handler.clone())
+                .collect::<Vec<_>>();
+              let mut answer = None;
               for handler in handler_list {
-                // handler.lock().unwrap().handle(event.clone(), payload.clone());
-                handler.lock().unwrap().handle_mut(event.clone(), payload.clone());
+                // handler.lock().unwrap().handle(event.clone());
+                let response = handler.lock().unwrap().respond(event.clone());
+                answer = answer.or(response);
               }
+              if let Some(reply) = reply {
+                // whoever asked might have stopped waiting
+                let _ = reply.send(answer);
+              }
+            },
+            // every sender is gone or shutdown asked this loop to stop
+            Ok(Envelope::Stop) | Err(_) => {
+              break;
             }
-      
-          },
-          Err(e) => {
-            break;
           }
         }
       };
@@ -76,8 +300,11 @@ impl<'a> Dispatcher<'a> {
 
 impl<'a> Drop for Dispatcher<'a> {
   fn drop(&mut self) {
+      if self.shutdown(ShutdownMode::Discard, DEFAULT_SHUTDOWN_TIMEOUT).is_err() {
+        println!("Event loop still busy, leaving it running");
+      }
       self.threadpool = None;
-      self.handler_registry.lock().unwrap().drain();
+      self.handler_registry.lock().unwrap().clear();
       
   }
 }
@@ -91,16 +318,15 @@ mod tests {
     pub struct TestHandler;
 
     impl Handler for TestHandler {
-      fn handle_mut(&mut self, event: Event, payload: Payload) {
-          let data = String::from_utf8(payload).unwrap();
-          println!("{:?} {:?}", event, data);
+      fn handle_mut(&mut self, event: Event) {
+          println!("{:?}", event);
       }
     }
     let pool = ThreadPool::new(1).unwrap();
 
     let mut event_loop = Dispatcher::new(&pool);
 
-    event_loop.register_handler(Event::MoveInput, Arc::new(Mutex::new(TestHandler)));
+    event_loop.register_handler(EventKind::Chat, Arc::new(Mutex::new(TestHandler)));
 
     event_loop.start();
 
@@ -109,19 +335,112 @@ mod tests {
 
       let mut input = String::new();
 
-      std::io::stdin()
+      let read = std::io::stdin()
         .read_line(&mut input)
         .expect("Input Error");
 
       let input = input.trim();
 
-      if input == "exit" {
+      // stdin is closed when the tests arent run from a terminal
+      if read == 0 || input == "exit" {
         break;
       }
-      
-      let event = Event::MoveInput;
 
-      event_loop.trigger_event(event, input.as_bytes().to_vec());
+      event_loop.trigger_event(Event::Chat { from: "stdin".to_owned(), text: input.to_owned() });
+    }
+  }
+
+  #[test]
+  fn request_waits_for_handlers() {
+    struct Echo(usize);
+
+    impl Handler for Echo {
+      fn handle_mut(&mut self, _event: Event) {
+        self.0 += 1;
+      }
+
+      fn respond(&mut self, event: Event) -> Option<Reply> {
+        self.handle_mut(event.clone());
+        match event {
+          Event::Chat { text, .. } => Some(Reply::Line(text)),
+          _ => None,
+        }
+      }
+    }
+
+    let pool = ThreadPool::new(1).unwrap();
+    let mut event_loop = Dispatcher::new(&pool);
+    let echo = Arc::new(Mutex::new(Echo(0)));
+    event_loop.register_handler(EventKind::Chat, echo.clone());
+    event_loop.register_handler(EventKind::DrawOffer, echo.clone());
+    event_loop.start();
+
+    let chat = Event::Chat { from: "white".to_owned(), text: "gg".to_owned() };
+    assert_eq!(event_loop.request_blocking(chat), Some(Reply::Line("gg".to_owned())));
+    assert_eq!(block_on(event_loop.request(Event::DrawOffer)), None);
+    // nobody handles resignations here, the request still finishes
+    assert_eq!(event_loop.request_blocking(Event::Surrender(None)), None);
+    assert_eq!(echo.lock().unwrap().0, 2);
+  }
+
+  #[test]
+  fn priorities_wildcards_and_unregistering() {
+    struct Recorder(&'static str, Arc<Mutex<Vec<&'static str>>>);
+
+    impl Handler for Recorder {
+      fn handle_mut(&mut self, _event: Event) {
+        self.1.lock().unwrap().push(self.0);
+      }
+    }
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let recorder = |name| Arc::new(Mutex::new(Recorder(name, seen.clone())));
+    let pool = ThreadPool::new(1).unwrap();
+    let mut event_loop = Dispatcher::new(&pool);
+    event_loop.register_handler(EventKind::DrawOffer, recorder("game"));
+    let overlay = event_loop.register_handler_with_priority(EventKind::DrawOffer, 10, recorder("overlay"));
+    event_loop.subscribe_all(-10, recorder("log"));
+    event_loop.register_handler(EventKind::Chat, recorder("chat"));
+    event_loop.start();
+
+    event_loop.request_blocking(Event::DrawOffer);
+    assert_eq!(*seen.lock().unwrap(), vec!["overlay", "game", "log"]);
+
+    seen.lock().unwrap().clear();
+    assert!(event_loop.unregister_handler(overlay));
+    assert!(!event_loop.unregister_handler(overlay));
+    event_loop.request_blocking(Event::DrawOffer);
+    event_loop.request_blocking(Event::UserInput);
+    assert_eq!(*seen.lock().unwrap(), vec!["game", "log", "log"]);
+  }
+
+  #[test]
+  fn shutdown_drains_or_discards() {
+    struct Counter(Arc<Mutex<usize>>);
+
+    impl Handler for Counter {
+      fn handle_mut(&mut self, _event: Event) {
+        std::thread::sleep(Duration::from_millis(10));
+        *self.0.lock().unwrap() += 1;
+      }
+    }
+
+    let pool = ThreadPool::new(2).unwrap();
+    for mode in [ShutdownMode::Drain, ShutdownMode::Discard] {
+      let count = Arc::new(Mutex::new(0));
+      let mut event_loop = Dispatcher::new(&pool);
+      event_loop.register_handler(EventKind::DrawOffer, Arc::new(Mutex::new(Counter(count.clone()))));
+      // a sender held elsewhere used to keep the loop alive forever
+      let _sender = event_loop.sender();
+      event_loop.start();
+      event_loop.request_blocking(Event::DrawOffer);
+      for _ in 0..4 {
+        event_loop.trigger_event(Event::DrawOffer);
+      }
+      assert_eq!(event_loop.shutdown(mode, Duration::from_secs(5)), Ok(()));
+      // the loop may already be handling the next one when discarding starts
+      let handled = *count.lock().unwrap();
+      assert!(if mode == ShutdownMode::Drain { handled == 5 } else { handled <= 2 });
     }
   }
 }
@@ -1,22 +1,34 @@
-use crate::gameloop::{Event, Handler, Payload};
+use crate::gameloop::{Event, Handler, Reply};
 
 
 pub struct UserInput {
   pub input: String,
 }
 
-impl Handler for UserInput {
-  fn handle_mut(&mut self, _event: Event, _payload: Payload) {
+impl UserInput {
+  // None once stdin is closed
+  fn read_line(&mut self) -> Option<String> {
     println!("Make move");
     
     let mut input = String::new();
-    std::io::stdin()
+    let read = std::io::stdin()
       .read_line(&mut input)
       .expect("Error reading input");
 
     self.input = input.to_lowercase().trim().to_owned();
+    (read > 0).then(|| self.input.clone())
+  }
+}
+
+impl Handler for UserInput {
+  fn handle_mut(&mut self, _event: Event) {
+    self.read_line();
+  }
+
+  fn respond(&mut self, _event: Event) -> Option<Reply> {
+    self.read_line().map(Reply::Line)
   }
 }
 
 
-// pub fn special_commands(input: &str) {}
\ No newline at end of file
+// pub fn special_commands(input: &str) {}
@@ -2,15 +2,64 @@ mod gameloop;
 mod input;
 mod threadpool;
 mod bitboard;
+mod timecontrol;
+mod daily;
+mod save;
+mod chess960;
+mod fen;
+mod variant;
+mod crazyhouse;
+mod atomic;
+mod antichess;
+mod network;
+mod server;
+mod websocket;
+mod broadcast;
+mod eventlog;
+mod engine;
+mod computer;
+mod notation;
+mod command;
+mod render;
+mod session;
+mod tui;
+mod uci;
+mod analysis;
+#[cfg(test)]
+mod testing;
 
 use std::time::Duration;
 use std::sync::{Arc, Mutex};
 use std::ops::{Index, IndexMut};
+use serde::{Deserialize, Serialize};
 
 pub use gameloop::*;
 pub use input::*;
 pub use threadpool::*;
 pub use bitboard::*;
+pub use timecontrol::*;
+pub use daily::*;
+pub use save::*;
+pub use chess960::*;
+pub use fen::*;
+pub use variant::*;
+pub use crazyhouse::*;
+pub use atomic::*;
+pub use antichess::*;
+pub use network::*;
+pub use server::*;
+pub use websocket::*;
+pub use broadcast::*;
+pub use eventlog::*;
+pub use engine::*;
+pub use computer::*;
+pub use notation::*;
+pub use command::*;
+pub use render::*;
+pub use session::*;
+pub use tui::*;
+pub use uci::*;
+pub use analysis::*;
 
 use crate::PieceColour::*;
 
@@ -22,18 +71,31 @@ pub const ROOK: u8 = 11;
 pub const QUEEN: u8 = 14;
 pub const KING: u8 = 15;
 
+// one thread for each job in run() that lasts the whole game: the dispatcher loop, a second
+// loop for network games so the opponent's moves land while stdin is read, the network reader
+// and the clock ticker for spectators
+const GAME_THREADS: usize = 4;
+
 
 pub fn run() {
     let mut game = GameState::new();
+    let mut hosting = None;
+    let mut network = None;
+    let mut computer = None;
+    // a 960 or fen start position is its own setup, the variant prompt would replace it
+    let mut custom_start = false;
 
     println!("Select Mode");
     
     
     let mut input = String::new();
     loop {
-        std::io::stdin()
+        let read = std::io::stdin()
           .read_line(&mut input)
           .expect("Error reading input");
+        if read == 0 {
+            stdin_closed();
+        }
         input = input.trim().to_owned();
 
          if input == "default" {
@@ -43,94 +105,288 @@ pub fn run() {
             println!("Blitz game mode selected");
             game.blitz_mode();
             break;
+        } else if input == "rapid" {
+            println!("Rapid game mode selected");
+            game.rapid_mode();
+            break;
+        } else if input == "classical" {
+            println!("Classical game mode selected");
+            game.classical_mode();
+            break;
+        } else if input == "engine" || input.starts_with("engine ") {
+            // practice against the computer, eg. engine black hard or engine white 1200, then the mode as usual
+            let mut options = input["engine".len()..].split_whitespace();
+            let colour = match options.next() {
+                Some(colour) => parse_player_colour(colour),
+                None => Ok(prompt("Play as white, black or random?", parse_player_colour)),
+            };
+            let difficulty = match options.next() {
+                Some(difficulty) => difficulty.parse::<Difficulty>(),
+                None => Ok(prompt(&format!("Difficulty: beginner, easy, medium, hard, maximum or an elo from {} to {}?", MIN_ELO, MAX_ELO), |input| input.parse::<Difficulty>())),
+            };
+            match (colour, difficulty) {
+                (Ok(colour), Ok(difficulty)) => {
+                    println!("Playing {:?} against the {:?} engine, select the mode to play", colour, difficulty);
+                    computer = Some(Computer::against(colour, difficulty));
+                    game.render.flipped = colour == Black;
+                },
+                (Err(e), _) | (_, Err(e)) => println!("{}", e),
+            }
+            input.clear();
+        } else if input == "host" || input.starts_with("host ") {
+            // the host still picks the mode and variant, then waits for someone to join
+            let port = input[4..].trim().parse::<u16>().unwrap_or(DEFAULT_PORT);
+            hosting = Some(port);
+            println!("Hosting on port {}, select the mode to play", port);
+            input.clear();
+        } else if let Some(address) = input.strip_prefix("join ") {
+            match join_game(address.trim()) {
+                Ok((connection, state)) => {
+                    println!("Joined game, you are playing black");
+                    network = Some((connection, Black));
+                    game = state;
+                    break;
+                },
+                Err(e) => {
+                    println!("Error joining game: {}", e);
+                    input.clear();
+                }
+            }
+        } else if input == "daily" {
+            println!("Daily game mode selected");
+            game = select_daily_game();
+            break;
+        } else if input == "960" || input.starts_with("960 ") {
+            // random position unless a number is given
+            let number = input[3..].trim().parse::<u16>().ok()
+                .filter(|number| *number < CHESS960_POSITIONS)
+                .unwrap_or_else(random_chess960_number);
+            println!("Chess960 position {} selected", number);
+            game = GameState::new_chess960(number);
+            custom_start = true;
+            break;
+        } else if let Some(fen) = input.strip_prefix("fen ") {
+            match from_fen(fen) {
+                Ok(state) => {
+                    println!("Starting from {}", to_fen(&state));
+                    game = state;
+                    custom_start = true;
+                    break;
+                },
+                Err(e) => {
+                    println!("{}", e);
+                    input.clear();
+                }
+            }
+        } else if let Some(time_control) = input.strip_prefix("custom ") {
+            // PGN style, eg. custom 40/5400+30:1800+30
+            match time_control.parse::<TimeControl>() {
+                Ok(time_control) => {
+                    println!("Custom time control {} selected", time_control);
+                    game.set_time_control(time_control);
+                    break;
+                },
+                Err(e) => {
+                    println!("{}", e);
+                    input.clear();
+                }
+            }
         } else {
             println!("Invalid game mode: {}", input);
             input.clear();
         }
     }
-   
-    
-    game.white_timer = game.white_timer + game.timer_increment;
 
+    // daily and joined games already know their variant, so do 960 and fen start positions
+    if game.correspondence.is_none() && network.is_none() && !custom_start {
+        println!("Select Variant: standard, kingofthehill, threecheck, racingkings, crazyhouse, atomic, antichess, horde");
+        loop {
+            input.clear();
+            let read = std::io::stdin()
+              .read_line(&mut input)
+              .expect("Error reading input");
+            if read == 0 {
+                stdin_closed();
+            }
+
+            match input.parse::<Variant>() {
+                Ok(Variant::Standard) => break,
+                Ok(variant) => {
+                    println!("{:?} selected", variant);
+                    let mut variant_game = GameState::new_variant(variant);
+                    variant_game.mode = game.mode;
+                    variant_game.render = game.render;
+                    variant_game.set_time_control(game.time_control.clone());
+                    game = variant_game;
+                    break;
+                },
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    if let Some(port) = hosting {
+        println!("Waiting for an opponent on port {}", port);
+        match std::net::TcpListener::bind(("0.0.0.0", port)).and_then(|listener| host_game(&listener, &game)) {
+            Ok(connection) => {
+                println!("You are playing white");
+                network = Some((connection, White));
+            },
+            Err(e) => println!("Error hosting game, playing locally: {}", e),
+        }
+    }
+
+    // dont count the time spent picking a mode
+    game.clock = std::time::Instant::now();
     get_legal_move_list(&mut game);
 
-    let threadpool = ThreadPool::new(2).expect("Error creating threads");
+    let threadpool = ThreadPool::new(GAME_THREADS).expect("Error creating threads");
     let input_struct = Arc::new(Mutex::new(UserInput { input: String::new() }));
     
 
     
     let mut event_loop = gameloop::Dispatcher::new(&threadpool);
     let game_state_pointer = Arc::new(Mutex::new(game));
+    game_state_pointer.lock().unwrap().publisher = Some(event_loop.sender());
     
     
-    event_loop.register_handler(Event::UserInput, input_struct.clone());
-    event_loop.register_handler(Event::MoveInput, game_state_pointer.clone());
+    event_loop.register_handler(EventKind::UserInput, input_struct.clone());
+    let mut peer = None;
+    let chat_name = network.as_ref().map_or("Players".to_owned(), |(_, colour)| format!("{:?}", colour));
+    match network {
+        // moves go through the peer so they reach the other player too
+        Some((connection, colour)) => {
+            let (writer, reader) = connection.split();
+            let network_peer = Arc::new(Mutex::new(NetworkPeer::new(game_state_pointer.clone(), writer, colour)));
+            for kind in [EventKind::Move, EventKind::Drop, EventKind::Surrender, EventKind::DrawOffer, EventKind::RemoteInput, EventKind::Chat] {
+                event_loop.register_handler(kind, network_peer.clone());
+            }
+            let sender = event_loop.sender();
+            threadpool.execute(move || forward_messages(reader, sender));
+            peer = Some(network_peer);
+        },
+        None => {
+            event_loop.register_handler(EventKind::Move, game_state_pointer.clone());
+            event_loop.register_handler(EventKind::Drop, game_state_pointer.clone());
+            event_loop.register_handler(EventKind::Surrender, game_state_pointer.clone());
+            event_loop.register_handler(EventKind::DrawOffer, game_state_pointer.clone());
+        },
+    }
 
 
     event_loop.start();    
+    if peer.is_some() {
+        // second loop so the opponent's moves are played while the first one waits on stdin
+        event_loop.start();
+    }
+
+    // spectators see the clocks run down between moves
+    let ticking = Arc::new(std::sync::atomic::AtomicBool::new(true));
+    {
+        let ticking = ticking.clone();
+        let game_state_pointer = game_state_pointer.clone();
+        threadpool.execute(move || {
+            while ticking.load(std::sync::atomic::Ordering::Relaxed) {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                let state = game_state_pointer.lock().unwrap();
+                if !state.game_over {
+                    state.publish(Event::clock_tick(&state));
+                }
+            }
+        });
+    }
     
+    println!("Type help for the list of commands");
+    let mut session = Session::new(game_state_pointer.clone(), &mut event_loop, peer.clone(), chat_name);
+    // theres no one to play against over the network
+    session.computer = computer.filter(|_| peer.is_none());
+    println!("{}", session.board());
     'main_loop: loop {
-        std::thread::sleep(std::time::Duration::from_millis(40)); 
         if game_state_pointer.lock().unwrap().game_over {
             println!("Game Over");
             break 'main_loop
         }
-        
-        
-
-        // probably should be async
-        event_loop.trigger_event(Event::UserInput, Vec::new());
-        std::thread::sleep(std::time::Duration::from_millis(40));
-        // or else main locks inputstruct before input
-        // should be .join() with threadpool
-        let input = input_struct.lock().unwrap().input.clone();
 
-        println!("Line: {input}");
+        if let Some(text) = session.wait_for_computer() {
+            println!("{}", text);
+            continue 'main_loop;
+        }
 
-        //Commands list
-        if input == "exit" {
+        // waits for the line, stdin being closed is the same as exit
+        let Some(Reply::Line(input)) = session.dispatcher.request_blocking(Event::UserInput) else {
             break 'main_loop;
-        }
-        
-        if input == "reset" {
-            game_state_pointer.lock().unwrap().reset();
+        };
+        if input.trim().is_empty() {
+            continue 'main_loop;
         }
 
-        if input == "resign" {
-            let mut state = game_state_pointer.lock().unwrap();
-            state.game_over = true;
-            if state.player_turn == 1 {
-                println!("White Resigns")
-            } else if state.player_turn == 2 {
-                println!("Black Resigns")
-            } else {
-                println!("Invalid player turn")
+        let command = match Command::parse(&input) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue 'main_loop;
             }
+        };
+
+        if command == Command::Tui {
+            match run_tui(&mut session) {
+                Ok(true) => break 'main_loop,
+                Ok(false) => println!("{}", session.board()),
+                Err(e) => println!("Error starting the full screen board: {}", e),
+            }
+            continue 'main_loop;
         }
-        
-        
-        let event = gameloop::Event::MoveInput;
 
-        if let Ok(payload) = parse_payload_from_index(&input) {
-            event_loop.trigger_event(event, payload);
-        } else if let Some(payload) = parse_payload_from_coordinates(&input) {
-            event_loop.trigger_event(event, payload);
-        } /* else {
-            std::thread::sleep(std::time::Duration::from_secs(1));
-        } */
-        // println!("{} :  ", game.board, );
+        let shows_board = Session::shows_board(&command);
+        match session.execute(command) {
+            Outcome::Exit => break 'main_loop,
+            Outcome::Output(text) => if !text.is_empty() {
+                println!("{}", text);
+            },
+        }
+        if shows_board {
+            println!("{}", session.board());
+        }
+    }
+    // stops the engine if it was still thinking
+    drop(session);
 
-        
+    if let Some(peer) = &peer {
+        peer.lock().unwrap().shutdown();
+    }
+    ticking.store(false, std::sync::atomic::Ordering::Relaxed);
+    game_state_pointer.lock().unwrap().publisher = None;
+    // moves still queued are played out before the loops stop
+    if let Err(e) = event_loop.shutdown(ShutdownMode::Drain, DEFAULT_SHUTDOWN_TIMEOUT) {
+        println!("{} event loop(s) did not stop in time", e.still_running);
     }
-    
-    //drop(event_loop);
     
     
 }
 
+// asks until the answer parses, stdin closing gives the default answer if an empty one has one
+fn prompt<T>(question: &str, parse: impl Fn(&str) -> Result<T, String>) -> T {
+    loop {
+        println!("{}", question);
+        let mut input = String::new();
+        let read = std::io::stdin().read_line(&mut input).expect("Error reading input");
+        match parse(&input) {
+            Ok(answer) => return answer,
+            Err(_) if read == 0 => stdin_closed(),
+            Err(e) => println!("{}", e),
+        }
+    }
+}
+
+// nothing more can be asked before the game starts, so theres nothing to play
+fn stdin_closed() -> ! {
+    println!("No more input, exiting");
+    std::process::exit(0)
+}
+
 type BoardRep = (Vec<u8>, Vec<PieceColour>); //1 array of piece type in space and 2nd array of colour of piece type
 type PastBoardRep = Vec<BoardRep>; // 3 move draw rule
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub board: BoardRep, //look into bitboards in the future instead of vec array
     pub move_list: PlayerValidMoves,
@@ -151,51 +407,193 @@ pub struct GameState {
     pub black_in_check: bool,
     pub white_pieces: PieceSet,
     pub black_pieces: PieceSet,
+    // crazyhouse captures waiting to be dropped
+    #[serde(default = "PieceSet::empty")]
+    pub white_pocket: PieceSet,
+    #[serde(default = "PieceSet::empty")]
+    pub black_pocket: PieceSet,
+    // bit per square holding a promoted piece, those go back in the pocket as pawns
+    #[serde(default)]
+    pub promoted_squares: u64,
+    // piece picked ahead of time for the next promotion, asked for on stdin otherwise
+    #[serde(skip)]
+    pub promotion_choice: Option<u8>,
+    // where moves, clock updates and the result get published for spectators
+    #[serde(skip)]
+    pub publisher: Option<EventSender>,
+    // how the board is drawn in the terminal after each move
+    #[serde(skip)]
+    pub render: RenderOptions,
+    // the moves in algebraic notation, for pgn and the move list
+    #[serde(default)]
+    pub san_history: Vec<String>,
+    // the position before the first move, set when it is played
+    #[serde(default)]
+    pub start_fen: Option<String>,
+    #[serde(skip, default = "std::time::Instant::now")]
     pub clock : std::time::Instant,
-    pub timer_increment : std::time::Duration,
+    pub time_control: TimeControl,
     pub mode: GameMode,
     pub game_over: bool,
-    //fide rules set time to 50minutes after 40 moves etc... pub move_count_time_added: ((u8, Duration), (u8, Duration))
+    pub correspondence: Option<Correspondence>,
+    #[serde(default)]
+    pub move_history: Vec<MoveRecord>,
+    #[serde(default)]
+    pub castling_files: CastlingFiles,
+    #[serde(default)]
+    pub chess960: bool,
+    #[serde(default)]
+    pub variant: Variant,
+    #[serde(default)]
+    pub checks_given_by_white: u8,
+    #[serde(default)]
+    pub checks_given_by_black: u8,
+    // how the game ended, None while its still going
+    #[serde(default)]
+    pub result: Option<GameEnd>,
     //reversable table state check
 }
 
-#[derive(Copy, Clone, Debug)]
+// starting files of the king and rooks, same for both colours, only differs from e/h/a in chess960
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CastlingFiles {
+    pub king: usize,
+    pub kingside_rook: usize,
+    pub queenside_rook: usize,
+}
+
+impl Default for CastlingFiles {
+    fn default() -> Self {
+        CastlingFiles { king: 4, kingside_rook: 7, queenside_rook: 0 }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GameMode {
     Default,
     Blitz,
     Rapid,
+    Classical,
     Daily,
 }
 
+impl GameMode {
+    pub fn time_control(&self) -> TimeControl {
+        match self {
+            GameMode::Default => TimeControl::default(),
+            GameMode::Blitz => TimeControl::fischer(5, 1),
+            GameMode::Rapid => TimeControl::fischer(15, 10),
+            GameMode::Classical => TimeControl::fide_classical(),
+            GameMode::Daily => TimeControl::daily(3),
+        }
+    }
+}
 
-impl gameloop::Handler for GameState {
-    // fn handle(&self, event: gameloop::Event, payload: gameloop::Payload) {}
+impl std::str::FromStr for GameMode {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.trim().to_lowercase().as_str() {
+            "default" => Ok(GameMode::Default),
+            "blitz" => Ok(GameMode::Blitz),
+            "rapid" => Ok(GameMode::Rapid),
+            "classical" => Ok(GameMode::Classical),
+            "daily" => Ok(GameMode::Daily),
+            _ => Err(format!("Unknown mode: {}", input)),
+        }
+    }
+}
 
-    fn handle_mut(&mut self, event: gameloop::Event, payload: gameloop::Payload) {
-        let translation = parse_coordinates_from_payload(payload);
-        let valid_move = match self.player_turn {
-            1 => {
-                &self.move_list.white.iter().any(|elem| *elem == translation)
+
+impl gameloop::Handler for GameState {
+    // fn handle(&self, event: gameloop::Event) {}
+
+    fn handle_mut(&mut self, event: gameloop::Event) {
+        match event {
+            Event::Surrender(player) => {
+                let player = player.unwrap_or(self.player_turn);
+                if !self.game_over {
+                    announce(self, if player == 1 { "White Resigns" } else { "Black Resigns" });
+                    self.game_over = true;
+                    self.result = Some(GameEnd::Resignation(player == 1));
+                    self.publish_game_end();
+                }
             },
-            2 => {
-                &self.move_list.black.iter().any(|elem| *elem == translation)
+            Event::Drop(drop) => {
+                let valid_drop = match self.player_turn {
+                    1 => self.move_list.white_drops.contains(&drop),
+                    2 => self.move_list.black_drops.contains(&drop),
+                    _ => panic!("Player_turn wrong"),
+                };
+                if valid_drop {
+                    self.update_chess_clock();
+                    take_drop(self, drop);
+                    self.turn_played();
+                }
             },
-            _ => panic!("Player_turn wrong"),
-        };
-        if *valid_move {
-            self.update_chess_clock();
-            take_turn(self, translation);
-        } else {
-            println!("Not in move list")
+            Event::Move { translation, promotion } => {
+                self.promotion_choice = promotion;
+                let valid_move = match self.player_turn {
+                    1 => {
+                        &self.move_list.white.iter().any(|elem| *elem == translation)
+                    },
+                    2 => {
+                        &self.move_list.black.iter().any(|elem| *elem == translation)
+                    },
+                    _ => panic!("Player_turn wrong"),
+                };
+                if *valid_move {
+                    self.update_chess_clock();
+                    take_turn(self, translation);
+                    self.turn_played();
+                }
+            },
+            // both players are at the same keyboard, offering is agreeing
+            Event::DrawOffer => {
+                if !self.game_over {
+                    announce(self, "Draw by agreement");
+                    self.game_over = true;
+                    self.result = Some(GameEnd::DrawByAgreement);
+                    self.publish_game_end();
+                }
+            },
+            // the game only acts on moves, draws and resignations
+            Event::UserInput | Event::RemoteInput(_) => {},
+            Event::MoveMade { .. } | Event::ClockTick { .. } | Event::GameEnded(_) | Event::Chat { .. } => {},
         }
-        
+    }
+
+    // moves and drops that werent played are answered with why
+    fn respond(&mut self, event: gameloop::Event) -> Option<Reply> {
+        let moves_played = self.move_history.len();
+        let rejection = match &event {
+            _ if self.game_over => "Game is over",
+            Event::Move { .. } => "Illegal move",
+            Event::Drop(_) => "Illegal drop",
+            _ => {
+                self.handle_mut(event);
+                return None;
+            },
+        };
+        self.handle_mut(event);
+        (self.move_history.len() == moves_played).then(|| Reply::Rejected(rejection.to_owned()))
     }
 }
 impl GameState {
+    // after a move or drop from handle_mut went through
+    fn turn_played(&mut self) {
+        if self.correspondence.is_some() {
+            if let Err(e) = save_daily_game(self) {
+                println!("Error saving daily game: {}", e);
+            }
+        }
+        self.publish_turn();
+    }
+
     pub fn new() -> Self {
         GameState {
             board: generate_start_board(),
-            move_list: PlayerValidMoves{ black: MoveList::new(), white: MoveList::new()},
+            move_list: PlayerValidMoves::default(),
             last_move: None,
             player_turn: 1,     //when white takes turn add 1 when black takes turn -1
             white_can_castle_queenside: true,
@@ -205,47 +603,100 @@ impl GameState {
             last_capture_or_pawn_move: 0,
             table_states_since_last_capture_or_pawn_move: vec![boardrep_to_bitboard(&generate_start_board())],
             en_passant_possible: false, //detects if en_passant_possible from last move
-            white_timer: Duration::from_secs(1800), 
-            black_timer: Duration::from_secs(1800),
+            white_timer: TimeControl::default().starting_time(),
+            black_timer: TimeControl::default().starting_time(),
             turn_counter: 0,
             white_in_check: false,
             black_in_check: false,
             white_pieces: PieceSet::new(),
             black_pieces: PieceSet::new(),
+            white_pocket: PieceSet::empty(),
+            black_pocket: PieceSet::empty(),
+            promoted_squares: 0,
+            promotion_choice: None,
+            san_history: Vec::new(),
+            start_fen: None,
+            publisher: None,
+            render: RenderOptions::default(),
             clock: std::time::Instant::now(),
-            timer_increment: Duration::from_secs(30),
+            time_control: TimeControl::default(),
             mode: GameMode::Default,
             game_over: false,
+            correspondence: None,
+            move_history: Vec::new(),
+            castling_files: CastlingFiles::default(),
+            chess960: false,
+            variant: Variant::Standard,
+            checks_given_by_white: 0,
+            checks_given_by_black: 0,
+            result: None,
         }       
     }
-    // allow people to choose mode, blitz/default, can add more later.
+    // allow people to choose mode, blitz/default/rapid/classical, or any time control
+    pub fn set_mode(&mut self, mode: GameMode) {
+        self.set_time_control(mode.time_control());
+        self.mode = mode;
+    }
+
     pub fn blitz_mode(&mut self) {
-        self.white_timer = Duration::from_secs(300);
-        self.black_timer = Duration::from_secs(300);
-        self.timer_increment = Duration:: from_secs(1);
-        self.mode = GameMode::Blitz;
+        self.set_mode(GameMode::Blitz);
+    }
+
+    pub fn rapid_mode(&mut self) {
+        self.set_mode(GameMode::Rapid);
+    }
+
+    pub fn classical_mode(&mut self) {
+        self.set_mode(GameMode::Classical);
+    }
+
+    pub fn set_time_control(&mut self, time_control: TimeControl) {
+        self.white_timer = time_control.starting_time();
+        self.black_timer = time_control.starting_time();
+        self.time_control = time_control;
     }
 
     pub fn reset (&mut self) {
         let mode = self.mode;
-        *self = GameState::new();
-        if let GameMode::Blitz = mode {
-            self.blitz_mode();
-        } 
-        self.white_timer = self.white_timer + self.timer_increment;
+        let time_control = self.time_control.clone();
+        let correspondence = self.correspondence.take();
+        let publisher = self.publisher.take();
+        let render = self.render;
+        *self = GameState::new_variant(self.variant);
+        self.publisher = publisher;
+        self.render = render;
+        self.mode = mode;
+        self.set_time_control(time_control);
+        self.correspondence = correspondence;
         get_legal_move_list(self);
     }
 
     pub fn update_chess_clock(&mut self) {
+        if let Some(correspondence) = &mut self.correspondence {
+            // wall clock deadline instead of the running clock, next player gets the full allowance
+            let time_left = correspondence.time_left();
+            let per_move = correspondence.per_move();
+            correspondence.deadline = std::time::SystemTime::now() + per_move;
+            match self.player_turn {
+                1 => (self.white_timer, self.black_timer) = (time_left, per_move),
+                2 => (self.black_timer, self.white_timer) = (time_left, per_move),
+                _=> panic!("Clock updating detected no player turn")
+            }
+            self.clock = std::time::Instant::now();
+            return;
+        }
+
+        let elapsed = self.clock.elapsed();
+        // turn_counter counts half moves, both players are on the same move number
+        let move_number = self.turn_counter / 2 + 1;
         match self.player_turn {
             1 => {
-                self.white_timer = self.white_timer.saturating_sub(self.clock.elapsed());
+                self.time_control.apply_move(&mut self.white_timer, &mut self.black_timer, elapsed, move_number);
             },
             2 => {
-                self.black_timer = self.black_timer.saturating_sub(self.clock.elapsed()); 
+                self.time_control.apply_move(&mut self.black_timer, &mut self.white_timer, elapsed, move_number);
             },
             _=> panic!("Clock updating detected no player turn")
-                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                                      
         }
         
         self.clock = std::time::Instant::now();
@@ -262,7 +713,7 @@ impl GameState {
 
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct PieceSet {
     pawn: u8,
     rook: u8,
@@ -275,6 +726,58 @@ impl PieceSet {
     pub fn new() -> Self {
         PieceSet { pawn: 8, rook: 2, knight: 2, bishop: 2, queen: 1}
     }
+
+    // counts what is actually on the board, for positions that dont start from the normal setup
+    pub fn empty() -> Self {
+        PieceSet { pawn: 0, rook: 0, knight: 0, bishop: 0, queen: 0 }
+    }
+
+    pub fn count(&self, piece: u8) -> u8 {
+        match piece {
+            PAWN => self.pawn,
+            ROOK => self.rook,
+            KNIGHT => self.knight,
+            BISHOP => self.bishop,
+            QUEEN => self.queen,
+            _ => 0,
+        }
+    }
+
+    pub fn add_piece(&mut self, piece: u8) {
+        match piece {
+            PAWN => self.pawn += 1,
+            ROOK => self.rook += 1,
+            KNIGHT => self.knight += 1,
+            BISHOP => self.bishop += 1,
+            QUEEN => self.queen += 1,
+            EMPTY | KING => {},
+            _ => panic!("No Piecetype {}", piece),
+        }
+    }
+
+    // kings arent counted so taking one is fine in the variants that allow it
+    pub fn remove_piece(&mut self, piece: u8) {
+        match piece {
+            PAWN => self.pawn -= 1,
+            ROOK => self.rook -= 1,
+            KNIGHT => self.knight -= 1,
+            BISHOP => self.bishop -= 1,
+            QUEEN => self.queen -= 1,
+            EMPTY | KING => {},
+            _ => panic!("No Piecetype {}", piece),
+        }
+    }
+
+    pub fn from_board(board: &BoardRep, colour: PieceColour) -> Self {
+        let mut pieces = PieceSet::empty();
+        for (piece, square_colour) in board.0.iter().zip(board.1.iter()) {
+            if *square_colour != colour {
+                continue;
+            }
+            pieces.add_piece(*piece);
+        }
+        pieces
+    }
 }
 
 impl Index<usize> for PieceSet {
@@ -330,13 +833,28 @@ fn generate_start_board() -> BoardRep {
     (piece_type, piece_colour)    
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Coordinates {
     pub x: usize,
     pub y: usize,
 }
 
 impl Coordinates {
+    // a1 is x 0 y 0, h8 is x 7 y 7
+    pub fn to_algebraic(self) -> String {
+        format!("{}{}", (b'a' + self.x as u8) as char, self.y + 1)
+    }
+
+    pub fn from_algebraic(square: &str) -> Option<Self> {
+        let mut chars = square.chars();
+        let file = chars.next()?;
+        let rank = chars.next()?.to_digit(10)? as usize;
+        if chars.next().is_some() || !('a'..='h').contains(&file) || !(1..=8).contains(&rank) {
+            return None;
+        }
+        Some(Coordinates { x: file as usize - 'a' as usize, y: rank - 1 })
+    }
+
     pub fn does_move_run_off_side(self, coord_2: Self) -> Result<Self, RunOffError> {
         if self.y == coord_2.y {
             return Ok(self);
@@ -385,13 +903,27 @@ impl From<Coordinates> for usize {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct PlayerValidMoves {
     pub white: MoveList,
     pub black: MoveList,
+    #[serde(default)]
+    pub white_drops: Vec<DropMove>,
+    #[serde(default)]
+    pub black_drops: Vec<DropMove>,
+}
+
+impl PlayerValidMoves {
+    pub fn is_empty_for(&self, colour: PieceColour) -> bool {
+        match colour {
+            White => self.white.is_empty() && self.white_drops.is_empty(),
+            Black => self.black.is_empty() && self.black_drops.is_empty(),
+            Empty => true,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PieceColour {
     Black,
     White,
@@ -541,6 +1073,7 @@ impl Pawn {
      
     // if pawn is black and origin.y is 6 truncate vertical move to 2
     //else if pawn is white and origin.y is 1 truncate vertical move to 2
+    // horde pawns start on the back rank and can double step from there too
     // if diagonally blocked by other colour allow diagonal move of 1
     pub fn pawn_specific_moves(origin: Coordinates, board: &BoardRep) -> MoveList {
     
@@ -567,7 +1100,7 @@ impl Pawn {
                             .collect::<MoveList>()
                         }, 
                         MoveDirection::South => {
-                            if origin.y == 1 {
+                            if origin.y <= 1 {
                                 directional_move_list
                                 .into_iter()
                                 .enumerate()
@@ -604,7 +1137,7 @@ impl Pawn {
                             .collect::<MoveList>()
                         }, 
                         MoveDirection::North => {
-                            if origin.y == 6 {
+                            if origin.y >= 6 {
                                 directional_move_list
                                 .into_iter()
                                 .enumerate()
@@ -668,12 +1201,21 @@ impl Pawn {
         //because the last move was a pawn move and the vector of table states has been cleared as a result
 
         let premove_board = bitboard_to_boardrep(&state.table_states_since_last_capture_or_pawn_move[0]);
+        let origin_index = usize::from(translation.0);
+        let origin_piece_type = premove_board.0[origin_index] as u8;
+
+        // checked before looking either side of the destination, a1 and h8 have nothing on one side
+        if origin_piece_type != PAWN
+        || ((translation.0.y != 1 && translation.1.y != 3) && (translation.0.y != 6 && translation.1.y != 4))
+        {
+            state.en_passant_possible = false;
+            return (None, None, None);
+        }
+
         let destination = translation.1;
         let left_destination = Coordinates::from(usize::from(translation.1) - 1);
         let right_destination = Coordinates::from(usize::from(translation.1) + 1);
-        let origin_index = usize::from(translation.0);
         let destination_index = usize::from(translation.1);
-        let origin_piece_type = premove_board.0[origin_index] as u8;
         let moving_colour = premove_board.1[origin_index];
         let runs_off_left = destination.does_move_run_off_side(left_destination);
         let runs_off_right = destination.does_move_run_off_side(right_destination);
@@ -681,14 +1223,6 @@ impl Pawn {
         let right_piece_type = (state.board.0[destination_index + 1]) as u8;
         let left_pawn_colour = state.board.1[destination_index - 1];
         let right_pawn_colour = state.board.1[destination_index + 1];
-        
-
-        if origin_piece_type != PAWN
-        || ((translation.0.y != 1 && translation.1.y != 3) && (translation.0.y != 6 && translation.1.y != 4))
-        {
-            state.en_passant_possible = false;
-            return (None, None, None);
-        }
 
         let mut left_is_opposite_colour_pawn: Option<usize> = None;
         let mut right_is_opposite_colour_pawn: Option<usize> = None;
@@ -714,45 +1248,34 @@ impl Pawn {
         //actually write the maneuver
     }
 
+    // after a pawn moves two squares, enemy pawns beside it can take it as if it had moved one
     pub fn append_en_passant_moves(state: &mut GameState, move_list: PlayerValidMoves, translation: Move) -> PlayerValidMoves {
-        let mut output_move_list = PlayerValidMoves {
-            black: move_list.black,
-            white: move_list.white,
-        };
-
-        // let premove_board = &state.table_states_since_last_capture_or_pawn_move[0];
-        let (option_left_pawn_index, option_right_pawn_index, destination) = Pawn::en_passant(state, translation);
-        
-        if state.en_passant_possible == true && option_left_pawn_index.is_some() {
-            let left_pawn_index = option_left_pawn_index.unwrap();
-            match state.board.1[left_pawn_index] {
-                White => {
-                    output_move_list.white.push((Coordinates::from(destination.unwrap()), Coordinates::from(left_pawn_index)));
-                },
-                Black => {
-                    output_move_list.black.push((Coordinates::from(destination.unwrap()), Coordinates::from(left_pawn_index)));
-                },
-                _ => panic!("En passant returning empty colour"),
-            }
+        let mut output_move_list = move_list;
+        let (origin, destination) = translation;
+        let destination_index = usize::from(destination);
+        if state.board.0[destination_index] != PAWN || origin.x != destination.x || origin.y.abs_diff(destination.y) != 2 {
+            return output_move_list;
         }
-        if state.en_passant_possible == true && option_right_pawn_index.is_some() {
-            let right_pawn_index = option_right_pawn_index.unwrap();
-            match state.board.1[right_pawn_index] {
-                White => {
-                    output_move_list.white.push((Coordinates::from(destination.unwrap()), Coordinates::from(right_pawn_index)));
-                },
-                Black => {
-                    output_move_list.black.push((Coordinates::from(destination.unwrap()), Coordinates::from(right_pawn_index)));
-                },
-                _ => panic!("En passant returning empty colour")
+        let moving_colour = state.board.1[destination_index];
+        let target = Coordinates { x: destination.x, y: (origin.y + destination.y) / 2 };
+        for x in [destination.x.checked_sub(1), Some(destination.x + 1).filter(|x| *x < 8)].into_iter().flatten() {
+            let pawn = Coordinates { x, y: destination.y };
+            let pawn_index = usize::from(pawn);
+            if state.board.0[pawn_index] != PAWN {
+                continue;
+            }
+            match (moving_colour, state.board.1[pawn_index]) {
+                (Black, White) => output_move_list.white.push((pawn, target)),
+                (White, Black) => output_move_list.black.push((pawn, target)),
+                _ => {},
             }
         }
-        return output_move_list;
+        output_move_list
     }
 
     pub fn pawn_promotion (destination: Coordinates, state: &mut GameState) {
         //if pawn is on y of 0 or y of 7 after it moves, it promotes to one of the options
-        let promotion_choice = Pawn::get_promotion_choice();
+        let promotion_choice = state.promotion_choice.take().unwrap_or_else(Pawn::get_promotion_choice);
         if state.player_turn == 1 {
             match promotion_choice {
                 KNIGHT => state.white_pieces.knight += 1,
@@ -929,6 +1452,9 @@ impl King {
 
     pub fn check_checker(state: &mut GameState, move_list: MoveList) -> bool {
         // if king position is in movelist, player of king colour is in check.s
+        if move_list.is_empty() {
+            return false;
+        }
         let first_move = move_list[0];
         let first_move_origin = first_move.0;
         let colour_of_moves = state.board.1[usize::from(first_move_origin)];
@@ -960,127 +1486,118 @@ impl King {
                 continue;
             }
         }
-        panic!("Missing King");
-    }
-
-    pub fn can_castle_kingside(move_list: &PlayerValidMoves, state: &GameState) -> (bool, bool) {
-        let uncheckable_squares_white = [Coordinates{x:4,y:0}, Coordinates{x:5,y:0}, Coordinates{x:6,y:0}];
-        let uncheckable_squares_black = [Coordinates{x:4,y:7}, Coordinates{x:5,y:7}, Coordinates{x:6,y:7}];
-        let empty_squares_white = [Coordinates{x:5,y:0}, Coordinates{x:6,y:0}]; 
-        let empty_squares_black = [Coordinates{x:5,y:7}, Coordinates{x:6,y:7}]; 
-
-        let white_moves = &move_list.white;
-        let black_moves = &move_list.black;
-        let mut white_castle = true;
-        let mut black_castle = true;
-        //if uncheckable squares are in end position of opposite colour movelist, you cannot castle
-        //if coordinates of x1 x2 or x3 are occupied, cannot castle
-        for translation in white_moves {
-            if uncheckable_squares_black.into_iter().any(|square| square == translation.1)
-            || empty_squares_black.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.black_can_castle_queenside == false
-            || (state.board.0[63] != ROOK || state.board.1[63] != PieceColour::Black)
-            {
-                black_castle = false;
-            }
-        }
+        // horde has no white king, nothing to check
+        false
+    }
 
-        for translation in black_moves {
-            if uncheckable_squares_white.into_iter().any(|square| square == translation.1)
-            || empty_squares_white.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.white_can_castle_queenside == false
-            || (state.board.0[7] != ROOK || state.board.1[7] != PieceColour::White)
-            {
-                white_castle = false;
-            }
-        }
-        return (white_castle, black_castle)
+    pub fn can_castle_kingside(state: &GameState) -> (bool, bool) {
+        (King::can_castle(state, White, true), King::can_castle(state, Black, true))
     }
     
-    pub fn can_castle_queenside(move_list: &PlayerValidMoves, state: &GameState) -> (bool, bool) {
-        let uncheckable_squares_white = [Coordinates{x:2,y:0}, Coordinates{x:3,y:0}, Coordinates{x:4,y:0}];
-        let uncheckable_squares_black = [Coordinates{x:2,y:7}, Coordinates{x:3,y:7}, Coordinates{x:4,y:7}];
-        let empty_squares_white = [Coordinates{x:2,y:0}, Coordinates{x:3,y:0}]; 
-        let empty_squares_black = [Coordinates{x:2,y:7}, Coordinates{x:3,y:7}]; 
-        
-        
-        let white_moves = &move_list.white;
-        let black_moves = &move_list.black;
-        let mut white_castle = true;
-        let mut black_castle = true;
-        //if uncheckable squares are in end position of opposite colour movelist, you cannot castle
-        //if coordinates of x1 x2 or x3 are occupied, cannot castle
-        for translation in white_moves {
-            if uncheckable_squares_black.into_iter().any(|square| square == translation.1)
-            || empty_squares_black.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.black_can_castle_queenside == false
-            ||(state.board.0[56] != ROOK || state.board.1[56] != PieceColour::Black)
-            {
-                black_castle = false;
-            }
+    pub fn can_castle_queenside(state: &GameState) -> (bool, bool) {
+        (King::can_castle(state, White, false), King::can_castle(state, Black, false))
+    }
+
+    // works for any chess960 setup, the king always ends on the g or c file and the rook next to it on the f or d file
+    pub fn can_castle(state: &GameState, colour: PieceColour, kingside: bool) -> bool {
+        let (rank, has_right) = match (colour, kingside) {
+            (White, true) => (0, state.white_can_castle_kingside),
+            (White, false) => (0, state.white_can_castle_queenside),
+            (Black, true) => (7, state.black_can_castle_kingside),
+            (Black, false) => (7, state.black_can_castle_queenside),
+            _ => panic!("Empty colour cannot castle"),
+        };
+        let files = state.castling_files;
+        let rook_file = if kingside { files.kingside_rook } else { files.queenside_rook };
+        let (king_destination, rook_destination) = if kingside { (6, 5) } else { (2, 3) };
+        let square = |x: usize| rank * 8 + x;
+
+        if !has_right
+        || state.board.0[square(files.king)] != KING || state.board.1[square(files.king)] != colour
+        || state.board.0[square(rook_file)] != ROOK || state.board.1[square(rook_file)] != colour
+        {
+            return false;
         }
 
-        for translation in black_moves {
-            if uncheckable_squares_white.into_iter().any(|square| square == translation.1)
-            || empty_squares_white.into_iter().any(|square| state.board.1[usize::from(square)] != PieceColour::Empty)
-            || state.white_can_castle_queenside == false
-            || (state.board.0[0] != ROOK || state.board.1[0] != PieceColour::White)
-            {
-                white_castle = false;
-            }
+        //everything the king and rook pass over has to be empty, apart from the king and rook themselves
+        let king_path = files.king.min(king_destination)..=files.king.max(king_destination);
+        let rook_path = rook_file.min(rook_destination)..=rook_file.max(rook_destination);
+        let path_blocked = king_path.clone().chain(rook_path).any(|x| {
+            x != files.king && x != rook_file && state.board.1[square(x)] != PieceColour::Empty
+        });
+        if path_blocked {
+            return false;
         }
 
-        return (white_castle, black_castle)
+        //cant castle out of, through or into check
+        let opponent = if colour == White { Black } else { White };
+        !king_path.into_iter().any(|x| is_square_attacked(&state.board, Coordinates::from(square(x)), opponent))
     }
 
     pub fn append_castle_moves(move_list: PlayerValidMoves, state: &GameState) -> PlayerValidMoves {
         let mut output_move_list = PlayerValidMoves {
             white: move_list.white,
             black: move_list.black,
+            ..move_list
         };
-        //if white can queenside  castle append that move
-        let white_queenside_castle_move = (Coordinates {x:4, y: 0}, Coordinates{x: 2,y: 0});
-        let black_queenside_castle_move = (Coordinates {x:4, y: 7}, Coordinates{x: 2,y: 7});
-        let white_kingside_castle_move = (Coordinates {x:4, y: 0}, Coordinates{x: 6, y: 0});
-        let black_kingside_castle_move = (Coordinates {x:4, y: 7}, Coordinates{x: 6, y: 7});
+        let kingside = King::can_castle_kingside(state);
+        let queenside = King::can_castle_queenside(state);
         
-        let kingside = King::can_castle_kingside(&output_move_list, state);
-        let queenside = King::can_castle_queenside(&output_move_list, state);
-        
-        if state.white_can_castle_kingside && kingside.0 {
-            output_move_list.white.push(white_kingside_castle_move)
+        if kingside.0 {
+            output_move_list.white.push(King::castle_move(state, 0, true))
         }
-        if state.black_can_castle_kingside && kingside.1 {
-            output_move_list.black.push(black_kingside_castle_move)
+        if kingside.1 {
+            output_move_list.black.push(King::castle_move(state, 7, true))
         }
-        if state.white_can_castle_queenside && queenside.0{
-            output_move_list.white.push(white_queenside_castle_move)
+        if queenside.0 {
+            output_move_list.white.push(King::castle_move(state, 0, false))
         }
-        if state.black_can_castle_queenside && queenside.1{
-            output_move_list.black.push(black_queenside_castle_move)
+        if queenside.1 {
+            output_move_list.black.push(King::castle_move(state, 7, false))
         }
 
         return output_move_list;
     }
+
+    // standard games move the king 2 squares, chess960 moves the king onto its own rook so its never ambiguous
+    pub fn castle_move(state: &GameState, rank: usize, kingside: bool) -> Move {
+        let files = state.castling_files;
+        let origin = Coordinates { x: files.king, y: rank };
+        let destination = match (state.chess960, kingside) {
+            (true, true) => files.kingside_rook,
+            (true, false) => files.queenside_rook,
+            (false, true) => 6,
+            (false, false) => 2,
+        };
+        (origin, Coordinates { x: destination, y: rank })
+    }
     
     // call this function when move selected and board changed
     pub fn is_move_a_castle(translation: Move, board: &BoardRep) -> bool {
-        return board.0[usize::from(translation.0)] == KING
-        && (translation.1.x as i8).abs_diff(translation.0.x as i8) > 1;
+        let origin = usize::from(translation.0);
+        let destination = usize::from(translation.1);
+        return board.0[origin] == KING
+        && ((translation.1.x as i8).abs_diff(translation.0.x as i8) > 1
+            || (board.0[destination] == ROOK && board.1[destination] == board.1[origin]));
     }
+
     pub fn check_to_disable_castling(state: &mut GameState) {
-        if state.board.0[4] != ROOK || state.board.1[7] != White {
-            state.white_can_castle_kingside = false
-        }
-        if state.board.0[60] != ROOK || state.board.1[63] != Black {
-            state.black_can_castle_kingside = false
-        }
-        if state.board.0[4] != ROOK || state.board.1[0] != White {
-            state.white_can_castle_queenside = false
-        }
-        if state.board.0[60] != ROOK || state.board.1[56] != Black {
-            state.black_can_castle_queenside = false
-        }
+        let files = state.castling_files;
+        let is_piece = |x: usize, y: usize, piece: u8, colour: PieceColour| {
+            state.board.0[y * 8 + x] == piece && state.board.1[y * 8 + x] == colour
+        };
+        //king moved, both sides gone, rook moved or captured only that side
+        let white_king_home = is_piece(files.king, 0, KING, White);
+        let black_king_home = is_piece(files.king, 7, KING, Black);
+        let white_kingside = white_king_home && is_piece(files.kingside_rook, 0, ROOK, White);
+        let black_kingside = black_king_home && is_piece(files.kingside_rook, 7, ROOK, Black);
+        let white_queenside = white_king_home && is_piece(files.queenside_rook, 0, ROOK, White);
+        let black_queenside = black_king_home && is_piece(files.queenside_rook, 7, ROOK, Black);
+
+        state.white_can_castle_kingside &= white_kingside;
+        state.black_can_castle_kingside &= black_kingside;
+        state.white_can_castle_queenside &= white_queenside;
+        state.black_can_castle_queenside &= black_queenside;
     }
     // pub fn its_1000_years_too_early_for_you_to_fight_me_kid(state: &GameState) {
     //     //king teleports behind a pawn and kills it
@@ -1100,6 +1617,29 @@ pub fn is_square_occupied(movement: Move, board: &BoardRep) -> (bool, bool) {
     return (is_occupied, is_same_colour);
 }
 
+pub fn is_in_check(board: &BoardRep, colour: PieceColour) -> bool {
+    let opponent = if colour == White { Black } else { White };
+    let king = (0..board.0.len()).find(|index| board.0[*index] == KING && board.1[*index] == colour);
+    match king {
+        Some(index) => is_square_attacked(board, Coordinates::from(index), opponent),
+        None => false,
+    }
+}
+
+// puts a piece of the defending colour on the square so pawn captures show up, then looks for any move landing on it
+pub fn is_square_attacked(board: &BoardRep, square: Coordinates, by_colour: PieceColour) -> bool {
+    let mut board = board.clone();
+    let index = usize::from(square);
+    if board.1[index] == Empty {
+        board.0[index] = KNIGHT;
+        board.1[index] = if by_colour == White { Black } else { White };
+    }
+
+    let moves = get_valid_moves_for_piece(&board);
+    let attacker_moves = if by_colour == White { moves.white } else { moves.black };
+    attacker_moves.iter().any(|translation| translation.1 == square)
+}
+
 pub fn slice_valid_moves_at_collision(list: MoveList, board: &BoardRep) -> MoveList {
     let mut move_list: MoveList = Vec::new();
     for index in 0..list.len() {
@@ -1146,7 +1686,17 @@ pub fn generate_lateral_moves(origin: Coordinates, board: &BoardRep) -> MoveList
     return move_list;
 }
 
-type Move = (Coordinates, Coordinates);
+pub type Move = (Coordinates, Coordinates);
+
+// a played move, promotion is the piece chosen when a pawn reached the last rank
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub translation: Move,
+    pub promotion: Option<u8>,
+    // crazyhouse piece dropped on translation.1
+    #[serde(default)]
+    pub drop: Option<u8>,
+}
 
 pub fn separate_direction_from_movelist(list: &MoveList, direction: MoveDirection) -> MoveList {
     let mut moves = list.clone()
@@ -1237,11 +1787,19 @@ pub fn get_valid_moves_for_piece(board: &BoardRep) -> PlayerValidMoves {
     let total_moves_for_piece = PlayerValidMoves {
         white: white_move_list,
         black: black_move_list,
+        ..Default::default()
     };
 
     return total_moves_for_piece;                                                                                                                                                                                                                                                                                                                                                                                                                                   
 }
 
+// the square of the pawn taken en passant, if thats what the move is
+pub fn en_passant_capture(board: &BoardRep, translation: Move) -> Option<usize> {
+    let (origin, destination) = translation;
+    let is_en_passant = board.0[usize::from(origin)] == PAWN && origin.x != destination.x && board.0[usize::from(destination)] == EMPTY;
+    is_en_passant.then(|| usize::from(Coordinates { x: destination.x, y: origin.y }))
+}
+
 pub fn make_move(board: &BoardRep, translation: Move) -> BoardRep {
     let mut piece_board = board.0.clone();
     let mut colour_board = board.1.clone();
@@ -1254,27 +1812,35 @@ pub fn make_move(board: &BoardRep, translation: Move) -> BoardRep {
     colour_board[origin_index] = Empty;
     colour_board[destination_index] = piece_colour;
 
+    // a pawn can only move sideways onto an empty square by taking en passant
+    if let Some(passed_pawn) = en_passant_capture(board, translation) {
+        piece_board[passed_pawn] = EMPTY;
+        colour_board[passed_pawn] = Empty;
+    }
+
     if King::is_move_a_castle(translation, board) {
-        //if king's X increases kingside rook of same Y value moves -2 X
-        let king_origin = usize::from(translation.0);
-        let king_destination = usize::from(translation.1);
-        let king_colour = board.1[king_origin];
-
-        if king_destination as i8 - king_origin as i8 == 2 {
-            piece_board[king_destination + 1] = EMPTY;
-            colour_board[king_destination + 1] = Empty;
-            
-            piece_board[king_origin + 1] = ROOK;
-            colour_board[king_origin + 1] = king_colour;
-        }
-        // if king's X decreases, queenside rook of same Y moves +3 X
-        else {
-            piece_board[king_destination - 2] = EMPTY;
-            colour_board[king_destination - 2] = Empty;
-            
-            piece_board[king_origin - 1] = ROOK;
-            colour_board[king_origin - 1] = king_colour;
-        }
+        //king ends on g or c file, rook next to it on the inside
+        //standard games say where the king goes, chess960 games say which rook
+        let rank = translation.0.y * 8;
+        let kingside = translation.1.x > translation.0.x;
+        let rook_origin = if board.0[destination_index] == ROOK && board.1[destination_index] == piece_colour {
+            destination_index
+        } else if kingside {
+            rank + 7
+        } else {
+            rank
+        };
+        let (king_destination, rook_destination) = if kingside { (rank + 6, rank + 5) } else { (rank + 2, rank + 3) };
+
+        piece_board[destination_index] = EMPTY;
+        colour_board[destination_index] = Empty;
+        piece_board[rook_origin] = EMPTY;
+        colour_board[rook_origin] = Empty;
+
+        piece_board[king_destination] = KING;
+        colour_board[king_destination] = piece_colour;
+        piece_board[rook_destination] = ROOK;
+        colour_board[rook_destination] = piece_colour;
     } 
 
     return (piece_board, colour_board)
@@ -1285,6 +1851,9 @@ pub fn remove_check_positions(list: MoveList, state: &mut GameState) -> MoveList
     //takes in movelist makes move for every move of its colour,
     // checks to see if its king is in check in any of the boards that were generated
     // it outputs a movelist without the moves that put its king in check
+    if list.is_empty() {
+        return list;
+    }
     let first_move = list[0];
     let origin = first_move.0;
     let move_color = state.board.1[usize::from(origin)];
@@ -1315,63 +1884,74 @@ pub fn remove_check_positions(list: MoveList, state: &mut GameState) -> MoveList
 }
 
 pub fn get_legal_move_list(state: &mut GameState) {
+    let rules = state.variant.rules();
+    state.move_list = rules.legal_moves(state);
+
+    // the simulated boards in remove_check_positions leave these set to whatever the last move tried was
+    state.white_in_check = rules.in_check(&state.board, White);
+    state.black_in_check = rules.in_check(&state.board, Black);
+}
+
+// normal chess rules, moves that leave your own king in check are removed
+pub fn standard_legal_moves(state: &mut GameState) -> PlayerValidMoves {
     let move_list = get_valid_moves_for_piece(&state.board);
     let white_move_list = remove_check_positions(move_list.white, state);
     let black_move_list = remove_check_positions(move_list.black, state);
 
-    let mut output_move_list = PlayerValidMoves {white: white_move_list, black: black_move_list};
+    let mut output_move_list = PlayerValidMoves {white: white_move_list, black: black_move_list, ..Default::default()};
     
     output_move_list = King::append_castle_moves(output_move_list, state);
 
-    if state.last_move.is_some(){
-        output_move_list = Pawn::append_en_passant_moves(state, output_move_list, state.last_move.unwrap());
+    // taking en passant can uncover a check along the rank, so it goes through the same filter
+    if let Some(last_move) = state.last_move {
+        let en_passant = Pawn::append_en_passant_moves(state, PlayerValidMoves::default(), last_move);
+        output_move_list.white.extend(remove_check_positions(en_passant.white, state));
+        output_move_list.black.extend(remove_check_positions(en_passant.black, state));
     }
 
-    state.move_list = output_move_list;
+    output_move_list
 }
 
 pub fn take_turn(state: &mut GameState, translation: Move) {
+    if state.move_history.is_empty() && state.start_fen.is_none() {
+        state.start_fen = Some(to_fen(state));
+    }
+    let san = move_to_san(state, translation, None);
     let premove_board = state.board.clone();
     // let move_colour = state.board.1[usize::from(translation.0)];
     state.board = make_move(&state.board, translation);
 
-    let captured_piece = premove_board.0[usize::from(translation.1)];
-    let captured_colour = premove_board.1[usize::from(translation.1)];
-    if captured_colour == White {
-        match captured_piece {
-            PAWN => state.white_pieces.pawn -= 1,
-            ROOK => state.white_pieces.rook -= 1,
-            KNIGHT => state.white_pieces.knight -= 1,
-            BISHOP =>state.white_pieces.bishop -= 1,
-            QUEEN =>state.white_pieces.queen -= 1,
-            EMPTY => {},
-            _=> panic!("No Piecetype for given square"),
-                
-        }    
-    } else if captured_colour == Black {
-        match captured_piece {
-            PAWN => state.black_pieces.pawn -=1,
-            ROOK => state.black_pieces.rook -= 1,
-            KNIGHT => state.black_pieces.knight -= 1,
-            BISHOP => state.black_pieces.bishop -= 1,
-            QUEEN => state.black_pieces.queen -= 1,           
-            EMPTY => {},
-            _=> panic!("No Piecetype for given square"),
-        }
+    // chess960 castling lands on your own rook, thats not a capture
+    let is_castle = King::is_move_a_castle(translation, &premove_board);
+    let captured_square = en_passant_capture(&premove_board, translation).unwrap_or(usize::from(translation.1));
+    let captured_piece = if is_castle { EMPTY } else { premove_board.0[captured_square] };
+    let captured_colour = if is_castle { Empty } else { premove_board.1[captured_square] };
+    match captured_colour {
+        White => state.white_pieces.remove_piece(captured_piece),
+        Black => state.black_pieces.remove_piece(captured_piece),
+        Empty => {},
     }
-    
-
 
     // pawn promotion
     let is_pawn = premove_board.0[usize::from(translation.0)] == PAWN;
+    let mut promotion = None;
     if is_pawn && (translation.1.y == 0 || translation.1.y == 7) {
         Pawn::pawn_promotion(translation.1, state);
+        promotion = Some(state.board.0[usize::from(translation.1)]);
+    }
+    state.move_history.push(MoveRecord { translation, promotion, drop: None });
+    // old saves have no san history, those games keep going without it
+    if state.san_history.len() + 1 == state.move_history.len() {
+        state.san_history.push(match promotion {
+            Some(piece) => format!("{}={}", san, piece_letter(piece)),
+            None => san,
+        });
     }
 
     state.last_move = Some(translation);
     
     //table states updates
-    if premove_board.0[usize::from(translation.0)] == PAWN || premove_board.0[usize::from(translation.1)] != EMPTY {
+    if premove_board.0[usize::from(translation.0)] == PAWN || captured_piece != EMPTY {
         state.last_capture_or_pawn_move = 0;
         
         state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board.clone())];
@@ -1383,12 +1963,28 @@ pub fn take_turn(state: &mut GameState, translation: Move) {
 
     //if king or kingside rook moves, state.colour.can kinside castle = false
     King::check_to_disable_castling(state);
+    // a chess960 king can castle without leaving its square, so the rook check above isnt enough
+    if is_castle {
+        match premove_board.1[usize::from(translation.0)] {
+            White => (state.white_can_castle_kingside, state.white_can_castle_queenside) = (false, false),
+            Black => (state.black_can_castle_kingside, state.black_can_castle_queenside) = (false, false),
+            _ => panic!("Empty colour castled"),
+        }
+    }
     //en passant logic: black pawn on y= 6 moving to y=4, white pawn on y=4 takes y = 5 where x is +1 or -1 not between
     // white pawn y=1 moving to y=3, black pawn y=3 takes y=2 where x is either -1 or +1
     // call function
 
     Pawn::en_passant(state, translation);
 
+    let rules = state.variant.rules();
+    rules.after_move(state, translation, &premove_board);
+
+    finish_turn(state);
+}
+
+// everything after the board changes thats the same for moves and crazyhouse drops
+pub fn finish_turn(state: &mut GameState) {
     //check checking
     match state.player_turn {
         1 => state.white_in_check = false,
@@ -1401,30 +1997,38 @@ pub fn take_turn(state: &mut GameState, translation: Move) {
     
     //game over check
     if let Some(ending) = game_end(state) {
-        println!("{:?}", ending);
+        announce(state, &format!("{:?}", ending));
         state.game_over = true;
+        state.result = Some(ending);
+    }
+
+    // check and mate marks on the move just played
+    let opponent_in_check = if state.player_turn == 1 { state.black_in_check } else { state.white_in_check };
+    if state.san_history.len() == state.move_history.len() {
+        if let Some(san) = state.san_history.last_mut() {
+            if matches!(state.result, Some(GameEnd::Checkmate(_))) {
+                san.push('#');
+            } else if opponent_in_check {
+                san.push('+');
+            }
+        }
     }
     
     //update turn counter
     state.turn_counter += 1;
 
-    // add timer increment to next player
-    match state.player_turn {
-        1 => state.black_timer += state.timer_increment,
-        2 => state.white_timer += state.timer_increment,
-        _ => panic!("Player turn is not 1(white) or 2(black)"),
-    }
     //change player turn
     if state.player_turn == 1 {
         state.player_turn = 2
     } else {
         state.player_turn = 1
     }
-    //fantastic GUI
-    println!("{:?}, player turn {:?}, White clock {:?}, Black clock {:?}, Is white in check {:?}, Is black in check {:?}", state.board, state.player_turn, state.white_timer, state.black_timer, state.white_in_check, state.black_in_check )
+    if !state.render.silent {
+        println!("{}", render_game(state, &state.render));
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GameEnd {
     Stalemate, // good
     InsufficientMaterials, // good
@@ -1433,9 +2037,31 @@ pub enum GameEnd {
     Checkmate(bool), // good 
     TimeOut(bool), // good
     Resignation(bool),
+    // variant wins, true when white loses like the ones above
+    KingOfTheHill(bool),
+    ThreeCheck(bool),
+    RaceFinished(bool),
+    RaceDraw,
+    KingExploded(bool),
+    HordeCaptured,
+    AllPiecesLost(bool),
+    StalemateWin(bool),
+    DrawByAgreement,
 }
 
 impl GameEnd {
+    // None for draws
+    pub fn white_lost(&self) -> Option<bool> {
+        match *self {
+            GameEnd::Checkmate(white_lost) | GameEnd::TimeOut(white_lost) | GameEnd::Resignation(white_lost)
+            | GameEnd::KingOfTheHill(white_lost) | GameEnd::ThreeCheck(white_lost) | GameEnd::RaceFinished(white_lost)
+            | GameEnd::KingExploded(white_lost) | GameEnd::AllPiecesLost(white_lost) | GameEnd::StalemateWin(white_lost) => Some(white_lost),
+            GameEnd::HordeCaptured => Some(true),
+            GameEnd::Stalemate | GameEnd::InsufficientMaterials | GameEnd::FiftyMoveRuleDraw | GameEnd::RepetitionDraw
+            | GameEnd::RaceDraw | GameEnd::DrawByAgreement => None,
+        }
+    }
+
     pub fn insufficient_materials(state: &GameState) -> (bool, bool)  {
         //pawn, rook, and queen must be 0
         //knight alone must be less than 3
@@ -1484,44 +2110,44 @@ pub fn checker_board(square: Coordinates) -> bool {
     }
 }
 
-pub fn game_end(state: &mut GameState) -> Option<GameEnd> {   
+pub fn game_end(state: &mut GameState) -> Option<GameEnd> {
+    let rules = state.variant.rules();
+    rules.game_end(state)
+}
+
+pub fn standard_game_end(state: &mut GameState) -> Option<GameEnd> {   
+    let insufficient_materials = state.variant.rules().insufficient_material(state);
     //given player movelist is empty, game ends and given player loses.
-    return if state.move_list.white.len() == 0 && state.white_in_check {
-        println!("Black Wins by Checkmate");
+    return if state.move_list.is_empty_for(White) && state.white_in_check {
+        announce(state, "Black Wins by Checkmate");
         Some(GameEnd::Checkmate(true))
-    } else if state.move_list.white.len() == 0 && !state.white_in_check {
-        println!("White Draws Stalemate");
+    } else if state.move_list.is_empty_for(White) && !state.white_in_check {
+        announce(state, "White Draws Stalemate");
         Some(GameEnd::Stalemate)
-    } else if state.move_list.black.len() == 0 && state.black_in_check {
-        println!("White Wins by Checkmate");
+    } else if state.move_list.is_empty_for(Black) && state.black_in_check {
+        announce(state, "White Wins by Checkmate");
         Some(GameEnd::Checkmate(false))
-    } else if state.move_list.black.len() == 0 && !state.black_in_check {
-        println!("White Draws Stalemate");
+    } else if state.move_list.is_empty_for(Black) && !state.black_in_check {
+        announce(state, "White Draws Stalemate");
         Some(GameEnd::Stalemate)
     } else if state.last_capture_or_pawn_move >= 100 {
-        println!("Draw by 50 move rule");
+        announce(state, "Draw by 50 move rule");
         Some(GameEnd::FiftyMoveRuleDraw)
     } else if game_end_by_repetition(state) {
-        println!("Draw by threefold repetition");
+        announce(state, "Draw by threefold repetition");
         Some(GameEnd::RepetitionDraw)
-    } else if GameEnd::insufficient_materials(state) == (true, true) {
-        println!("Draw by insufficient Material");
+    } else if insufficient_materials == (true, true) {
+        announce(state, "Draw by insufficient Material");
         Some(GameEnd::InsufficientMaterials)
-    } else if state.white_timer <= Duration::from_secs(0) && GameEnd::insufficient_materials(state) == (false, true) {
-        println!("Draw by White Time Out and Black Insufficient Materials");
+    } else if state.white_timer <= Duration::from_secs(0) && insufficient_materials == (false, true) {
+        announce(state, "Draw by White Time Out and Black Insufficient Materials");
         Some(GameEnd::InsufficientMaterials)
-    } else if state.black_timer <= Duration::from_secs(0) && GameEnd::insufficient_materials(state) == (true, false) {
-        println!("Draw by Black Time Out and White Insufficient Materials");
+    } else if state.black_timer <= Duration::from_secs(0) && insufficient_materials == (true, false) {
+        announce(state, "Draw by Black Time Out and White Insufficient Materials");
         Some(GameEnd::InsufficientMaterials)
-    } else if state.white_timer <= Duration::from_secs(0) {
-        println!("Draw by White Time Out");
-        Some(GameEnd::TimeOut(true))
-    } else if state.black_timer <= Duration::from_secs(0) {
-        println!("Draw by Black Time Out");
-        Some(GameEnd::TimeOut(false))
     } // Resignation  
     else {
-        None
+        game_end_by_time(state)
     };
 
     // insufficient material, King, king bishop, king knight, kingknight knight, 
@@ -1534,6 +2160,26 @@ pub fn game_end(state: &mut GameState) -> Option<GameEnd> {
     //stalemate, insufficient material, 50 move rule, repitition, and agreement are all draws.
 }
 
+// game end messages stay off stdout when rendering is silent, the same as the board
+fn announce(state: &GameState, message: &str) {
+    if !state.render.silent {
+        println!("{}", message);
+    }
+}
+
+// whoever ran out of time loses, for variants that dont share the rest of standard_game_end
+fn game_end_by_time(state: &GameState) -> Option<GameEnd> {
+    if state.white_timer <= Duration::from_secs(0) {
+        announce(state, "Black Wins on Time");
+        Some(GameEnd::TimeOut(true))
+    } else if state.black_timer <= Duration::from_secs(0) {
+        announce(state, "White Wins on Time");
+        Some(GameEnd::TimeOut(false))
+    } else {
+        None
+    }
+}
+
 fn game_end_by_repetition(state: &mut GameState) -> bool {
     let board_list = &state.table_states_since_last_capture_or_pawn_move;
     if board_list.len() <= 2 {
@@ -1558,22 +2204,22 @@ fn game_end_by_repetition(state: &mut GameState) -> bool {
 
 }
 
-fn parse_payload_from_index(index_string: &str) -> Result<gameloop::Payload, std::num::ParseIntError> {
-    let mut split = index_string.trim().split_whitespace();
+fn parse_move_from_index(index_string: &str) -> Option<Move> {
+    let mut split = index_string.split_whitespace();
     let indices = (
         split.next().unwrap_or_default().to_string(),
         split.next().unwrap_or_default().to_string()
     );
 
-    let origin_index = indices.0.trim().parse::<u8>()?;
-    let destination_index = indices.1.trim().parse::<u8>()?;
+    let origin_index = indices.0.trim().parse::<usize>().ok().filter(|index| *index < 64)?;
+    let destination_index = indices.1.trim().parse::<usize>().ok().filter(|index| *index < 64)?;
 
-    Ok(vec![origin_index, destination_index])
+    Some((Coordinates::from(origin_index), Coordinates::from(destination_index)))
 
 }
 
 
-fn parse_payload_from_coordinates(simple_coords: &str) -> Option<gameloop::Payload> {
+fn parse_move_from_coordinates(simple_coords: &str) -> Option<Move> {
     //input format origin to destination : (a,b) (x,y) 
     let mut split = simple_coords.split_whitespace();
     let indices = (
@@ -1583,21 +2229,14 @@ fn parse_payload_from_coordinates(simple_coords: &str) -> Option<gameloop::Paylo
 
     let x_digit = indices.0.trim().chars().nth(1)?;
     let y_digit =  indices.0.trim().chars().nth(3)?;
-    let origin = Coordinates { x: x_digit.to_digit(10)? as usize, y: y_digit.to_digit(10)? as usize }; 
+    let origin = Coordinates { x: x_digit.to_digit(8)? as usize, y: y_digit.to_digit(8)? as usize }; 
                                              
 
     let x_digit = indices.1.trim().chars().nth(1)?;
     let y_digit =  indices.1.trim().chars().nth(3)?;
-    let destination = Coordinates { x: x_digit.to_digit(10)? as usize, y: y_digit.to_digit(10)? as usize };
+    let destination = Coordinates { x: x_digit.to_digit(8)? as usize, y: y_digit.to_digit(8)? as usize };
 
     // println!("{:?}, {:?}", origin, destination);
 
-    Some(vec![usize::from(origin) as u8, usize::from(destination) as u8])
-}
-
-fn parse_coordinates_from_payload(payload: Payload) -> Move {
-    let origin = Coordinates::from(payload[0] as usize);
-    let destination = Coordinates::from(payload[1] as usize);
-
-    (origin, destination)
+    Some((origin, destination))
 }
\ No newline at end of file
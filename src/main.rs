@@ -1,10 +1,57 @@
 use cheess::run;
+use cheess::*;
+use cheess::PieceColour::*;
 use eframe::{App, run_native};
 use eframe::egui;
+use std::time::Duration;
 mod pog;
 
-#[derive(Default)]
-struct Game {}
+#[derive(Clone, Copy, PartialEq)]
+enum Opponent {
+    Human,
+    Computer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    White,
+    Black,
+    Random,
+}
+
+// what the start screen asks for, same choices as the mode prompt in run()
+struct Setup {
+    mode: GameMode,
+    opponent: Opponent,
+    side: Side,
+    difficulty: Difficulty,
+}
+
+struct Game {
+    setup: Setup,
+    // None while the start screen is up
+    state: Option<GameState>,
+    computer: Option<Computer>,
+    thinking: Option<TaskHandle<Option<Event>>>,
+    selected: Option<usize>,
+    flipped: bool,
+    // one thread for the engine's moves and one for analysis
+    threads: ThreadPool,
+    analysing: bool,
+    // how many of the engine's best lines the panel lists
+    analysis_lines: usize,
+    analysis: Option<Analysis>,
+    // an earlier position being looked at and how many moves into the game it is, None for the live board
+    viewing: Option<(usize, GameState)>,
+}
+
+const LIGHT_SQUARE: egui::Color32 = egui::Color32::from_rgb(240, 217, 181);
+const DARK_SQUARE: egui::Color32 = egui::Color32::from_rgb(181, 136, 99);
+const LAST_MOVE: egui::Color32 = egui::Color32::from_rgb(205, 210, 106);
+const SELECTED: egui::Color32 = egui::Color32::from_rgb(130, 151, 105);
+const CHECK: egui::Color32 = egui::Color32::from_rgb(220, 80, 70);
+// more lines than this dont fit in the side panel
+const MAX_ANALYSIS_LINES: usize = 8;
 
 impl Game {
     fn new(cc: &eframe::CreationContext<'_>) -> Self {
@@ -12,15 +59,313 @@ impl Game {
         // Restore app state using cc.storage (requires the "persistence" feature).
         // Use the cc.gl (a glow::Context) to create graphics shaders and buffers that you can use
         // for e.g. egui::PaintCallback.
-        Self::default()
+        Game {
+            setup: Setup { mode: GameMode::Default, opponent: Opponent::Human, side: Side::White, difficulty: Difficulty::default() },
+            state: None,
+            computer: None,
+            thinking: None,
+            selected: None,
+            flipped: false,
+            threads: ThreadPool::new(2).expect("Error creating threads"),
+            analysing: false,
+            analysis_lines: DEFAULT_ANALYSIS_LINES,
+            analysis: None,
+            viewing: None,
+        }
+    }
+
+    fn start(&mut self) {
+        let mut state = GameState::new();
+        state.set_mode(self.setup.mode);
+        // the window shows the board, nothing needs printing
+        state.render.silent = true;
+        get_legal_move_list(&mut state);
+        state.clock = std::time::Instant::now();
+
+        let human = match self.setup.side {
+            Side::White => White,
+            Side::Black => Black,
+            Side::Random => random_colour(),
+        };
+        self.computer = (self.setup.opponent == Opponent::Computer).then(|| Computer::against(human, self.setup.difficulty));
+        self.flipped = self.computer.is_some() && human == Black;
+        self.selected = None;
+        self.viewing = None;
+        self.analysis = None;
+        self.state = Some(state);
+    }
+
+    fn new_game(&mut self) {
+        if let Some(thinking) = self.thinking.take() {
+            thinking.cancel();
+        }
+        self.analysis = None;
+        self.state = None;
+    }
+
+    fn shown(&self) -> Option<&GameState> {
+        self.viewing.as_ref().map(|(_, position)| position).or(self.state.as_ref())
+    }
+
+    // back and forth through the moves played so far, the last one goes back to the live board
+    fn step_to(&mut self, ply: usize) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        self.selected = None;
+        self.viewing = match ply.min(state.move_history.len()) {
+            ply if ply == state.move_history.len() => None,
+            ply => position_at(state, ply).map(|position| (ply, position)),
+        };
+    }
+
+    // a fresh search whenever the board being shown or the number of lines changes, the old one stops when dropped
+    fn update_analysis(&mut self) {
+        let Some(shown) = self.shown().filter(|_| self.analysing) else {
+            self.analysis = None;
+            return;
+        };
+        let current = self.analysis.as_ref().is_some_and(|analysis| {
+            analysis.position.board == shown.board && analysis.position.player_turn == shown.player_turn && analysis.position.move_history.len() == shown.move_history.len() && analysis.lines == self.analysis_lines
+        });
+        if !current {
+            self.analysis = Some(Analysis::start(&self.threads, shown, self.analysis_lines));
+        }
+    }
+
+    fn setup_screen(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading("New game");
+            ui.horizontal(|ui| {
+                ui.label("Mode");
+                for (mode, name) in [(GameMode::Default, "Default"), (GameMode::Blitz, "Blitz"), (GameMode::Rapid, "Rapid"), (GameMode::Classical, "Classical")] {
+                    ui.radio_value(&mut self.setup.mode, mode, name);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Opponent");
+                ui.radio_value(&mut self.setup.opponent, Opponent::Human, "Two players");
+                ui.radio_value(&mut self.setup.opponent, Opponent::Computer, "Vs engine");
+            });
+            ui.add_enabled_ui(self.setup.opponent == Opponent::Computer, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Play as");
+                    ui.radio_value(&mut self.setup.side, Side::White, "White");
+                    ui.radio_value(&mut self.setup.side, Side::Black, "Black");
+                    ui.radio_value(&mut self.setup.side, Side::Random, "Random");
+                });
+                egui::ComboBox::from_label("Difficulty")
+                    .selected_text(format!("{:?}", self.setup.difficulty))
+                    .show_ui(ui, |ui| {
+                        for difficulty in DIFFICULTIES {
+                            ui.selectable_value(&mut self.setup.difficulty, difficulty, format!("{:?}", difficulty));
+                        }
+                        if ui.selectable_label(matches!(self.setup.difficulty, Difficulty::Elo(_)), "Elo").clicked() {
+                            self.setup.difficulty = Difficulty::Elo(self.setup.difficulty.elo().unwrap_or(1500));
+                        }
+                    });
+                if let Difficulty::Elo(elo) = &mut self.setup.difficulty {
+                    ui.add(egui::Slider::new(elo, MIN_ELO..=MAX_ELO).step_by(50.0).text("Elo"));
+                }
+            });
+            if ui.button("Start").clicked() {
+                self.start();
+            }
+        });
+    }
+
+    // the engine searches on the pool so the window keeps drawing, its move goes in like a click would
+    fn computer_turn(&mut self) {
+        let (Some(state), Some(computer)) = (&mut self.state, self.computer) else {
+            return;
+        };
+        match self.thinking.as_mut().map(TaskHandle::is_finished) {
+            Some(true) => {
+                if let Some(Ok(Some(event))) = self.thinking.take().map(TaskHandle::join) {
+                    state.handle_mut(event);
+                }
+            },
+            None if computer.to_move(state) => {
+                let position = state.clone();
+                self.thinking = Some(self.threads.spawn(move |token| computer.choose_move(&position, token)));
+            },
+            Some(false) | None => {},
+        }
+    }
+
+    fn square_at(&self, board: egui::Rect, position: egui::Pos2) -> Option<usize> {
+        let size = board.width() / 8.0;
+        let column = ((position.x - board.min.x) / size).floor() as i32;
+        let row = ((position.y - board.min.y) / size).floor() as i32;
+        if !(0..8).contains(&column) || !(0..8).contains(&row) {
+            return None;
+        }
+        let (x, y) = if self.flipped { (7 - column, row) } else { (column, 7 - row) };
+        Some((y * 8 + x) as usize)
+    }
+
+    // first click picks a piece, the second moves it, promotions are always to a queen
+    fn click(&mut self, square: usize) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+        if self.viewing.is_some() || state.game_over || self.computer.is_some_and(|computer| computer.to_move(state)) {
+            return;
+        }
+        let colour = if state.player_turn == 1 { White } else { Black };
+        let own_piece = state.board.0[square] != EMPTY && state.board.1[square] == colour;
+        match self.selected {
+            Some(origin) if origin == square => self.selected = None,
+            _ if own_piece => self.selected = Some(square),
+            None => {},
+            Some(origin) => {
+                self.selected = None;
+                let translation = (Coordinates::from(origin), Coordinates::from(square));
+                state.handle_mut(Event::Move { translation, promotion: queen_promotion(&state.board, translation) });
+            },
+        }
+    }
+
+    fn draw_board(&self, ui: &mut egui::Ui, state: &GameState) -> egui::Response {
+        let side = ui.available_size().min_elem();
+        let (response, painter) = ui.allocate_painter(egui::vec2(side, side), egui::Sense::click());
+        let board = response.rect;
+        let size = side / 8.0;
+        let last_move = state.move_history.last().map(|record| [usize::from(record.translation.0), usize::from(record.translation.1)]);
+        let in_check = if state.white_in_check { Some(White) } else if state.black_in_check { Some(Black) } else { None };
+
+        for index in 0..64 {
+            let (x, y) = (index % 8, index / 8);
+            let (column, row) = if self.flipped { (7 - x, y) } else { (x, 7 - y) };
+            let rect = egui::Rect::from_min_size(board.min + egui::vec2(column as f32 * size, row as f32 * size), egui::vec2(size, size));
+            let piece = state.board.0[index];
+            let colour = state.board.1[index];
+            let fill = if self.selected == Some(index) {
+                SELECTED
+            } else if piece == KING && in_check == Some(colour) {
+                CHECK
+            } else if last_move.is_some_and(|squares| squares.contains(&index)) {
+                LAST_MOVE
+            } else if (x + y) % 2 == 1 {
+                LIGHT_SQUARE
+            } else {
+                DARK_SQUARE
+            };
+            painter.rect_filled(rect, 0.0, fill);
+            if piece != EMPTY {
+                // letters in discs, the default fonts dont all have the chess glyphs
+                let (disc, letter) = if colour == White { (egui::Color32::WHITE, egui::Color32::BLACK) } else { (egui::Color32::from_gray(30), egui::Color32::WHITE) };
+                painter.circle(rect.center(), size * 0.38, disc, egui::Stroke::new(1.5, egui::Color32::from_gray(60)));
+                let text = piece_to_char(piece, White).to_string();
+                painter.text(rect.center(), egui::Align2::CENTER_CENTER, text, egui::FontId::proportional(size * 0.45), letter);
+            }
+        }
+        response
+    }
+
+    fn side_panel(&mut self, ctx: &egui::Context) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let clocks = Clocks::live(state);
+        let mut resign = None;
+        let mut new_game = false;
+        let plies = state.move_history.len();
+        let ply = self.viewing.as_ref().map_or(plies, |(ply, _)| *ply);
+        let mut step = None;
+        egui::SidePanel::right("game").min_width(180.0).show(ctx, |ui| {
+            ui.heading(format!("Black {}", format_clock(clocks.black_ms)));
+            ui.heading(format!("White {}", format_clock(clocks.white_ms)));
+            if let Some(computer) = self.computer {
+                ui.label(format!("Engine: {:?}, playing {:?}", computer.difficulty, computer.colour));
+            }
+            match (state.game_over, state.result) {
+                (true, Some(result)) => ui.label(format!("Game over: {:?}", result)),
+                (true, None) => ui.label("Game over"),
+                _ if self.thinking.is_some() => ui.label("Engine is thinking"),
+                _ => ui.label(if state.player_turn == 1 { "White to move" } else { "Black to move" }),
+            };
+            ui.separator();
+            egui::ScrollArea::vertical().max_height(300.0).stick_to_bottom(true).show(ui, |ui| {
+                for (number, pair) in state.san_history.chunks(2).enumerate() {
+                    ui.monospace(format!("{:>3}. {}", number + 1, pair.join(" ")));
+                }
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.add_enabled(!state.game_over, egui::Button::new("Resign")).clicked() {
+                    // against the engine only the human resigns, whoevers turn it is
+                    resign = Some(match self.computer {
+                        Some(computer) => if computer.colour == White { 2 } else { 1 },
+                        None => state.player_turn,
+                    });
+                }
+                if ui.button("Flip").clicked() {
+                    self.flipped = !self.flipped;
+                }
+                new_game = ui.button("New game").clicked();
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.analysing, "Analysis");
+                for (label, target) in [("|<", 0), ("<", ply.saturating_sub(1)), (">", ply + 1), (">|", plies)] {
+                    if ui.add_enabled(target != ply && target <= plies, egui::Button::new(label)).clicked() {
+                        step = Some(target);
+                    }
+                }
+            });
+            if self.analysing {
+                ui.add(egui::Slider::new(&mut self.analysis_lines, 1..=MAX_ANALYSIS_LINES).text("Lines"));
+            }
+            if ply < plies {
+                ui.label(format!("Looking at move {} of {}", ply, plies));
+            }
+            if let (true, Some(analysis)) = (self.analysing, &mut self.analysis) {
+                match analysis.latest() {
+                    Some(update) => ui.label(format!("Depth {}, {} nodes", update.depth, update.nodes)),
+                    None => ui.label("Analysing"),
+                };
+                for line in analysis.describe() {
+                    ui.add(egui::Label::new(egui::RichText::new(line.as_str()).monospace()).wrap());
+                }
+            }
+        });
+        if let Some(ply) = step {
+            self.step_to(ply);
+        }
+        if let (Some(player), Some(state)) = (resign, &mut self.state) {
+            state.handle_mut(Event::Surrender(Some(player)));
+        }
+        if new_game {
+            self.new_game();
+        }
     }
 }
 
 impl App for Game {
     fn update(&mut self, ctx: &eframe::egui::Context, frame: &mut eframe::Frame) {
+        if self.state.is_none() {
+            self.setup_screen(ctx);
+            return;
+        }
+        self.computer_turn();
+        self.side_panel(ctx);
+        self.update_analysis();
+
+        let mut clicked = None;
         egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Hello World!");
+            if let Some(state) = self.shown() {
+                let response = self.draw_board(ui, state);
+                if response.clicked() {
+                    clicked = response.interact_pointer_pos().and_then(|position| self.square_at(response.rect, position));
+                }
+            }
         });
+        if let Some(square) = clicked {
+            self.click(square);
+        }
+        // clocks keep running and the engine's move lands without any input
+        ctx.request_repaint_after(Duration::from_millis(100));
     }
 
 }
@@ -31,4 +376,3 @@ fn main() {
     // run();
     ()
 }
-
@@ -0,0 +1,386 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use crate::PieceColour::*;
+use crate::{get_legal_move_list, Event, GameEnd, EventSender, GameState, Handler, PieceColour, Reply};
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+// one JSON object per line, the host sends Hello as soon as someone joins
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NetMessage {
+  Hello { game: Box<GameState> },
+  Move { origin: u8, destination: u8, promotion: Option<u8>, clocks: Clocks },
+  Drop { piece: u8, square: u8, clocks: Clocks },
+  Resign,
+  DrawOffer,
+  DrawAccept,
+  Chat { text: String },
+}
+
+// both clocks as the mover saw them right after their move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clocks {
+  pub white_ms: u64,
+  pub black_ms: u64,
+}
+
+impl Clocks {
+  pub fn of(state: &GameState) -> Self {
+    Clocks { white_ms: state.white_timer.as_millis() as u64, black_ms: state.black_timer.as_millis() as u64 }
+  }
+
+  // counts the time the player to move has used so far
+  pub fn live(state: &GameState) -> Self {
+    let mut clocks = Clocks::of(state);
+    if !state.game_over {
+      let elapsed = state.clock.elapsed().as_millis() as u64;
+      match state.player_turn {
+        1 => clocks.white_ms = clocks.white_ms.saturating_sub(elapsed),
+        _ => clocks.black_ms = clocks.black_ms.saturating_sub(elapsed),
+      }
+    }
+    clocks
+  }
+
+  pub fn apply(&self, state: &mut GameState) {
+    state.white_timer = Duration::from_millis(self.white_ms);
+    state.black_timer = Duration::from_millis(self.black_ms);
+    state.clock = Instant::now();
+  }
+}
+
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> io::Result<()> {
+  let mut line = serde_json::to_string(message)?;
+  line.push('\n');
+  writer.write_all(line.as_bytes())
+}
+
+pub struct Connection {
+  writer: TcpStream,
+  reader: BufReader<TcpStream>,
+}
+
+impl Connection {
+  pub fn new(stream: TcpStream) -> io::Result<Self> {
+    Ok(Connection { reader: BufReader::new(stream.try_clone()?), writer: stream })
+  }
+
+  pub fn send(&mut self, message: &NetMessage) -> io::Result<()> {
+    write_message(&mut self.writer, message)
+  }
+
+  // None once the other side hangs up
+  pub fn receive(&mut self) -> io::Result<Option<NetMessage>> {
+    let mut line = String::new();
+    if self.reader.read_line(&mut line)? == 0 {
+      return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+  }
+
+  pub fn split(self) -> (TcpStream, BufReader<TcpStream>) {
+    (self.writer, self.reader)
+  }
+}
+
+// waits for one opponent and sends them the game, the host plays white
+pub fn host_game(listener: &TcpListener, game: &GameState) -> io::Result<Connection> {
+  let (stream, address) = listener.accept()?;
+  println!("{} joined", address);
+  let mut connection = Connection::new(stream)?;
+  connection.send(&NetMessage::Hello { game: Box::new(game.clone()) })?;
+  Ok(connection)
+}
+
+pub fn join_game(address: &str) -> io::Result<(Connection, GameState)> {
+  let mut connection = Connection::new(TcpStream::connect(address)?)?;
+  match connection.receive()? {
+    Some(NetMessage::Hello { game }) => {
+      let mut game = *game;
+      game.clock = Instant::now();
+      get_legal_move_list(&mut game);
+      Ok((connection, game))
+    },
+    _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Expected a game from the host")),
+  }
+}
+
+// runs until the connection closes, every message becomes a RemoteInput event
+pub fn forward_messages(mut reader: BufReader<TcpStream>, sender: EventSender) {
+  loop {
+    let mut line = String::new();
+    let message = match reader.read_line(&mut line) {
+      Ok(0) | Err(_) => None,
+      Ok(_) => match serde_json::from_str::<NetMessage>(&line) {
+        Ok(message) => Some(message),
+        Err(e) => {
+          println!("Bad message from opponent: {}", e);
+          continue;
+        },
+      },
+    };
+    let disconnected = message.is_none();
+    if sender.send(Event::RemoteInput(message)).is_err() || disconnected {
+      break;
+    }
+  }
+}
+
+fn turn_of(colour: PieceColour) -> u8 {
+  if colour == White { 1 } else { 2 }
+}
+
+// sits between the local player and the game, passes local moves on to the opponent and plays theirs
+pub struct NetworkPeer {
+  game: Arc<Mutex<GameState>>,
+  writer: TcpStream,
+  colour: PieceColour,
+  draw_offered_by: Option<PieceColour>,
+}
+
+impl NetworkPeer {
+  pub fn new(game: Arc<Mutex<GameState>>, writer: TcpStream, colour: PieceColour) -> Self {
+    NetworkPeer { game, writer, colour, draw_offered_by: None }
+  }
+
+  fn opponent(&self) -> PieceColour {
+    if self.colour == White { Black } else { White }
+  }
+
+  fn send(&mut self, message: &NetMessage) -> io::Result<()> {
+    write_message(&mut self.writer, message)
+  }
+
+  // also wakes up the forward_messages thread so the program can exit
+  pub fn shutdown(&self) {
+    let _ = self.writer.shutdown(Shutdown::Both);
+  }
+
+  fn connection_lost(&mut self, e: io::Error) {
+    println!("Connection lost: {}", e);
+    self.end_game(None);
+  }
+
+  // spectators and the event log hear about every ending, a game thats already over stays as it was
+  fn end_game(&self, result: Option<GameEnd>) {
+    let mut game = self.game.lock().unwrap();
+    if game.game_over {
+      return;
+    }
+    game.game_over = true;
+    game.result = result;
+    game.publish_game_end();
+  }
+
+  // the reply says why a move wasnt sent
+  fn local_move(&mut self, event: Event) -> io::Result<Option<Reply>> {
+    let mut game = self.game.lock().unwrap();
+    if game.player_turn != turn_of(self.colour) {
+      return Ok(Some(Reply::Rejected("Waiting for the opponent's move".to_owned())));
+    }
+    let moves_played = game.move_history.len();
+    if let Some(rejection) = game.respond(event) {
+      return Ok(Some(rejection));
+    }
+    let Some(record) = game.move_history.get(moves_played).copied() else {
+      return Ok(None);
+    };
+    let clocks = Clocks::of(&game);
+    drop(game);
+
+    self.draw_offered_by = None;
+    let message = match record.drop {
+      Some(piece) => NetMessage::Drop { piece, square: usize::from(record.translation.1) as u8, clocks },
+      None => NetMessage::Move {
+        origin: usize::from(record.translation.0) as u8,
+        destination: usize::from(record.translation.1) as u8,
+        promotion: record.promotion,
+        clocks,
+      },
+    };
+    self.send(&message).map(|_| None)
+  }
+
+  fn remote_move(&mut self, event: Option<Event>, clocks: Clocks) {
+    let Some(event) = event else {
+      println!("Opponent sent an illegal move");
+      return;
+    };
+    let mut game = self.game.lock().unwrap();
+    if game.player_turn != turn_of(self.opponent()) {
+      println!("Opponent moved out of turn");
+      return;
+    }
+    let moves_played = game.move_history.len();
+    game.handle_mut(event);
+    if game.move_history.len() == moves_played {
+      println!("Opponent sent an illegal move");
+      return;
+    }
+    // their clock is the one that was running
+    clocks.apply(&mut game);
+    self.draw_offered_by = None;
+  }
+
+  fn remote_input(&mut self, message: Option<NetMessage>) {
+    let Some(message) = message else {
+      println!("Opponent disconnected");
+      self.end_game(None);
+      return;
+    };
+
+    let opponent_is_white = self.opponent() == White;
+    match message {
+      NetMessage::Move { origin, destination, promotion, clocks } => {
+        self.remote_move(Event::move_from_indices(origin, destination, promotion), clocks);
+      },
+      NetMessage::Drop { piece, square, clocks } => self.remote_move(Event::drop_from_indices(piece, square), clocks),
+      NetMessage::Chat { text } => println!("{:?}: {}", self.opponent(), text),
+      NetMessage::Resign => {
+        println!("{:?} Resigns", self.opponent());
+        self.end_game(Some(GameEnd::Resignation(opponent_is_white)));
+      },
+      NetMessage::DrawOffer | NetMessage::DrawAccept if self.draw_offered_by == Some(self.colour) => self.agree_draw(),
+      NetMessage::DrawOffer => {
+        println!("Opponent offers a draw, type draw to accept");
+        self.draw_offered_by = Some(self.opponent());
+      },
+      NetMessage::DrawAccept | NetMessage::Hello { .. } => {},
+    }
+  }
+
+  fn offer_draw(&mut self) -> io::Result<()> {
+    if self.draw_offered_by == Some(self.opponent()) {
+      self.send(&NetMessage::DrawAccept)?;
+      self.agree_draw();
+      return Ok(());
+    }
+    println!("Draw offered");
+    self.draw_offered_by = Some(self.colour);
+    self.send(&NetMessage::DrawOffer)
+  }
+
+  fn agree_draw(&mut self) {
+    println!("Draw by agreement");
+    self.end_game(Some(GameEnd::DrawByAgreement));
+  }
+
+  fn resign(&mut self) -> io::Result<()> {
+    println!("{:?} Resigns", self.colour);
+    self.end_game(Some(GameEnd::Resignation(self.colour == White)));
+    self.send(&NetMessage::Resign)
+  }
+}
+
+impl Handler for NetworkPeer {
+  fn handle_mut(&mut self, event: Event) {
+    let result = match event {
+      Event::Move { .. } | Event::Drop(_) => self.local_move(event).map(|_| ()),
+      Event::RemoteInput(message) => {
+        self.remote_input(message);
+        Ok(())
+      },
+      Event::DrawOffer => self.offer_draw(),
+      Event::Surrender(_) => self.resign(),
+      Event::Chat { text, .. } => self.send(&NetMessage::Chat { text }),
+      Event::UserInput | Event::MoveMade { .. } | Event::ClockTick { .. } | Event::GameEnded(_) => Ok(()),
+    };
+    if let Err(e) = result {
+      self.connection_lost(e);
+    }
+  }
+
+  fn respond(&mut self, event: Event) -> Option<Reply> {
+    if !matches!(event, Event::Move { .. } | Event::Drop(_)) {
+      self.handle_mut(event);
+      return None;
+    }
+    self.local_move(event).unwrap_or_else(|e| {
+      self.connection_lost(e);
+      None
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Dispatcher, EventKind, ThreadPool};
+
+  // what a spectator would be told about the end of the game
+  #[derive(Default)]
+  struct Endings(Vec<Option<GameEnd>>);
+
+  impl Handler for Endings {
+    fn handle_mut(&mut self, event: Event) {
+      if let Event::GameEnded(result) = event {
+        self.0.push(result);
+      }
+    }
+  }
+
+  #[test]
+  fn loopback_game() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let joiner = std::thread::spawn(move || join_game(&address).unwrap());
+
+    let mut host_state = GameState::new();
+    host_state.blitz_mode();
+    get_legal_move_list(&mut host_state);
+    let (host_writer, _) = host_game(&listener, &host_state).unwrap().split();
+    let (join_connection, join_state) = joiner.join().unwrap();
+    assert_eq!(join_state.time_control, host_state.time_control);
+
+    let host_game_state = Arc::new(Mutex::new(host_state));
+    let join_game_state = Arc::new(Mutex::new(join_state));
+    let mut host = NetworkPeer::new(host_game_state.clone(), host_writer, White);
+    let (join_writer, join_reader) = join_connection.split();
+    let join_peer = Arc::new(Mutex::new(NetworkPeer::new(join_game_state.clone(), join_writer, Black)));
+
+    let pool = ThreadPool::new(2).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+    dispatcher.register_handler(EventKind::RemoteInput, join_peer.clone());
+    let endings = Arc::new(Mutex::new(Endings::default()));
+    dispatcher.register_handler(EventKind::GameEnded, endings.clone());
+    join_game_state.lock().unwrap().publisher = Some(dispatcher.sender());
+    dispatcher.start();
+    let sender = dispatcher.sender();
+    pool.execute(move || forward_messages(join_reader, sender));
+
+    // e2e4 from the host shows up on the joining side with the host's clocks
+    host.handle_mut(Event::move_from_indices(12, 28, None).unwrap());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while join_game_state.lock().unwrap().move_history.is_empty() && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    let joined = join_game_state.lock().unwrap().clone();
+    let hosted = host_game_state.lock().unwrap().clone();
+    assert_eq!(joined.board, hosted.board);
+    assert_eq!(joined.player_turn, 2);
+    assert_eq!(Clocks::of(&joined).white_ms, Clocks::of(&hosted).white_ms);
+
+    // moving for the other side is refused locally
+    host.handle_mut(Event::move_from_indices(52, 36, None).unwrap());
+    assert_eq!(host_game_state.lock().unwrap().move_history.len(), 1);
+
+    host.handle_mut(Event::Surrender(None));
+    while !join_game_state.lock().unwrap().game_over && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert!(join_game_state.lock().unwrap().game_over);
+    while endings.lock().unwrap().0.is_empty() && Instant::now() < deadline {
+      std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(endings.lock().unwrap().0, vec![Some(GameEnd::Resignation(true))]);
+
+    host.shutdown();
+    join_peer.lock().unwrap().shutdown();
+    drop(dispatcher);
+  }
+}
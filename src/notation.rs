@@ -0,0 +1,238 @@
+use crate::PieceColour::*;
+use crate::{char_to_piece, is_square_occupied, piece_to_char, Coordinates, DropMove, GameEnd, GameState, King, Move, Variant};
+use crate::{STARTING_FEN, PAWN, ROOK, KNIGHT, BISHOP, QUEEN};
+
+pub const PROMOTION_PIECES: [u8; 4] = [QUEEN, ROOK, BISHOP, KNIGHT];
+
+pub fn piece_letter(piece: u8) -> char {
+  piece_to_char(piece, White)
+}
+
+fn square_name(square: Coordinates) -> String {
+  square.to_algebraic()
+}
+
+// standard algebraic notation for a legal move in the position before it is played
+// check and mate marks are added by finish_turn once the move is on the board
+pub fn move_to_san(state: &GameState, translation: Move, promotion: Option<u8>) -> String {
+  let board = &state.board;
+  if King::is_move_a_castle(translation, board) {
+    return if translation.1.x > translation.0.x { "O-O".to_owned() } else { "O-O-O".to_owned() };
+  }
+  let (origin, destination) = translation;
+  let piece = board.0[usize::from(origin)];
+  let (occupied, same_colour) = is_square_occupied(translation, board);
+  // pawns changing file onto an empty square are taking en passant
+  let capture = (occupied && !same_colour) || (piece == PAWN && origin.x != destination.x);
+
+  let mut san = String::new();
+  if piece == PAWN {
+    if capture {
+      san.push((b'a' + origin.x as u8) as char);
+    }
+  } else {
+    san.push(piece_letter(piece));
+    let legal_moves = if board.1[usize::from(origin)] == White { &state.move_list.white } else { &state.move_list.black };
+    let rivals = legal_moves.iter()
+      .filter(|(other, to)| *to == destination && *other != origin && board.0[usize::from(*other)] == piece)
+      .map(|(other, _)| *other)
+      .collect::<Vec<_>>();
+    if !rivals.is_empty() {
+      let file = (b'a' + origin.x as u8) as char;
+      let rank = char::from_digit(origin.y as u32 + 1, 10).unwrap_or('?');
+      if rivals.iter().all(|other| other.x != origin.x) {
+        san.push(file);
+      } else if rivals.iter().all(|other| other.y != origin.y) {
+        san.push(rank);
+      } else {
+        san.push(file);
+        san.push(rank);
+      }
+    }
+  }
+  if capture {
+    san.push('x');
+  }
+  san.push_str(&square_name(destination));
+  if let Some(promotion) = promotion {
+    san.push('=');
+    san.push(piece_letter(promotion));
+  }
+  san
+}
+
+pub fn drop_to_san(drop: DropMove) -> String {
+  format!("{}@{}", piece_letter(drop.piece), square_name(drop.square))
+}
+
+pub fn move_to_long_algebraic(translation: Move, promotion: Option<u8>) -> String {
+  let mut text = format!("{}{}", square_name(translation.0), square_name(translation.1));
+  if let Some(promotion) = promotion {
+    text.push(piece_to_char(promotion, Black));
+  }
+  text
+}
+
+// e2e4, e2-e4, e2 e4 and e7e8q
+pub fn parse_long_algebraic(input: &str) -> Option<(Move, Option<u8>)> {
+  let text = input.trim().to_ascii_lowercase().replace(['-', ' '], "");
+  if !(4..=5).contains(&text.len()) || !text.is_ascii() {
+    return None;
+  }
+  let origin = Coordinates::from_algebraic(&text[0..2])?;
+  let destination = Coordinates::from_algebraic(&text[2..4])?;
+  let promotion = match text[4..].chars().next() {
+    Some(letter) => Some(char_to_piece(letter).map(|(piece, _)| piece).filter(|piece| PROMOTION_PIECES.contains(piece))?),
+    None => None,
+  };
+  Some(((origin, destination), promotion))
+}
+
+fn is_promotion(state: &GameState, translation: Move) -> bool {
+  state.board.0[usize::from(translation.0)] == PAWN && (translation.1.y == 0 || translation.1.y == 7)
+}
+
+// matched against every legal move, so it doesnt mind case, missing check marks or 0-0
+pub fn parse_san(state: &GameState, input: &str) -> Result<(Move, Option<u8>), String> {
+  let normalise = |text: &str| text.trim().replace(['+', '#', '!', '?'], "").replace('0', "O").to_ascii_lowercase();
+  let wanted = normalise(input);
+  let legal_moves = if state.player_turn == 1 { &state.move_list.white } else { &state.move_list.black };
+  let mut matches = Vec::new();
+  for translation in legal_moves {
+    let promotions = if is_promotion(state, *translation) { PROMOTION_PIECES.map(Some).to_vec() } else { vec![None] };
+    for promotion in promotions {
+      if normalise(&move_to_san(state, *translation, promotion)) == wanted {
+        matches.push((*translation, promotion));
+      }
+    }
+  }
+  match matches.len() {
+    1 => Ok(matches[0]),
+    0 => Err(format!("Illegal move: {}", input.trim())),
+    // lowercase input cant tell a bishop from the b pawn
+    _ => Err(format!("Ambiguous move: {}, try the squares like b2c3", input.trim())),
+  }
+}
+
+pub fn pgn_result(result: Option<GameEnd>) -> &'static str {
+  match result {
+    None => "*",
+    Some(result) => match result.white_lost() {
+      Some(true) => "0-1",
+      Some(false) => "1-0",
+      None => "1/2-1/2",
+    },
+  }
+}
+
+fn variant_name(variant: Variant) -> &'static str {
+  match variant {
+    Variant::Standard => "Standard",
+    Variant::KingOfTheHill => "King of the Hill",
+    Variant::ThreeCheck => "Three-check",
+    Variant::RacingKings => "Racing Kings",
+    Variant::Crazyhouse => "Crazyhouse",
+    Variant::Atomic => "Atomic",
+    Variant::Antichess => "Antichess",
+    Variant::Horde => "Horde",
+  }
+}
+
+// games loaded from saves older than the san history fall back to e2e4 style moves
+pub fn to_pgn(state: &GameState) -> String {
+  let result = pgn_result(state.result);
+  let mut pgn = String::new();
+  let mut header = |name: &str, value: &str| pgn.push_str(&format!("[{} \"{}\"]\n", name, value));
+  header("Event", "Casual game");
+  header("Site", "cheess");
+  header("White", "White");
+  header("Black", "Black");
+  header("Result", result);
+  if state.variant != Variant::Standard {
+    header("Variant", variant_name(state.variant));
+  }
+  header("TimeControl", &state.time_control.to_string());
+  if let Some(fen) = state.start_fen.as_deref().filter(|fen| *fen != STARTING_FEN) {
+    header("SetUp", "1");
+    header("FEN", fen);
+  }
+  pgn.push('\n');
+
+  let moves = if state.san_history.len() == state.move_history.len() {
+    state.san_history.clone()
+  } else {
+    state.move_history.iter().map(|record| match record.drop {
+      Some(piece) => drop_to_san(DropMove { piece, square: record.translation.1 }),
+      None => move_to_long_algebraic(record.translation, record.promotion),
+    }).collect()
+  };
+  // a game set up with black to move starts on a black half move
+  let black_first = state.start_fen.as_deref().is_some_and(|fen| fen.split_whitespace().nth(1) == Some("b"));
+  let first_move_number = state.start_fen.as_deref()
+    .and_then(|fen| fen.split_whitespace().nth(5)?.parse::<usize>().ok())
+    .unwrap_or(1);
+
+  let mut tokens = Vec::new();
+  for (index, san) in moves.iter().enumerate() {
+    let half_move = index + usize::from(black_first);
+    let number = first_move_number + half_move / 2;
+    if half_move % 2 == 0 {
+      tokens.push(format!("{}.", number));
+    } else if index == 0 {
+      tokens.push(format!("{}...", number));
+    }
+    tokens.push(san.clone());
+  }
+  tokens.push(result.to_owned());
+
+  // movetext lines stay under 80 characters
+  let mut line = String::new();
+  for token in tokens {
+    if !line.is_empty() && line.len() + token.len() + 1 > 79 {
+      pgn.push_str(&line);
+      pgn.push('\n');
+      line.clear();
+    }
+    if !line.is_empty() {
+      line.push(' ');
+    }
+    line.push_str(&token);
+  }
+  pgn.push_str(&line);
+  pgn.push('\n');
+  pgn
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{from_fen, get_legal_move_list, take_turn};
+
+  fn play(state: &mut GameState, san: &str) {
+    let (translation, promotion) = parse_san(state, san).unwrap();
+    state.promotion_choice = promotion;
+    take_turn(state, translation);
+  }
+
+  #[test]
+  fn san_round_trip_and_pgn() {
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    for san in ["e4", "e5", "Nf3", "Nc6", "Bc4", "Nf6", "Ng5", "d5", "exd5", "Nxd5", "Nxf7", "Kxf7", "Qf3+", "Ke6", "Nc3"] {
+      play(&mut state, san);
+    }
+    assert_eq!(state.san_history[12], "Qf3+");
+    let pgn = to_pgn(&state);
+    assert!(pgn.contains("[Result \"*\"]"));
+    let movetext = pgn.split("\n\n").nth(1).unwrap().replace('\n', " ");
+    assert_eq!(movetext.trim(), "1. e4 e5 2. Nf3 Nc6 3. Bc4 Nf6 4. Ng5 d5 5. exd5 Nxd5 6. Nxf7 Kxf7 7. Qf3+ Ke6 8. Nc3 *");
+    assert!(!pgn.contains("[FEN"));
+
+    // both rooks reach d1, the file tells them apart
+    let mut state = from_fen("4k3/8/8/8/8/8/4K3/R6R w - - 0 1").unwrap();
+    assert!(parse_san(&state, "rd1").is_err());
+    play(&mut state, "Rad1");
+    assert_eq!(state.san_history, vec!["Rad1"]);
+    assert_eq!(parse_long_algebraic("e7e8q"), Some(((Coordinates::from_algebraic("e7").unwrap(), Coordinates::from_algebraic("e8").unwrap()), Some(QUEEN))));
+  }
+}
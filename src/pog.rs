@@ -60,7 +60,7 @@ let translation = (Coordinates{x:2, y:6}, Coordinates{x:2, y:7});
 
 let mut state: GameState = GameState { 
   board: (piece_board, piece_color),
-  move_list: PlayerValidMoves{ black: MoveList::new(), white: MoveList::new()},
+  move_list: PlayerValidMoves::default(),
   last_move: Some(translation),
   player_turn: 1,     //when white takes turn add 1 when black takes turn -1
   white_can_castle_queenside: true,
@@ -77,10 +77,26 @@ let mut state: GameState = GameState {
   black_in_check: false,
   white_pieces: PieceSet::new(),
   black_pieces: PieceSet::new(),
+  white_pocket: PieceSet::empty(),
+  black_pocket: PieceSet::empty(),
+  promoted_squares: 0,
+  promotion_choice: None,
+  san_history: Vec::new(),
+  start_fen: None,
+  publisher: None,
+  render: RenderOptions::default(),
   clock: std::time::Instant::now(),
-  timer_increment: Duration::from_secs(30),
+  time_control: TimeControl::default(),
   mode: GameMode::Default,
   game_over: false,
+  correspondence: None,
+  move_history: Vec::new(),
+  castling_files: CastlingFiles::default(),
+  chess960: false,
+  variant: Variant::Standard,
+  checks_given_by_white: 0,
+  checks_given_by_black: 0,
+  result: None,
 };
 
 let translation = (Coordinates {x: 1, y: 0}, Coordinates { x: 2, y: 2});
@@ -88,7 +104,7 @@ get_legal_move_list(&mut state);
 take_turn(&mut state, translation);
 
 // let test = ();
-println!("{:?}", state.board);
+println!("{}", render_game(&state, &state.render));
 // for translation in state.move_list.white {
 //   let index = (usize::from(translation.0), usize::from(translation.1));
 //   println!("{:?}", index);
@@ -0,0 +1,197 @@
+use crate::PieceColour::*;
+use crate::{piece_to_char, Clocks, GameState, PieceColour};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PieceStyle {
+  #[default]
+  Unicode,
+  // for terminals without the chess glyphs
+  Ascii,
+}
+
+// xterm 256 colour numbers, backgrounds for the squares and foregrounds for the pieces
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+  pub light: u8,
+  pub dark: u8,
+  pub last_move: u8,
+  pub check: u8,
+  pub cursor: u8,
+  pub selected: u8,
+  pub white: u8,
+  pub black: u8,
+}
+
+impl Theme {
+  pub const CLASSIC: Theme = Theme { light: 180, dark: 137, last_move: 143, check: 160, cursor: 74, selected: 107, white: 231, black: 16 };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RenderOptions {
+  pub pieces: PieceStyle,
+  // black at the bottom
+  pub flipped: bool,
+  // plain text when None, the last move and check are marked with brackets instead
+  pub theme: Option<Theme>,
+  // square picking with the arrow keys in the tui
+  pub cursor: Option<usize>,
+  pub selected: Option<usize>,
+  // nothing printed after each move, for front ends that draw the board themselves
+  pub silent: bool,
+}
+
+const RESET: &str = "\x1b[0m";
+
+// with a theme the colour tells the sides apart, so both use the solid glyphs
+fn piece_glyph(piece: u8, colour: PieceColour, options: &RenderOptions) -> char {
+  if options.pieces == PieceStyle::Ascii {
+    return piece_to_char(piece, colour);
+  }
+  let solid = colour == Black || options.theme.is_some();
+  match (piece, solid) {
+    (KING, false) => '♔',
+    (QUEEN, false) => '♕',
+    (ROOK, false) => '♖',
+    (BISHOP, false) => '♗',
+    (KNIGHT, false) => '♘',
+    (PAWN, false) => '♙',
+    (KING, true) => '♚',
+    (QUEEN, true) => '♛',
+    (ROOK, true) => '♜',
+    (BISHOP, true) => '♝',
+    (KNIGHT, true) => '♞',
+    (PAWN, true) => '♟',
+    _ => '.',
+  }
+}
+
+fn checked_king(state: &GameState) -> Option<usize> {
+  let colour = match (state.white_in_check, state.black_in_check) {
+    (true, _) => White,
+    (_, true) => Black,
+    _ => return None,
+  };
+  (0..64).find(|index| state.board.0[*index] == KING && state.board.1[*index] == colour)
+}
+
+pub fn format_clock(ms: u64) -> String {
+  let seconds = ms / 1000;
+  format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+pub fn describe_clocks(state: &GameState) -> String {
+  let clocks = Clocks::live(state);
+  let to_move = if state.player_turn == 1 { "White" } else { "Black" };
+  format!("White {}  Black {}  {} to move", format_clock(clocks.white_ms), format_clock(clocks.black_ms), to_move)
+}
+
+// ranks down the left and files along the bottom, from whichever side is at the bottom
+pub fn render_board(state: &GameState, options: &RenderOptions) -> String {
+  let ranks: Vec<usize> = if options.flipped { (0..8).collect() } else { (0..8).rev().collect() };
+  let files: Vec<usize> = if options.flipped { (0..8).rev().collect() } else { (0..8).collect() };
+  // drops leave last_move empty for en passant, the history has them
+  let last_move = state.move_history.last().map(|record| [usize::from(record.translation.0), usize::from(record.translation.1)]);
+  let check = checked_king(state);
+
+  let mut board = String::new();
+  for y in ranks {
+    board.push_str(&format!("{} ", y + 1));
+    for x in &files {
+      let index = y * 8 + x;
+      let piece = state.board.0[index];
+      let colour = state.board.1[index];
+      let moved = last_move.is_some_and(|squares| squares.contains(&index));
+      let glyph = if piece == EMPTY { ' ' } else { piece_glyph(piece, colour, options) };
+      match options.theme {
+        Some(theme) => {
+          let background = if options.cursor == Some(index) {
+            theme.cursor
+          } else if options.selected == Some(index) {
+            theme.selected
+          } else if check == Some(index) {
+            theme.check
+          } else if moved {
+            theme.last_move
+          } else if (x + y) % 2 == 1 {
+            theme.light
+          } else {
+            theme.dark
+          };
+          let foreground = if colour == White { theme.white } else { theme.black };
+          board.push_str(&format!("\x1b[48;5;{}m\x1b[38;5;{}m {} {}", background, foreground, glyph, RESET));
+        },
+        None => {
+          let glyph = if piece == EMPTY { '.' } else { glyph };
+          let (open, close) = if options.cursor == Some(index) {
+            ('>', '<')
+          } else if options.selected == Some(index) {
+            ('{', '}')
+          } else if check == Some(index) {
+            ('(', ')')
+          } else if moved {
+            ('[', ']')
+          } else {
+            (' ', ' ')
+          };
+          board.push(open);
+          board.push(glyph);
+          board.push(close);
+        },
+      }
+    }
+    board.push('\n');
+  }
+  board.push_str("  ");
+  for x in files {
+    board.push_str(&format!(" {} ", (b'a' + x as u8) as char));
+  }
+  board.trim_end().to_owned()
+}
+
+// the board with each player's clock on their side of it, and who is to move
+pub fn render_game(state: &GameState, options: &RenderOptions) -> String {
+  let clocks = Clocks::live(state);
+  let clock_line = |colour: PieceColour| {
+    let (name, ms, turn) = if colour == White { ("White", clocks.white_ms, 1) } else { ("Black", clocks.black_ms, 2) };
+    let marker = if state.player_turn == turn && !state.game_over { "  to move" } else { "" };
+    format!("{} {}{}", name, format_clock(ms), marker)
+  };
+  let (top, bottom) = if options.flipped { (White, Black) } else { (Black, White) };
+
+  let mut game = format!("{}\n{}\n{}", clock_line(top), render_board(state, options), clock_line(bottom));
+  if let Some(san) = state.san_history.last() {
+    game.push_str(&format!("\nLast move: {}", san));
+  }
+  game
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{from_fen, get_legal_move_list, take_turn, Coordinates};
+
+  #[test]
+  fn plain_board_marks_moves_and_check() {
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    take_turn(&mut state, (Coordinates { x: 4, y: 1 }, Coordinates { x: 4, y: 3 }));
+    let options = RenderOptions { pieces: PieceStyle::Ascii, ..Default::default() };
+    let board = render_board(&state, &options);
+    let lines = board.lines().map(str::trim_end).collect::<Vec<_>>();
+    assert_eq!(lines[0], "8  r  n  b  q  k  b  n  r");
+    assert_eq!(lines[4], "4  .  .  .  . [P] .  .  .");
+    assert_eq!(lines[6], "2  P  P  P  P [.] P  P  P");
+    assert_eq!(lines[8], "   a  b  c  d  e  f  g  h");
+
+    let flipped = render_board(&state, &RenderOptions { flipped: true, ..options });
+    assert!(flipped.starts_with("1  R  N  B  K  Q  B  N  R") && flipped.ends_with("h  g  f  e  d  c  b  a"));
+
+    let state = from_fen("4k3/8/8/8/8/8/8/4R1K1 b - - 0 1").unwrap();
+    assert!(render_board(&state, &options).contains("(k)"));
+    let game = render_game(&state, &RenderOptions::default());
+    // black is on the move and at the top
+    let top = game.lines().next().unwrap();
+    assert!(top.starts_with("Black 0:") && top.ends_with("to move") && game.contains('♚'));
+  }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::{get_legal_move_list, GameState};
+
+// bump when GameState changes in a way old saves cant be read with #[serde(default)]
+pub const SAVE_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+  version: u32,
+  game: GameState,
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+  Io(io::Error),
+  Format(serde_json::Error),
+  UnsupportedVersion(u32),
+}
+
+impl std::fmt::Display for SaveError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      SaveError::Io(e) => write!(f, "{}", e),
+      SaveError::Format(e) => write!(f, "Invalid save file: {}", e),
+      SaveError::UnsupportedVersion(version) => {
+        write!(f, "Save file version {} is newer than supported version {}", version, SAVE_FORMAT_VERSION)
+      },
+    }
+  }
+}
+
+impl From<io::Error> for SaveError {
+  fn from(e: io::Error) -> Self {
+    SaveError::Io(e)
+  }
+}
+
+impl From<serde_json::Error> for SaveError {
+  fn from(e: serde_json::Error) -> Self {
+    SaveError::Format(e)
+  }
+}
+
+pub fn save_game(file: &Path, state: &GameState) -> Result<(), SaveError> {
+  let mut game = state.clone();
+  // charge the player to move for the time spent so far, the clock is paused while on disk
+  if game.correspondence.is_none() {
+    match game.player_turn {
+      1 => game.white_timer = game.white_timer.saturating_sub(game.clock.elapsed()),
+      2 => game.black_timer = game.black_timer.saturating_sub(game.clock.elapsed()),
+      _ => panic!("Invalid player turn"),
+    }
+  }
+
+  let json = serde_json::to_string_pretty(&SaveFile { version: SAVE_FORMAT_VERSION, game })?;
+  if let Some(directory) = file.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+    fs::create_dir_all(directory)?;
+  }
+  // write then rename so a crash mid save doesnt lose the old file
+  let temp_file = temp_path(file);
+  fs::write(&temp_file, json)?;
+  fs::rename(&temp_file, file)?;
+  Ok(())
+}
+
+// the suffix goes on the whole name, so game.json and game.txt each get their own temp file
+fn temp_path(file: &Path) -> PathBuf {
+  let mut name = file.as_os_str().to_owned();
+  name.push(".tmp");
+  PathBuf::from(name)
+}
+
+pub fn load_game(file: &Path) -> Result<GameState, SaveError> {
+  let json = fs::read_to_string(file)?;
+
+  // read the version on its own first so newer files give a useful error instead of a parse failure
+  #[derive(Deserialize)]
+  struct Version {
+    version: u32,
+  }
+  let Version { version } = serde_json::from_str(&json)?;
+  if version > SAVE_FORMAT_VERSION {
+    return Err(SaveError::UnsupportedVersion(version));
+  }
+
+  let SaveFile { game: mut state, .. } = serde_json::from_str(&json)?;
+  get_legal_move_list(&mut state);
+  Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{take_turn, Coordinates, GameMode};
+
+  #[test]
+  fn round_trip() {
+    let file = std::env::temp_dir().join(format!("cheess_save_{}.json", std::process::id()));
+    let mut state = GameState::new();
+    state.blitz_mode();
+    get_legal_move_list(&mut state);
+    take_turn(&mut state, (Coordinates { x: 6, y: 0 }, Coordinates { x: 5, y: 2 }));
+
+    save_game(&file, &state).unwrap();
+    let loaded = load_game(&file).unwrap();
+
+    assert_eq!(loaded.board, state.board);
+    assert_eq!(loaded.move_history, state.move_history);
+    assert_eq!(loaded.player_turn, 2);
+    assert_eq!(loaded.time_control, state.time_control);
+    assert!(matches!(loaded.mode, GameMode::Blitz));
+    assert_eq!(loaded.white_can_castle_kingside, state.white_can_castle_kingside);
+    assert_eq!(loaded.black_can_castle_queenside, state.black_can_castle_queenside);
+
+    assert_eq!(temp_path(Path::new("games/game.json")), Path::new("games/game.json.tmp"));
+    assert_ne!(temp_path(Path::new("game.json")), temp_path(Path::new("game.txt")));
+
+    fs::write(&file, r#"{"version": 99, "game": {}}"#).unwrap();
+    assert!(matches!(load_game(&file), Err(SaveError::UnsupportedVersion(99))));
+
+    fs::remove_file(&file).unwrap();
+  }
+}
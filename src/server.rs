@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::PieceColour::*;
+use crate::{get_legal_move_list, to_fen, write_message, Clocks, Event, GameEnd, GameState, Handler};
+use crate::{MoveRecord, PieceColour, ThreadPool, TimeControl, Variant};
+use crate::{ROOK, KNIGHT, BISHOP, QUEEN};
+
+pub type ClientId = u64;
+pub type GameId = u64;
+
+// how often running clocks are checked for a flag fall
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// one JSON object per line from each client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+  // wait for an opponent wanting the same variant and time control
+  Seek {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    variant: Variant,
+    #[serde(default)]
+    time_control: TimeControl,
+  },
+  Move { game_id: GameId, origin: u8, destination: u8, promotion: Option<u8> },
+  Drop { game_id: GameId, piece: u8, square: u8 },
+  Resign { game_id: GameId },
+  Watch { game_id: GameId },
+  ListGames,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+  Welcome { client_id: ClientId },
+  Waiting,
+  GameStarted { game_id: GameId, colour: PieceColour, white: String, black: String, fen: String, clocks: Clocks },
+  // sent to spectators when they start watching
+  Snapshot { game_id: GameId, white: String, black: String, fen: String, clocks: Clocks, moves: Vec<MoveRecord> },
+  Moved { game_id: GameId, record: MoveRecord, fen: String, clocks: Clocks },
+  GameOver { game_id: GameId, result: Option<GameEnd> },
+  Games { games: Vec<GameSummary> },
+  Error { reason: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameSummary {
+  pub game_id: GameId,
+  pub white: String,
+  pub black: String,
+  pub variant: Variant,
+  pub moves: usize,
+  pub game_over: bool,
+}
+
+struct Client {
+  name: String,
+  // picked up by the client's writer thread
+  outbox: Sender<ServerMessage>,
+}
+
+struct Seek {
+  client: ClientId,
+  variant: Variant,
+  time_control: TimeControl,
+}
+
+pub struct ServerGame {
+  pub state: GameState,
+  pub white: ClientId,
+  pub black: ClientId,
+  pub spectators: Vec<ClientId>,
+}
+
+impl ServerGame {
+  fn colour_of(&self, client: ClientId) -> Option<PieceColour> {
+    if client == self.white {
+      Some(White)
+    } else if client == self.black {
+      Some(Black)
+    } else {
+      None
+    }
+  }
+}
+
+// true when the player to move has just run out of time, their clock is left at zero and the game is over
+pub fn flag_fall(state: &mut GameState) -> bool {
+  if state.game_over {
+    return false;
+  }
+  let white_to_move = state.player_turn == 1;
+  let timer = if white_to_move { state.white_timer } else { state.black_timer };
+  if !state.time_control.time_left(timer, state.clock.elapsed()).is_zero() {
+    return false;
+  }
+  if white_to_move {
+    state.white_timer = Duration::ZERO;
+  } else {
+    state.black_timer = Duration::ZERO;
+  }
+  state.game_over = true;
+  state.result = Some(GameEnd::TimeOut(white_to_move));
+  true
+}
+
+// everything the server knows, behind one lock
+#[derive(Default)]
+pub struct Lobby {
+  next_id: u64,
+  clients: HashMap<ClientId, Client>,
+  seeks: Vec<Seek>,
+  games: HashMap<GameId, ServerGame>,
+}
+
+impl Lobby {
+  fn next_id(&mut self) -> u64 {
+    self.next_id += 1;
+    self.next_id
+  }
+
+  // only queued, so a slow socket never holds up the lobby lock
+  fn send(&mut self, client: ClientId, message: &ServerMessage) {
+    if let Some(client) = self.clients.get(&client) {
+      let _ = client.outbox.send(message.clone());
+    }
+  }
+
+  fn error(&mut self, client: ClientId, reason: &str) {
+    self.send(client, &ServerMessage::Error { reason: reason.to_owned() });
+  }
+
+  // both players and every spectator
+  fn broadcast(&mut self, game_id: GameId, message: &ServerMessage) {
+    let Some(game) = self.games.get(&game_id) else {
+      return;
+    };
+    let mut audience = vec![game.white, game.black];
+    audience.extend(&game.spectators);
+    for client in audience {
+      self.send(client, message);
+    }
+  }
+
+  fn name_of(&self, client: ClientId) -> String {
+    self.clients.get(&client).map(|client| client.name.clone()).unwrap_or_default()
+  }
+
+  pub fn connect(&mut self, outbox: Sender<ServerMessage>) -> ClientId {
+    let id = self.next_id();
+    self.clients.insert(id, Client { name: format!("player{}", id), outbox });
+    self.send(id, &ServerMessage::Welcome { client_id: id });
+    id
+  }
+
+  // leaving mid game counts as resigning
+  pub fn disconnect(&mut self, client: ClientId) {
+    self.seeks.retain(|seek| seek.client != client);
+    let playing = self.games.iter()
+      .filter(|(_, game)| !game.state.game_over && game.colour_of(client).is_some())
+      .map(|(game_id, _)| *game_id)
+      .collect::<Vec<_>>();
+    for game_id in playing {
+      self.resign(client, game_id);
+    }
+    for game in self.games.values_mut() {
+      game.spectators.retain(|spectator| *spectator != client);
+    }
+    self.clients.remove(&client);
+  }
+
+  pub fn handle(&mut self, client: ClientId, message: ClientMessage) {
+    match message {
+      ClientMessage::Seek { name, variant, time_control } => self.seek(client, name, variant, time_control),
+      ClientMessage::Move { game_id, origin, destination, promotion } => {
+        // the server never asks stdin, so no promotion piece means a queen
+        let promotion = promotion.unwrap_or(QUEEN);
+        if ![ROOK, KNIGHT, BISHOP, QUEEN].contains(&promotion) {
+          self.error(client, "Invalid promotion piece");
+          return;
+        }
+        self.play(client, game_id, Event::move_from_indices(origin, destination, Some(promotion)));
+      },
+      ClientMessage::Drop { game_id, piece, square } => self.play(client, game_id, Event::drop_from_indices(piece, square)),
+      ClientMessage::Resign { game_id } => self.resign(client, game_id),
+      ClientMessage::Watch { game_id } => self.watch(client, game_id),
+      ClientMessage::ListGames => {
+        let mut games = self.games.iter()
+          .map(|(game_id, game)| GameSummary {
+            game_id: *game_id,
+            white: self.name_of(game.white),
+            black: self.name_of(game.black),
+            variant: game.state.variant,
+            moves: game.state.move_history.len(),
+            game_over: game.state.game_over,
+          })
+          .collect::<Vec<_>>();
+        games.sort_by_key(|game| game.game_id);
+        self.send(client, &ServerMessage::Games { games });
+      },
+    }
+  }
+
+  fn seek(&mut self, client: ClientId, name: String, variant: Variant, time_control: TimeControl) {
+    if !name.is_empty() {
+      if let Some(entry) = self.clients.get_mut(&client) {
+        entry.name = name;
+      }
+    }
+    let opponent = self.seeks.iter().position(|seek| {
+      seek.client != client && seek.variant == variant && seek.time_control == time_control
+    });
+    let Some(opponent) = opponent else {
+      self.seeks.retain(|seek| seek.client != client);
+      self.seeks.push(Seek { client, variant, time_control });
+      self.send(client, &ServerMessage::Waiting);
+      return;
+    };
+
+    // whoever waited longest gets white
+    let white = self.seeks.remove(opponent).client;
+    let black = client;
+    self.seeks.retain(|seek| seek.client != black);
+
+    let mut state = GameState::new_variant(variant);
+    state.set_time_control(time_control);
+    get_legal_move_list(&mut state);
+    state.clock = std::time::Instant::now();
+
+    let game_id = self.next_id();
+    let (white_name, black_name) = (self.name_of(white), self.name_of(black));
+    let fen = to_fen(&state);
+    let clocks = Clocks::of(&state);
+    self.games.insert(game_id, ServerGame { state, white, black, spectators: Vec::new() });
+
+    for (player, colour) in [(white, White), (black, Black)] {
+      let message = ServerMessage::GameStarted {
+        game_id,
+        colour,
+        white: white_name.clone(),
+        black: black_name.clone(),
+        fen: fen.clone(),
+        clocks,
+      };
+      self.send(player, &message);
+    }
+  }
+
+  // the game state checks the move is legal, the server only checks whose turn it is
+  // None when the client sent squares that arent on the board
+  fn play(&mut self, client: ClientId, game_id: GameId, event: Option<Event>) {
+    let Some(event) = event else {
+      self.error(client, "Invalid square");
+      return;
+    };
+    let Some(game) = self.games.get_mut(&game_id) else {
+      self.error(client, "No such game");
+      return;
+    };
+    let to_move = if game.state.player_turn == 1 { White } else { Black };
+    if game.state.game_over {
+      self.error(client, "Game is over");
+      return;
+    }
+    if game.colour_of(client) != Some(to_move) {
+      self.error(client, "Not your turn");
+      return;
+    }
+
+    let moves_played = game.state.move_history.len();
+    game.state.handle_mut(event);
+    let Some(record) = game.state.move_history.get(moves_played).copied() else {
+      self.error(client, "Illegal move");
+      return;
+    };
+    let message = ServerMessage::Moved { game_id, record, fen: to_fen(&game.state), clocks: Clocks::of(&game.state) };
+    let game_over = game.state.game_over;
+    self.broadcast(game_id, &message);
+    if game_over {
+      self.finish(game_id);
+    }
+  }
+
+  fn resign(&mut self, client: ClientId, game_id: GameId) {
+    let Some(game) = self.games.get_mut(&game_id) else {
+      self.error(client, "No such game");
+      return;
+    };
+    let Some(colour) = game.colour_of(client) else {
+      self.error(client, "Not your game");
+      return;
+    };
+    if game.state.game_over {
+      return;
+    }
+    game.state.game_over = true;
+    game.state.result = Some(GameEnd::Resignation(colour == White));
+    self.finish(game_id);
+  }
+
+  fn watch(&mut self, client: ClientId, game_id: GameId) {
+    let Some(game) = self.games.get_mut(&game_id) else {
+      self.error(client, "No such game");
+      return;
+    };
+    if !game.spectators.contains(&client) {
+      game.spectators.push(client);
+    }
+    let (white, black) = (game.white, game.black);
+    let fen = to_fen(&game.state);
+    let clocks = Clocks::of(&game.state);
+    let moves = game.state.move_history.clone();
+    let message = ServerMessage::Snapshot { game_id, white: self.name_of(white), black: self.name_of(black), fen, clocks, moves };
+    self.send(client, &message);
+  }
+
+  // once everyone has been told the game is dropped, so the lobby only holds games still being played
+  fn finish(&mut self, game_id: GameId) {
+    let result = self.games.get(&game_id).and_then(|game| game.state.result);
+    self.broadcast(game_id, &ServerMessage::GameOver { game_id, result });
+    self.games.remove(&game_id);
+  }
+
+  // players who never move still lose on time
+  pub fn check_clocks(&mut self) {
+    let mut flagged = Vec::new();
+    for (game_id, game) in self.games.iter_mut() {
+      if flag_fall(&mut game.state) {
+        flagged.push(*game_id);
+      }
+    }
+    for game_id in flagged {
+      self.finish(game_id);
+    }
+  }
+
+  pub fn game(&self, game_id: GameId) -> Option<&ServerGame> {
+    self.games.get(&game_id)
+  }
+}
+
+fn serve_client(stream: TcpStream, lobby: Arc<Mutex<Lobby>>, outbox: Sender<ServerMessage>) {
+  let client = lobby.lock().unwrap().connect(outbox);
+
+  for line in BufReader::new(stream).lines() {
+    let Ok(line) = line else {
+      break;
+    };
+    match serde_json::from_str::<ClientMessage>(&line) {
+      Ok(message) => lobby.lock().unwrap().handle(client, message),
+      Err(e) => lobby.lock().unwrap().error(client, &format!("Bad message: {}", e)),
+    }
+  }
+  lobby.lock().unwrap().disconnect(client);
+}
+
+// runs until the lobby forgets the client and drops its outbox
+fn write_queued(mut writer: TcpStream, queued: Receiver<ServerMessage>, connected: Arc<AtomicUsize>) {
+  for message in queued {
+    if write_message(&mut writer, &message).is_err() {
+      // the reader sees the socket close and disconnects the client
+      let _ = writer.shutdown(Shutdown::Both);
+    }
+  }
+  connected.fetch_sub(1, Ordering::SeqCst);
+}
+
+pub struct GameServer {
+  listener: TcpListener,
+  lobby: Arc<Mutex<Lobby>>,
+  threadpool: ThreadPool,
+  max_clients: usize,
+  connected: Arc<AtomicUsize>,
+}
+
+impl GameServer {
+  // every client holds a reading and a writing thread for as long as its connected, one more runs the clocks
+  pub fn bind(address: impl ToSocketAddrs, max_clients: usize) -> io::Result<Self> {
+    let threadpool = ThreadPool::new(2 * max_clients + 1)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+    Ok(GameServer {
+      listener: TcpListener::bind(address)?,
+      lobby: Arc::new(Mutex::new(Lobby::default())),
+      threadpool,
+      max_clients,
+      connected: Arc::new(AtomicUsize::new(0)),
+    })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+
+  pub fn lobby(&self) -> Arc<Mutex<Lobby>> {
+    self.lobby.clone()
+  }
+
+  pub fn run(&self) {
+    let lobby = self.lobby.clone();
+    self.threadpool.execute(move || loop {
+      std::thread::sleep(CLOCK_CHECK_INTERVAL);
+      lobby.lock().unwrap().check_clocks();
+    });
+
+    for stream in self.listener.incoming() {
+      match stream {
+        Ok(stream) => self.accept(stream),
+        Err(e) => println!("Connection failed: {}", e),
+      }
+    }
+  }
+
+  // a full server says so rather than leaving the client waiting for a free thread
+  fn accept(&self, mut stream: TcpStream) {
+    if self.connected.load(Ordering::SeqCst) >= self.max_clients {
+      let _ = write_message(&mut stream, &ServerMessage::Error { reason: "Server is full".to_owned() });
+      return;
+    }
+    let writer = match stream.try_clone() {
+      Ok(writer) => writer,
+      Err(e) => {
+        println!("Error accepting client: {}", e);
+        return;
+      },
+    };
+    self.connected.fetch_add(1, Ordering::SeqCst);
+    let (outbox, queued) = channel();
+    let connected = self.connected.clone();
+    self.threadpool.execute(move || write_queued(writer, queued, connected));
+    let lobby = self.lobby.clone();
+    self.threadpool.execute(move || serve_client(stream, lobby, outbox));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  struct TestClient {
+    writer: TcpStream,
+    reader: BufReader<TcpStream>,
+  }
+
+  impl TestClient {
+    fn connect(address: SocketAddr) -> Self {
+      let stream = TcpStream::connect(address).unwrap();
+      stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+      let mut client = TestClient { writer: stream.try_clone().unwrap(), reader: BufReader::new(stream) };
+      assert!(matches!(client.receive(), ServerMessage::Welcome { .. }));
+      client
+    }
+
+    fn send(&mut self, message: ClientMessage) {
+      write_message(&mut self.writer, &message).unwrap();
+    }
+
+    fn receive(&mut self) -> ServerMessage {
+      let mut line = String::new();
+      self.reader.read_line(&mut line).unwrap();
+      serde_json::from_str(&line).unwrap()
+    }
+  }
+
+  #[test]
+  fn matchmaking_and_spectating() {
+    let server = Arc::new(GameServer::bind("127.0.0.1:0", 4).unwrap());
+    let address = server.local_addr().unwrap();
+    let running = server.clone();
+    std::thread::spawn(move || running.run());
+
+    let seek = |name: &str| ClientMessage::Seek { name: name.to_owned(), variant: Variant::Standard, time_control: TimeControl::fischer(5, 0) };
+    let mut alice = TestClient::connect(address);
+    alice.send(seek("alice"));
+    assert!(matches!(alice.receive(), ServerMessage::Waiting));
+    let mut bob = TestClient::connect(address);
+    bob.send(seek("bob"));
+
+    let ServerMessage::GameStarted { game_id, colour: White, black, .. } = alice.receive() else {
+      panic!("alice should start as white");
+    };
+    assert_eq!(black, "bob");
+    assert!(matches!(bob.receive(), ServerMessage::GameStarted { colour: Black, .. }));
+
+    let mut carol = TestClient::connect(address);
+    carol.send(ClientMessage::Watch { game_id });
+    assert!(matches!(carol.receive(), ServerMessage::Snapshot { .. }));
+
+    // out of turn and illegal moves are refused, a legal one reaches everyone
+    bob.send(ClientMessage::Move { game_id, origin: 52, destination: 36, promotion: None });
+    assert!(matches!(bob.receive(), ServerMessage::Error { .. }));
+    alice.send(ClientMessage::Move { game_id, origin: 12, destination: 44, promotion: None });
+    assert!(matches!(alice.receive(), ServerMessage::Error { .. }));
+    alice.send(ClientMessage::Move { game_id, origin: 12, destination: 28, promotion: None });
+    for client in [&mut alice, &mut bob, &mut carol] {
+      let ServerMessage::Moved { fen, .. } = client.receive() else {
+        panic!("everyone should see the move");
+      };
+      assert_eq!(fen, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+    }
+
+    bob.send(ClientMessage::Resign { game_id });
+    assert!(matches!(carol.receive(), ServerMessage::GameOver { result: Some(GameEnd::Resignation(false)), .. }));
+    assert!(server.lobby().lock().unwrap().game(game_id).is_none());
+  }
+
+  #[test]
+  fn full_server_turns_clients_away() {
+    let server = Arc::new(GameServer::bind("127.0.0.1:0", 1).unwrap());
+    let address = server.local_addr().unwrap();
+    let running = server.clone();
+    std::thread::spawn(move || running.run());
+
+    let _alice = TestClient::connect(address);
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    assert!(matches!(serde_json::from_str(&line).unwrap(), ServerMessage::Error { .. }));
+  }
+
+  #[test]
+  fn delays_hold_off_the_flag() {
+    let mut state = GameState::new();
+    state.set_time_control(TimeControl::simple_delay(1, 5));
+    state.white_timer = Duration::from_secs(1);
+    state.clock = std::time::Instant::now() - Duration::from_secs(3);
+    // three seconds is past the one left on the clock, but the delay covers the first five
+    assert!(!flag_fall(&mut state));
+
+    state.set_time_control(TimeControl::fischer(1, 5));
+    state.white_timer = Duration::from_secs(1);
+    assert!(flag_fall(&mut state));
+    assert_eq!(state.result, Some(GameEnd::TimeOut(true)));
+  }
+}
@@ -0,0 +1,368 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::PieceColour::*;
+
+use crate::{describe_clocks, evaluate, format_score, hint_to_san, legal_moves_san, load_game, parse_move, queen_promotion, register_observer, render_game, replay_log, save_game};
+use crate::{search, to_fen, to_pgn, BoardSetting, CancelToken, Command, Dispatcher, Event, EventLog, FileBroadcaster, GameMode, GameState};
+use crate::{BoardRep, Computer, NetworkPeer, PieceStyle, Reply, SearchLimits, TaskHandle, TcpBroadcaster, Theme, ThreadPool};
+use crate::{DEFAULT_BROADCAST_PORT, DEFAULT_HINT_DEPTH, HELP, RECORDER_PRIORITY};
+
+// how often wait_for_computer looks for the engine's move
+const COMPUTER_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+pub enum Outcome {
+  // text for the player, empty when theres nothing to say
+  Output(String),
+  Exit,
+}
+
+// what a command line front end needs to run commands against the game, shared by the CLI and the TUI
+pub struct Session<'a, 'b> {
+  pub game: Arc<Mutex<GameState>>,
+  pub dispatcher: &'b mut Dispatcher<'a>,
+  pub peer: Option<Arc<Mutex<NetworkPeer>>>,
+  pub chat_name: String,
+  // promotions without a piece become queens instead of asking on stdin
+  pub auto_queen: bool,
+  // plays the other side when practising against the engine
+  pub computer: Option<Computer>,
+  // the engine's search and the board it started from, a result for any other board is thrown away
+  thinking: Option<(BoardRep, TaskHandle<Option<Event>>)>,
+  // the engine thinks here so the tui keeps drawing and hearing keys meanwhile
+  threads: ThreadPool,
+  // positions before each local move, for undo
+  undo_stack: Vec<GameState>,
+}
+
+impl<'a, 'b> Session<'a, 'b> {
+  pub fn new(game: Arc<Mutex<GameState>>, dispatcher: &'b mut Dispatcher<'a>, peer: Option<Arc<Mutex<NetworkPeer>>>, chat_name: String) -> Self {
+    let threads = ThreadPool::new(1).expect("Error creating threads");
+    Session { game, dispatcher, peer, chat_name, auto_queen: false, computer: None, thinking: None, threads, undo_stack: Vec::new() }
+  }
+
+  // the publisher and the board settings belong to the session, not the game being swapped in
+  fn replace_game(&mut self, mut replacement: GameState) {
+    let mut state = self.game.lock().unwrap();
+    replacement.publisher = state.publisher.take();
+    replacement.render = state.render;
+    *state = replacement;
+  }
+
+  fn engine(&self) -> (GameState, crate::SearchResult, i32) {
+    let state = self.game.lock().unwrap().clone();
+    let result = search(&state, SearchLimits::depth(DEFAULT_HINT_DEPTH), &CancelToken::new());
+    // scores are for the player to move, these are shown from white's side
+    let white = if state.player_turn == 1 { 1 } else { -1 };
+    (state, result, white)
+  }
+
+  // a move from the player, which has to wait while its the engine's turn
+  pub fn play(&mut self, event: Event) -> Result<(), String> {
+    if self.computer_to_move() {
+      return Err("Wait for the computer to move".to_owned());
+    }
+    self.submit(event)
+  }
+
+  fn submit(&mut self, event: Event) -> Result<(), String> {
+    let before = self.game.lock().unwrap().clone();
+    let event = match event {
+      Event::Move { translation, promotion: None } if self.auto_queen => Event::Move { translation, promotion: queen_promotion(&before.board, translation) },
+      event => event,
+    };
+    match self.dispatcher.request_blocking(event) {
+      Some(Reply::Rejected(reason)) => Err(reason),
+      _ => {
+        if self.peer.is_none() {
+          self.undo_stack.push(before);
+        }
+        Ok(())
+      },
+    }
+  }
+
+  pub fn execute(&mut self, command: Command) -> Outcome {
+    let output = match command {
+      Command::Help => HELP.to_owned(),
+      Command::Exit => return Outcome::Exit,
+      Command::Reset => {
+        self.game.lock().unwrap().reset();
+        self.undo_stack.clear();
+        String::new()
+      },
+      Command::Moves(square) => {
+        let moves = legal_moves_san(&self.game.lock().unwrap(), square);
+        match (moves.is_empty(), square) {
+          (true, Some(square)) => format!("No legal moves from {}", square.to_algebraic()),
+          (true, None) => "No legal moves".to_owned(),
+          (false, _) => moves.join(" "),
+        }
+      },
+      Command::Undo => {
+        if self.peer.is_some() {
+          return Outcome::Output("Moves cant be taken back in a network game".to_owned());
+        }
+        let Some(mut previous) = self.undo_stack.pop() else {
+          return Outcome::Output("No moves to take back".to_owned());
+        };
+        // the engine would only play its move again, so that goes back too
+        if self.computer.is_some_and(|computer| computer.to_move(&previous)) {
+          if let Some(earlier) = self.undo_stack.pop() {
+            previous = earlier;
+          }
+        }
+        let taken_back = self.game.lock().unwrap().san_history.last().cloned().unwrap_or_default();
+        previous.clock = Instant::now();
+        self.replace_game(previous);
+        format!("Took back {}", taken_back)
+      },
+      Command::Fen => to_fen(&self.game.lock().unwrap()),
+      Command::Pgn => to_pgn(&self.game.lock().unwrap()).trim_end().to_owned(),
+      Command::Flip => {
+        let mut state = self.game.lock().unwrap();
+        state.render.flipped = !state.render.flipped;
+        String::new()
+      },
+      Command::Board(setting) => {
+        let mut state = self.game.lock().unwrap();
+        match setting {
+          Some(BoardSetting::Unicode) => state.render.pieces = PieceStyle::Unicode,
+          Some(BoardSetting::Ascii) => state.render.pieces = PieceStyle::Ascii,
+          Some(BoardSetting::Colour) => state.render.theme = Some(Theme::CLASSIC),
+          Some(BoardSetting::Plain) => state.render.theme = None,
+          None => {},
+        }
+        String::new()
+      },
+      Command::Clock => describe_clocks(&self.game.lock().unwrap()),
+      Command::Eval => {
+        let (state, result, white) = self.engine();
+        format!("Material and position: {}\nDepth {} search: {}", format_score(evaluate(&state.board), None), result.depth, format_score(result.score * white, result.mate_in()))
+      },
+      Command::Hint => {
+        let (state, result, white) = self.engine();
+        match result.best_move {
+          Some(translation) => format!("Hint: {} ({})", hint_to_san(&state, translation), format_score(result.score * white, result.mate_in())),
+          None => "No legal moves".to_owned(),
+        }
+      },
+      // through the dispatcher so spectators and the event log see it
+      Command::Resign => {
+        self.dispatcher.request_blocking(Event::Surrender(None));
+        String::new()
+      },
+      // offering is agreeing at the same keyboard, the engine takes a draw only when it isnt ahead
+      Command::Draw => {
+        if let Some(computer) = self.computer {
+          let (_, result, white) = self.engine();
+          let engine_score = if computer.colour == White { result.score * white } else { -result.score * white };
+          if engine_score > 0 {
+            return Outcome::Output("The computer declines the draw".to_owned());
+          }
+        }
+        self.dispatcher.request_blocking(Event::DrawOffer);
+        String::new()
+      },
+      Command::Mode(None) => {
+        let state = self.game.lock().unwrap();
+        format!("{:?} mode, time control {}", state.mode, state.time_control)
+      },
+      Command::Mode(Some(mode)) => {
+        let mut state = self.game.lock().unwrap();
+        if self.peer.is_some() || !state.move_history.is_empty() {
+          "The mode can only be changed before the first move of a local game".to_owned()
+        } else if mode == GameMode::Daily {
+          "Daily games are picked when the program starts".to_owned()
+        } else {
+          state.set_mode(mode);
+          state.clock = Instant::now();
+          format!("{:?} mode selected", mode)
+        }
+      },
+      Command::Save(file) => match save_game(Path::new(&file), &self.game.lock().unwrap()) {
+        Ok(()) => format!("Game saved to {}", file),
+        Err(e) => format!("Error saving game: {}", e),
+      },
+      Command::Load(file) => match load_game(Path::new(&file)) {
+        Ok(loaded) => {
+          self.replace_game(loaded);
+          self.undo_stack.clear();
+          format!("Game loaded from {}", file)
+        },
+        Err(e) => format!("Error loading game: {}", e),
+      },
+      Command::Broadcast(port) => {
+        let port = port.unwrap_or(DEFAULT_BROADCAST_PORT);
+        match std::net::TcpListener::bind(("0.0.0.0", port)) {
+          Ok(listener) => {
+            let broadcaster = TcpBroadcaster::start(listener, self.game.clone());
+            register_observer(self.dispatcher, Arc::new(Mutex::new(broadcaster)));
+            format!("Broadcasting on port {}", port)
+          },
+          Err(e) => format!("Error broadcasting: {}", e),
+        }
+      },
+      Command::BroadcastFile(file) => match FileBroadcaster::create(Path::new(&file)) {
+        Ok(broadcaster) => {
+          register_observer(self.dispatcher, Arc::new(Mutex::new(broadcaster)));
+          format!("Broadcasting to {}", file)
+        },
+        Err(e) => format!("Error broadcasting: {}", e),
+      },
+      // goes to the opponent and anyone watching
+      Command::Say(text) => {
+        self.dispatcher.trigger_event(Event::Chat { from: self.chat_name.clone(), text });
+        String::new()
+      },
+      Command::Record(file) => match EventLog::create(Path::new(&file), self.game.clone()) {
+        Ok(log) => {
+          self.dispatcher.subscribe_all(RECORDER_PRIORITY, Arc::new(Mutex::new(log)));
+          format!("Recording events to {}", file)
+        },
+        Err(e) => format!("Error recording events: {}", e),
+      },
+      Command::Replay(file) => match replay_log(Path::new(&file)) {
+        Ok(replay) => {
+          let mut output = format!("Replayed {} events, {} moves", replay.events, replay.state.move_history.len());
+          for mismatch in &replay.mismatches {
+            output.push_str(&format!("\nMismatch: {}", mismatch));
+          }
+          self.replace_game(replay.state);
+          self.undo_stack.clear();
+          output
+        },
+        Err(e) => format!("Error replaying events: {}", e),
+      },
+      Command::Play(text) => {
+        let parsed = parse_move(&self.game.lock().unwrap(), &text);
+        match parsed.and_then(|event| self.play(event)) {
+          Ok(()) => String::new(),
+          Err(e) if e.contains(text.trim()) => e,
+          Err(e) => format!("{}: {}", e, text.trim()),
+        }
+      },
+      Command::Tui => "Already in the full screen board".to_owned(),
+    };
+    Outcome::Output(output)
+  }
+
+  pub fn computer_to_move(&self) -> bool {
+    self.computer.is_some_and(|computer| computer.to_move(&self.game.lock().unwrap()))
+  }
+
+  // starts the engine thinking on its turn and plays its move once its found one, None until then
+  // the thinking time comes off the engine's clock
+  pub fn computer_move(&mut self) -> Option<String> {
+    let computer = self.computer?;
+    let state = self.game.lock().unwrap().clone();
+    match &mut self.thinking {
+      // undo, reset or load changed the board underneath it
+      Some((board, thinking)) if *board != state.board || !computer.to_move(&state) => {
+        thinking.cancel();
+        self.thinking = None;
+        None
+      },
+      Some((_, thinking)) => {
+        if !thinking.is_finished() {
+          return None;
+        }
+        let (_, thinking) = self.thinking.take()?;
+        let event = thinking.join().ok().flatten()?;
+        Some(match self.submit(event) {
+          Ok(()) => format!("Computer plays {}", self.game.lock().unwrap().san_history.last().cloned().unwrap_or_default()),
+          Err(e) => format!("Computer move rejected: {}", e),
+        })
+      },
+      None if computer.to_move(&state) => {
+        let board = state.board.clone();
+        self.thinking = Some((board, self.threads.spawn(move |token| computer.choose_move(&state, token))));
+        None
+      },
+      None => None,
+    }
+  }
+
+  // the command line has nothing else to do until the engine has moved
+  pub fn wait_for_computer(&mut self) -> Option<String> {
+    loop {
+      if let Some(text) = self.computer_move() {
+        return Some(text);
+      }
+      self.thinking.as_ref()?;
+      std::thread::sleep(COMPUTER_POLL_INTERVAL);
+    }
+  }
+
+  // the board is shown again after these from the command line
+  pub fn shows_board(command: &Command) -> bool {
+    matches!(command, Command::Undo | Command::Reset | Command::Flip | Command::Board(_) | Command::Load(_) | Command::Replay(_))
+  }
+
+  pub fn board(&self) -> String {
+    let state = self.game.lock().unwrap();
+    render_game(&state, &state.render)
+  }
+}
+
+// the pool waits for its jobs when dropped, so a search still going is told to stop first
+impl Drop for Session<'_, '_> {
+  fn drop(&mut self) {
+    if let Some((_, thinking)) = &self.thinking {
+      thinking.cancel();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{from_fen, get_legal_move_list, Difficulty, EventKind, GameEnd};
+
+  #[test]
+  fn computer_thinks_in_the_background() {
+    let pool = ThreadPool::new(1).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    state.render.silent = true;
+    let game = Arc::new(Mutex::new(state));
+    dispatcher.register_handler(EventKind::Move, game.clone());
+    dispatcher.start();
+    let mut session = Session::new(game.clone(), &mut dispatcher, None, "Players".to_owned());
+    session.computer = Some(Computer::against(Black, Difficulty::Easy));
+
+    // the first call only starts the search, the player cant move for the engine meanwhile
+    assert_eq!(session.computer_move(), None);
+    assert!(session.thinking.is_some());
+    let e4 = parse_move(&game.lock().unwrap(), "e4").unwrap();
+    assert!(session.play(e4).is_err());
+
+    let text = session.wait_for_computer().unwrap();
+    assert!(text.starts_with("Computer plays"), "{}", text);
+    assert_eq!(game.lock().unwrap().player_turn, 2);
+  }
+
+  #[test]
+  fn computer_declines_draws_when_ahead() {
+    let pool = ThreadPool::new(1).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+    // white is a queen up
+    let mut state = from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+    state.render.silent = true;
+    let game = Arc::new(Mutex::new(state));
+    dispatcher.register_handler(EventKind::DrawOffer, game.clone());
+    dispatcher.start();
+    let mut session = Session::new(game.clone(), &mut dispatcher, None, "Players".to_owned());
+    session.computer = Some(Computer::against(Black, Difficulty::Easy));
+
+    assert!(matches!(session.execute(Command::Draw), Outcome::Output(text) if text == "The computer declines the draw"));
+    assert!(!game.lock().unwrap().game_over);
+
+    // the other way round the engine is glad of it
+    session.computer = Some(Computer::against(White, Difficulty::Easy));
+    session.execute(Command::Draw);
+    assert_eq!(game.lock().unwrap().result, Some(GameEnd::DrawByAgreement));
+  }
+}
@@ -0,0 +1,22 @@
+// helpers shared by the test modules
+use crate::{from_fen, Coordinates, GameState, Move};
+
+pub fn square(name: &str) -> Coordinates {
+  Coordinates::from_algebraic(name).unwrap()
+}
+
+pub fn translation(from: &str, to: &str) -> Move {
+  (square(from), square(to))
+}
+
+// a position from FEN that prints nothing when moves are played on it
+pub fn position(fen: &str) -> GameState {
+  let mut state = from_fen(fen).unwrap();
+  state.render.silent = true;
+  state
+}
+
+pub fn is_legal(state: &GameState, from: &str, to: &str) -> bool {
+  let moves = if state.player_turn == 1 { &state.move_list.white } else { &state.move_list.black };
+  moves.contains(&translation(from, to))
+}
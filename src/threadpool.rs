@@ -1,6 +1,9 @@
 use std::{
-  sync::{mpsc, Arc, Mutex},
+  any::Any,
+  panic::{self, AssertUnwindSafe},
+  sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex},
   thread,
+  time::{Duration, Instant},
 };
 
 pub struct ThreadPool {
@@ -15,11 +18,13 @@ impl ThreadPool {
   ///
   /// The size is the number of threads in the pool.
   ///
-  /// # Panics
+  /// # Errors
   ///
-  /// The `new` function will panic if the size is zero.
+  /// Returns `NonPositivePoolSize` if the size is zero.
   pub fn new(size: usize) -> Result<Self, PoolCreationError> {
-      assert!(size > 0);
+      if size == 0 {
+          return Err(PoolCreationError::NonPositivePoolSize);
+      }
 
       let (sender, receiver) = mpsc::channel();
 
@@ -37,31 +42,87 @@ impl ThreadPool {
       })
   }
 
+  /// Queue a job for the next free worker.
+  ///
+  /// A job that panics is caught on its worker, which prints a line and goes on
+  /// to the next job, so the pool never loses a thread and none is respawned.
   pub fn execute<F>(&self, f: F)
   where
       F: FnOnce() + Send + 'static,
   {
       let job = Box::new(f);
 
-      self.sender.as_ref().unwrap().send(job).unwrap();
+      self.sender.as_ref().expect("ThreadPool was shut down").send(job).unwrap();
   }
-}
 
-impl Drop for ThreadPool {
-  fn drop(&mut self) {
+  /// Run a job and get its result back through the handle.
+  ///
+  /// The job is given a token to check now and then, `TaskHandle::cancel` only
+  /// sets it. A job cancelled before a worker picks it up never runs. A panic
+  /// is caught and handed to whoever joins instead of taking the worker down.
+  pub fn spawn<F, T>(&self, f: F) -> TaskHandle<T>
+  where
+      F: FnOnce(&CancelToken) -> T + Send + 'static,
+      T: Send + 'static,
+  {
+      let (sender, result) = mpsc::channel();
+      let token = CancelToken::new();
+      let job_token = token.clone();
+      self.execute(move || {
+          let outcome = if job_token.is_cancelled() {
+              Err(TaskError::Cancelled)
+          } else {
+              panic::catch_unwind(AssertUnwindSafe(|| f(&job_token)))
+                  .map_err(|payload| TaskError::Panicked(panic_message(payload)))
+          };
+          // nobody might be waiting any more
+          let _ = sender.send(outcome);
+      });
+      TaskHandle { result, token, finished: None }
+  }
+
+  /// Stop taking jobs and wait for the workers to finish the ones they have.
+  ///
+  /// Queued jobs still run. Workers that are still busy when the timeout is up
+  /// are left running detached and counted in the error.
+  pub fn shutdown(&mut self, timeout: Duration) -> Result<(), ShutdownTimeout> {
       drop(self.sender.take());
+      let deadline = Instant::now() + timeout;
+      let mut still_running = 0;
       for worker in &mut self.workers {
-
-          if let Some(thread) = worker.thread.take() {
-              thread.join().unwrap();
+          let Some(thread) = worker.thread.take() else {
+              continue;
+          };
+          while !thread.is_finished() && Instant::now() < deadline {
+              thread::sleep(Duration::from_millis(5));
+          }
+          if !thread.is_finished() {
+              println!("Worker {} is still busy", worker.id);
+              still_running += 1;
+              continue;
           }
+          let _ = thread.join();
+      }
+      if still_running > 0 {
+          return Err(ShutdownTimeout { still_running });
+      }
+      Ok(())
+  }
+}
+
+pub const DEFAULT_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Drop for ThreadPool {
+  fn drop(&mut self) {
+      if let Err(e) = self.shutdown(DEFAULT_JOIN_TIMEOUT) {
+          println!("{} worker(s) did not stop in time", e.still_running);
       }
   }
 }
 
 struct Worker {
   id: usize,
-  pub thread: Option<thread::JoinHandle<()>>,
+  thread: Option<thread::JoinHandle<()>>,
 }
 
 impl Worker {
@@ -71,7 +132,10 @@ impl Worker {
 
           match message {
               Ok(job) => {
-                  job();
+                  // the panic is caught here, so the thread can go straight on to the next job
+                  if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                      println!("Worker {} panicked, carrying on", id);
+                  }
               }
               Err(_) => {
                   break;
@@ -86,7 +150,163 @@ impl Worker {
   }
 }
 
+// checked by jobs started with spawn, cancelling is up to the job
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  pub fn new() -> Self {
+      CancelToken::default()
+  }
+
+  pub fn cancel(&self) {
+      self.0.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+      self.0.load(Ordering::SeqCst)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskError {
+  // cancelled before it started
+  Cancelled,
+  Panicked(String),
+  // the pool shut down without running it
+  Lost,
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+  match payload.downcast::<String>() {
+      Ok(message) => *message,
+      Err(payload) => payload.downcast_ref::<&str>().map_or("unknown panic".to_owned(), |message| message.to_string()),
+  }
+}
+
+pub struct TaskHandle<T> {
+  result: mpsc::Receiver<Result<T, TaskError>>,
+  token: CancelToken,
+  // kept by is_finished when it sees the result first
+  finished: Option<Result<T, TaskError>>,
+}
+
+impl<T> TaskHandle<T> {
+  pub fn cancel(&self) {
+      self.token.cancel();
+  }
+
+  pub fn token(&self) -> CancelToken {
+      self.token.clone()
+  }
+
+  pub fn is_finished(&mut self) -> bool {
+      if self.finished.is_none() {
+          self.finished = match self.result.try_recv() {
+              Ok(result) => Some(result),
+              Err(mpsc::TryRecvError::Empty) => None,
+              Err(mpsc::TryRecvError::Disconnected) => Some(Err(TaskError::Lost)),
+          };
+      }
+      self.finished.is_some()
+  }
+
+  pub fn join(mut self) -> Result<T, TaskError> {
+      match self.finished.take() {
+          Some(result) => result,
+          None => self.result.recv().unwrap_or(Err(TaskError::Lost)),
+      }
+  }
+
+  // None if the job hasnt finished in time, the handle can be joined again later
+  pub fn join_timeout(&mut self, timeout: Duration) -> Option<Result<T, TaskError>> {
+      if let Some(result) = self.finished.take() {
+          return Some(result);
+      }
+      match self.result.recv_timeout(timeout) {
+          Ok(result) => Some(result),
+          Err(mpsc::RecvTimeoutError::Timeout) => None,
+          Err(mpsc::RecvTimeoutError::Disconnected) => Some(Err(TaskError::Lost)),
+      }
+  }
+}
+
 #[derive(Debug)]
 pub enum PoolCreationError {
   NonPositivePoolSize
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownTimeout {
+  pub still_running: usize,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shutdown_gives_up_on_stuck_workers() {
+      let mut pool = ThreadPool::new(2).unwrap();
+      let (release, stuck) = mpsc::channel::<()>();
+      pool.execute(move || {
+          let _ = stuck.recv();
+      });
+      pool.execute(|| thread::sleep(Duration::from_millis(10)));
+      assert_eq!(pool.shutdown(Duration::from_millis(100)), Err(ShutdownTimeout { still_running: 1 }));
+      drop(release);
+  }
+
+  #[test]
+  fn panicking_jobs_keep_every_worker() {
+      let pool = ThreadPool::new(3).unwrap();
+      for _ in 0..3 {
+          pool.execute(|| panic!("job blew up"));
+      }
+      // the jobs only finish once all three are running at the same time, which needs every worker
+      let barrier = Arc::new(std::sync::Barrier::new(3));
+      let mut tasks = (0..3)
+          .map(|number| {
+              let barrier = barrier.clone();
+              pool.spawn(move |_| {
+                  barrier.wait();
+                  number
+              })
+          })
+          .collect::<Vec<_>>();
+      for (number, task) in tasks.iter_mut().enumerate() {
+          assert_eq!(task.join_timeout(Duration::from_secs(5)), Some(Ok(number)));
+      }
+  }
+
+  #[test]
+  fn tasks_return_results_panics_and_cancellation() {
+      let pool = ThreadPool::new(1).unwrap();
+      assert!(matches!(ThreadPool::new(0), Err(PoolCreationError::NonPositivePoolSize)));
+
+      assert_eq!(pool.spawn(|_| 6 * 7).join(), Ok(42));
+      let panicked = pool.spawn(|_| -> u8 { panic!("search blew up") });
+      assert_eq!(panicked.join(), Err(TaskError::Panicked("search blew up".to_owned())));
+
+      // a plain job panicking doesnt lose the only worker
+      pool.execute(|| panic!("worker goes down"));
+      assert_eq!(pool.spawn(|_| "still here").join(), Ok("still here"));
+
+      let (started, running) = mpsc::channel();
+      let mut search = pool.spawn(move |token| {
+          started.send(()).unwrap();
+          let mut nodes = 0u64;
+          while !token.is_cancelled() {
+              nodes += 1;
+          }
+          nodes
+      });
+      let queued = pool.spawn(|_| 1);
+      queued.cancel();
+      running.recv().unwrap();
+      assert!(search.join_timeout(Duration::from_millis(20)).is_none());
+      search.cancel();
+      assert!(search.join().unwrap() > 0);
+      assert_eq!(queued.join(), Err(TaskError::Cancelled));
+  }
+}
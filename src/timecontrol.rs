@@ -0,0 +1,260 @@
+use std::time::Duration;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+// how the clock treats the time spent on a move
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimingMethod {
+  // Fischer, bonus added after every move
+  Increment(Duration),
+  // time used is given back after the move, never more than the delay
+  Bronstein(Duration),
+  // US delay, clock only starts counting down once the delay has passed
+  SimpleDelay(Duration),
+  // time used by the mover is added to the opponents clock
+  Hourglass,
+  SuddenDeath,
+}
+
+// a period of the game, FIDE style 90 minutes for 40 moves etc...
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeStage {
+  pub time: Duration,
+  // moves to be played in this stage, None means rest of the game
+  pub moves: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeControl {
+  pub stages: Vec<TimeStage>,
+  pub method: TimingMethod,
+}
+
+impl TimeControl {
+  pub fn new(base: Duration, method: TimingMethod) -> Self {
+    TimeControl {
+      stages: vec![TimeStage { time: base, moves: None }],
+      method,
+    }
+  }
+
+  /// Ends the current last stage after `moves` moves and adds `time` for the next one.
+  ///
+  /// `TimeControl::new(90 min, ..).then_after(40, 30 min)` is 90 minutes for 40 moves then +30 minutes.
+  pub fn then_after(mut self, moves: u16, time: Duration) -> Self {
+    if let Some(last) = self.stages.last_mut() {
+      last.moves = Some(moves);
+    }
+    self.stages.push(TimeStage { time, moves: None });
+    self
+  }
+
+  pub fn fischer(minutes: u64, increment_secs: u64) -> Self {
+    TimeControl::new(Duration::from_secs(minutes * 60), TimingMethod::Increment(Duration::from_secs(increment_secs)))
+  }
+
+  pub fn bronstein(minutes: u64, delay_secs: u64) -> Self {
+    TimeControl::new(Duration::from_secs(minutes * 60), TimingMethod::Bronstein(Duration::from_secs(delay_secs)))
+  }
+
+  pub fn simple_delay(minutes: u64, delay_secs: u64) -> Self {
+    TimeControl::new(Duration::from_secs(minutes * 60), TimingMethod::SimpleDelay(Duration::from_secs(delay_secs)))
+  }
+
+  pub fn hourglass(minutes: u64) -> Self {
+    TimeControl::new(Duration::from_secs(minutes * 60), TimingMethod::Hourglass)
+  }
+
+  // 90 minutes for 40 moves then 30 minutes for the rest, 30 seconds increment from move 1
+  pub fn fide_classical() -> Self {
+    TimeControl::fischer(90, 30).then_after(40, Duration::from_secs(1800))
+  }
+
+  pub fn starting_time(&self) -> Duration {
+    self.stages.first().map(|stage| stage.time).unwrap_or_default()
+  }
+
+  // time added when a player completes move number `move_number`, only non zero at the end of a stage
+  pub fn stage_bonus(&self, move_number: u16) -> Duration {
+    let mut moves_so_far: u16 = 0;
+    for (index, stage) in self.stages.iter().enumerate() {
+      let Some(moves) = stage.moves else {
+        break;
+      };
+      // no move number gets past the end of a stage that runs beyond u16
+      let Some(total) = moves_so_far.checked_add(moves) else {
+        break;
+      };
+      moves_so_far = total;
+      if moves_so_far == move_number {
+        return self.stages.get(index + 1).map(|next| next.time).unwrap_or_default();
+      }
+    }
+    Duration::ZERO
+  }
+
+  // what the movers clock is down to after thinking for elapsed, before anything is given back
+  // zero means the flag has fallen, a simple delay runs out before the clock starts counting
+  pub fn time_left(&self, timer: Duration, elapsed: Duration) -> Duration {
+    match self.method {
+      TimingMethod::SimpleDelay(delay) => timer.saturating_sub(elapsed.saturating_sub(delay)),
+      _ => timer.saturating_sub(elapsed),
+    }
+  }
+
+  // takes time off the movers clock for a completed move, then adds whatever the method and stages give back
+  // a flag that has fallen stays fallen
+  pub fn apply_move(&self, mover: &mut Duration, opponent: &mut Duration, elapsed: Duration, move_number: u16) {
+    *mover = self.time_left(*mover, elapsed);
+
+    if mover.is_zero() {
+      return;
+    }
+
+    match self.method {
+      TimingMethod::Increment(increment) => *mover += increment,
+      TimingMethod::Bronstein(delay) => *mover += elapsed.min(delay),
+      TimingMethod::Hourglass => *opponent += elapsed,
+      TimingMethod::SimpleDelay(_) | TimingMethod::SuddenDeath => {},
+    }
+
+    *mover += self.stage_bonus(move_number);
+  }
+}
+
+impl Default for TimeControl {
+  fn default() -> Self {
+    TimeControl::fischer(30, 30)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeControlParseError(pub String);
+
+impl std::fmt::Display for TimeControlParseError {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    write!(f, "Invalid time control: {}", self.0)
+  }
+}
+
+// PGN TimeControl tag style, all values in seconds: "300+2", "40/5400+30:1800+30"
+// the delay methods are written as a suffix: "900d5" simple delay, "900b5" bronstein, "300h" hourglass
+impl FromStr for TimeControl {
+  type Err = TimeControlParseError;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    let error = || TimeControlParseError(input.to_owned());
+    let parse_secs = |text: &str| text.parse::<u64>().map(Duration::from_secs).map_err(|_| error());
+
+    let mut stages = Vec::new();
+    let mut method = TimingMethod::SuddenDeath;
+
+    for stage in input.trim().split(':') {
+      let (moves, rest) = match stage.split_once('/') {
+        Some((moves, rest)) => (Some(moves.parse::<u16>().map_err(|_| error())?), rest),
+        None => (None, stage),
+      };
+
+      let time = if let Some((time, increment)) = rest.split_once('+') {
+        method = TimingMethod::Increment(parse_secs(increment)?);
+        time
+      } else if let Some((time, delay)) = rest.split_once('d') {
+        method = TimingMethod::SimpleDelay(parse_secs(delay)?);
+        time
+      } else if let Some((time, delay)) = rest.split_once('b') {
+        method = TimingMethod::Bronstein(parse_secs(delay)?);
+        time
+      } else if let Some(time) = rest.strip_suffix('h') {
+        method = TimingMethod::Hourglass;
+        time
+      } else {
+        rest
+      };
+
+      stages.push(TimeStage { time: parse_secs(time)?, moves });
+    }
+
+    // only the last stage may be open ended
+    if stages.iter().rev().skip(1).any(|stage| stage.moves.is_none()) {
+      return Err(error());
+    }
+    if let Some(last) = stages.last_mut() {
+      last.moves = None;
+    }
+
+    Ok(TimeControl { stages, method })
+  }
+}
+
+impl std::fmt::Display for TimeControl {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let suffix = match self.method {
+      TimingMethod::Increment(increment) => format!("+{}", increment.as_secs()),
+      TimingMethod::Bronstein(delay) => format!("b{}", delay.as_secs()),
+      TimingMethod::SimpleDelay(delay) => format!("d{}", delay.as_secs()),
+      TimingMethod::Hourglass => "h".to_owned(),
+      TimingMethod::SuddenDeath => String::new(),
+    };
+
+    let stages = self.stages
+      .iter()
+      .map(|stage| match stage.moves {
+        Some(moves) => format!("{}/{}{}", moves, stage.time.as_secs(), suffix),
+        None => format!("{}{}", stage.time.as_secs(), suffix),
+      })
+      .collect::<Vec<_>>();
+
+    write!(f, "{}", stages.join(":"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn delays_and_increments() {
+    let secs = Duration::from_secs;
+    let mut mover = secs(60);
+    let mut opponent = secs(60);
+
+    TimeControl::fischer(1, 2).apply_move(&mut mover, &mut opponent, secs(10), 1);
+    assert_eq!(mover, secs(52));
+
+    let mut mover = secs(60);
+    TimeControl::bronstein(1, 5).apply_move(&mut mover, &mut opponent, secs(3), 1);
+    assert_eq!(mover, secs(60));
+
+    let mut mover = secs(60);
+    TimeControl::simple_delay(1, 5).apply_move(&mut mover, &mut opponent, secs(8), 1);
+    assert_eq!(mover, secs(57));
+
+    let mut mover = secs(60);
+    TimeControl::hourglass(1).apply_move(&mut mover, &mut opponent, secs(10), 1);
+    assert_eq!((mover, opponent), (secs(50), secs(70)));
+
+    // flag fell, no increment to save it
+    let mut mover = secs(5);
+    TimeControl::fischer(1, 30).apply_move(&mut mover, &mut opponent, secs(10), 1);
+    assert!(mover.is_zero());
+  }
+
+  #[test]
+  fn fide_stages() {
+    let control = TimeControl::fide_classical();
+    assert_eq!(control.starting_time(), Duration::from_secs(5400));
+    assert_eq!(control.stage_bonus(39), Duration::ZERO);
+    assert_eq!(control.stage_bonus(40), Duration::from_secs(1800));
+    assert_eq!(control.stage_bonus(80), Duration::ZERO);
+
+    let parsed: TimeControl = "40/5400+30:1800+30".parse().unwrap();
+    assert_eq!(parsed, control);
+    assert_eq!(parsed.to_string(), "40/5400+30:1800+30");
+    assert!("5400:40/1800".parse::<TimeControl>().is_err());
+
+    let long: TimeControl = "65535/1:65535/1:1".parse().unwrap();
+    assert_eq!(long.to_string(), "65535/1:65535/1:1");
+    assert_eq!(long.stage_bonus(65535), Duration::from_secs(1));
+    assert_eq!(long.stage_bonus(1), Duration::ZERO);
+  }
+}
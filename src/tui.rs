@@ -0,0 +1,291 @@
+use std::io::{self, Stdout, Write};
+use std::time::Duration;
+use crossterm::event::{self, Event as TerminalEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue, style::Print};
+
+use crate::PieceColour::*;
+use crate::{render_game, Command, Coordinates, Event, GameState, Outcome, Session, EMPTY};
+
+// often enough for the clocks to count down smoothly
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+// where the move list and engine output start, just right of the board
+const SIDE_PANE_COLUMN: u16 = 32;
+
+const MOVE_LIST_LINES: usize = 8;
+
+const TITLE: &str = "cheess  arrows+enter: move  type a command + enter  esc: back to the command line  ctrl+c: quit";
+
+// puts the terminal back however the tui ends, panics included
+struct RawTerminal {
+  stdout: Stdout,
+}
+
+impl RawTerminal {
+  fn enter() -> io::Result<Self> {
+    terminal::enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen, cursor::Hide)?;
+    Ok(RawTerminal { stdout })
+  }
+}
+
+impl Drop for RawTerminal {
+  fn drop(&mut self) {
+    let _ = execute!(self.stdout, LeaveAlternateScreen, cursor::Show);
+    let _ = terminal::disable_raw_mode();
+  }
+}
+
+pub struct Tui {
+  command: String,
+  messages: Vec<String>,
+  engine: Vec<String>,
+  // square indices, same as the board
+  cursor: usize,
+  selected: Option<usize>,
+}
+
+// what a key press asks of run_tui
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuiExit {
+  Stay,
+  BackToCli,
+  Quit,
+}
+
+impl Tui {
+  pub fn new(state: &GameState) -> Self {
+    // starts on the king's pawn of whoever is at the bottom
+    let cursor = if state.render.flipped { 52 } else { 12 };
+    Tui { command: String::new(), messages: Vec::new(), engine: Vec::new(), cursor, selected: None }
+  }
+
+  fn say(&mut self, text: &str) {
+    self.messages.extend(text.lines().map(str::to_owned));
+  }
+
+  // up is towards the top of the screen, whichever way the board is turned
+  fn move_cursor(&mut self, flipped: bool, right: i32, up: i32) {
+    let (right, up) = if flipped { (-right, -up) } else { (right, up) };
+    let x = (self.cursor % 8) as i32 + right;
+    let y = (self.cursor / 8) as i32 + up;
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+      self.cursor = (y * 8 + x) as usize;
+    }
+  }
+
+  // first press picks the piece, the second one moves it
+  fn select(&mut self, session: &mut Session) {
+    let (own_piece, square_name) = {
+      let state = session.game.lock().unwrap();
+      let colour = if state.player_turn == 1 { White } else { Black };
+      (state.board.0[self.cursor] != EMPTY && state.board.1[self.cursor] == colour, Coordinates::from(self.cursor).to_algebraic())
+    };
+    match self.selected {
+      Some(origin) if origin == self.cursor => self.selected = None,
+      _ if own_piece => self.selected = Some(self.cursor),
+      None => self.say(&format!("No piece of yours on {}", square_name)),
+      Some(origin) => {
+        self.selected = None;
+        let translation = (Coordinates::from(origin), Coordinates::from(self.cursor));
+        if let Err(e) = session.play(Event::Move { translation, promotion: None }) {
+          self.say(&e);
+        }
+      },
+    }
+  }
+
+  fn run_command(&mut self, session: &mut Session) -> TuiExit {
+    let input = std::mem::take(&mut self.command);
+    let command = match Command::parse(&input) {
+      Ok(command) => command,
+      Err(e) => {
+        self.say(&e);
+        return TuiExit::Stay;
+      },
+    };
+    let engine_output = matches!(command, Command::Hint | Command::Eval);
+    match session.execute(command) {
+      Outcome::Exit => return TuiExit::Quit,
+      Outcome::Output(text) if engine_output => self.engine = text.lines().map(str::to_owned).collect(),
+      Outcome::Output(text) => self.say(&text),
+    }
+    TuiExit::Stay
+  }
+
+  pub fn key(&mut self, session: &mut Session, key: KeyEvent) -> TuiExit {
+    if key.kind != KeyEventKind::Press {
+      return TuiExit::Stay;
+    }
+    let flipped = session.game.lock().unwrap().render.flipped;
+    match key.code {
+      KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return TuiExit::Quit,
+      KeyCode::Esc if self.command.is_empty() && self.selected.is_none() => return TuiExit::BackToCli,
+      KeyCode::Esc => {
+        self.command.clear();
+        self.selected = None;
+      },
+      KeyCode::Enter if self.command.trim().is_empty() => {
+        self.command.clear();
+        self.select(session);
+      },
+      KeyCode::Enter => return self.run_command(session),
+      KeyCode::Backspace => {
+        self.command.pop();
+      },
+      KeyCode::Char(c) => self.command.push(c),
+      KeyCode::Up => self.move_cursor(flipped, 0, 1),
+      KeyCode::Down => self.move_cursor(flipped, 0, -1),
+      KeyCode::Left => self.move_cursor(flipped, -1, 0),
+      KeyCode::Right => self.move_cursor(flipped, 1, 0),
+      _ => {},
+    }
+    TuiExit::Stay
+  }
+
+  // the board, messages and command line down the left, moves and engine output on the right
+  pub fn screen(&self, state: &GameState, height: usize) -> (Vec<String>, Vec<String>) {
+    let mut options = state.render;
+    options.cursor = Some(self.cursor);
+    options.selected = self.selected;
+
+    let mut left = vec![TITLE.to_owned(), String::new()];
+    left.extend(render_game(state, &options).lines().map(str::to_owned));
+    left.push(String::new());
+    if let Some(result) = state.result.filter(|_| state.game_over) {
+      left.push(format!("Game over: {:?}", result));
+    } else if state.game_over {
+      left.push("Game over".to_owned());
+    }
+    // as many of the latest messages as fit above the command line
+    let room = height.saturating_sub(left.len() + 1);
+    left.extend(self.messages.iter().skip(self.messages.len().saturating_sub(room)).cloned());
+    left.push(format!("> {}_", self.command));
+
+    let moves = state.san_history.chunks(2).enumerate()
+      .map(|(number, pair)| format!("{:>3}. {}", number + 1, pair.join(" ")))
+      .collect::<Vec<_>>();
+    let mut right = vec![String::new(), String::new(), "Moves".to_owned()];
+    right.extend(moves.iter().skip(moves.len().saturating_sub(MOVE_LIST_LINES)).cloned());
+    right.resize(3 + MOVE_LIST_LINES, String::new());
+    right.push("Engine".to_owned());
+    right.extend(self.engine.iter().cloned());
+    (left, right)
+  }
+
+  fn draw(&self, terminal: &mut RawTerminal, state: &GameState) -> io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let (left, right) = self.screen(state, height as usize);
+    let side_width = (width.saturating_sub(SIDE_PANE_COLUMN)) as usize;
+    for row in 0..height {
+      queue!(terminal.stdout, cursor::MoveTo(0, row))?;
+      // the board lines carry colour codes, so only the plain lines get cut to fit
+      let line = left.get(row as usize).map(String::as_str).unwrap_or_default();
+      let line = if line.contains('\x1b') { line.to_owned() } else { line.chars().take(width as usize).collect() };
+      queue!(terminal.stdout, Print(line), Clear(ClearType::UntilNewLine))?;
+      // the side pane only sits beside the board, below it the messages get the whole width
+      if let Some(text) = right.get(row as usize).filter(|text| !text.is_empty() && side_width > 0) {
+        queue!(terminal.stdout, cursor::MoveTo(SIDE_PANE_COLUMN, row), Print(text.chars().take(side_width).collect::<String>()))?;
+      }
+    }
+    terminal.stdout.flush()
+  }
+}
+
+// full screen over the same game and dispatcher as the command line, true when the player quit
+pub fn run_tui(session: &mut Session) -> io::Result<bool> {
+  let (mut tui, render) = {
+    let mut state = session.game.lock().unwrap();
+    let render = state.render;
+    // finish_turn printing the board or the result would scribble over the screen, the status line shows how it ended
+    state.render.silent = true;
+    (Tui::new(&state), render)
+  };
+  let auto_queen = session.auto_queen;
+  session.auto_queen = true;
+
+  let result = (|| {
+    let mut terminal = RawTerminal::enter()?;
+    loop {
+      let state = session.game.lock().unwrap().clone();
+      tui.draw(&mut terminal, &state)?;
+      // the engine's reply is played as soon as its move is on the screen
+      if let Some(text) = session.computer_move() {
+        tui.say(&text);
+        continue;
+      }
+      if !event::poll(REDRAW_INTERVAL)? {
+        continue;
+      }
+      if let TerminalEvent::Key(key) = event::read()? {
+        match tui.key(session, key) {
+          TuiExit::Stay => {},
+          TuiExit::BackToCli => return Ok(false),
+          TuiExit::Quit => return Ok(true),
+        }
+      }
+    }
+  })();
+
+  session.auto_queen = auto_queen;
+  // flip and board style changes made in the tui are kept
+  let mut state = session.game.lock().unwrap();
+  state.render.silent = render.silent;
+  state.render.cursor = None;
+  state.render.selected = None;
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+  use crate::{get_legal_move_list, Dispatcher, EventKind, ThreadPool};
+
+  fn press(tui: &mut Tui, session: &mut Session, code: KeyCode) -> TuiExit {
+    tui.key(session, KeyEvent::new(code, KeyModifiers::NONE))
+  }
+
+  #[test]
+  fn arrow_keys_play_moves() {
+    let pool = ThreadPool::new(1).unwrap();
+    let mut dispatcher = Dispatcher::new(&pool);
+    let mut state = GameState::new();
+    get_legal_move_list(&mut state);
+    state.render.silent = true;
+    let game = Arc::new(Mutex::new(state));
+    dispatcher.register_handler(EventKind::Move, game.clone());
+    dispatcher.register_handler(EventKind::Surrender, game.clone());
+    dispatcher.start();
+    let mut session = Session::new(game.clone(), &mut dispatcher, None, "Players".to_owned());
+
+    // e2 to e4 with the cursor, then a typed reply
+    let mut tui = Tui::new(&game.lock().unwrap());
+    for code in [KeyCode::Enter, KeyCode::Up, KeyCode::Up, KeyCode::Enter] {
+      assert_eq!(press(&mut tui, &mut session, code), TuiExit::Stay);
+    }
+    for c in "e5".chars() {
+      press(&mut tui, &mut session, KeyCode::Char(c));
+    }
+    press(&mut tui, &mut session, KeyCode::Enter);
+    assert_eq!(game.lock().unwrap().san_history, vec!["e4", "e5"]);
+
+    // black's pawn on e5 isnt white's to move
+    press(&mut tui, &mut session, KeyCode::Up);
+    press(&mut tui, &mut session, KeyCode::Enter);
+    let (left, right) = tui.screen(&game.lock().unwrap(), 30);
+    assert!(right.contains(&"  1. e4 e5".to_owned()));
+    assert!(left.contains(&"No piece of yours on e5".to_owned()) && left.last().unwrap() == "> _");
+
+    // the ending goes on the screen rather than being printed over it
+    for c in "resign".chars() {
+      press(&mut tui, &mut session, KeyCode::Char(c));
+    }
+    press(&mut tui, &mut session, KeyCode::Enter);
+    let (left, _) = tui.screen(&game.lock().unwrap(), 30);
+    assert!(left.contains(&"Game over: Resignation(true)".to_owned()));
+    assert_eq!(press(&mut tui, &mut session, KeyCode::Esc), TuiExit::BackToCli);
+  }
+}
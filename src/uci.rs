@@ -0,0 +1,298 @@
+use std::io::{BufRead, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{from_fen, get_legal_move_list, make_move, move_to_long_algebraic, parse_long_algebraic, queen_promotion, search, CancelToken, Event, GameState, Handler};
+use crate::{BoardRep, Move, PoolCreationError, Rng, SearchLimits, TaskHandle, ThreadPool, Weakening, MAX_DEPTH, MAX_ELO, MIN_ELO};
+
+// without a movestogo the clock is shared out as if this many moves were left
+const MOVES_TO_GO: u64 = 30;
+
+// the engine side of the universal chess interface, for playing it from a chess GUI
+// commands come in one line at a time, searches run on their own thread so stop is heard
+pub struct UciEngine<W: Write + Send + 'static> {
+  state: GameState,
+  limit_strength: bool,
+  elo: u16,
+  threads: ThreadPool,
+  thinking: Option<TaskHandle<()>>,
+  output: Arc<Mutex<W>>,
+}
+
+// whatever a go command asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct GoOptions {
+  depth: Option<u8>,
+  nodes: Option<u64>,
+  movetime: Option<u64>,
+  white_ms: Option<u64>,
+  black_ms: Option<u64>,
+  white_increment: u64,
+  black_increment: u64,
+  moves_to_go: Option<u64>,
+  infinite: bool,
+}
+
+impl GoOptions {
+  fn parse(arguments: &str) -> Self {
+    let mut options = GoOptions::default();
+    let mut words = arguments.split_whitespace();
+    while let Some(word) = words.next() {
+      let mut number = || words.next().and_then(|value| value.parse::<u64>().ok());
+      match word {
+        "depth" => options.depth = number().map(|depth| depth.min(MAX_DEPTH as u64) as u8),
+        "nodes" => options.nodes = number(),
+        "movetime" => options.movetime = number(),
+        "wtime" => options.white_ms = number(),
+        "btime" => options.black_ms = number(),
+        "winc" => options.white_increment = number().unwrap_or(0),
+        "binc" => options.black_increment = number().unwrap_or(0),
+        "movestogo" => options.moves_to_go = number(),
+        "infinite" => options.infinite = true,
+        _ => {},
+      }
+    }
+    options
+  }
+
+  // a fixed time wins over the clock, nothing at all searches until stop
+  fn limits(&self, white_to_move: bool) -> SearchLimits {
+    let (clock, increment) = if white_to_move { (self.white_ms, self.white_increment) } else { (self.black_ms, self.black_increment) };
+    let time = match (self.infinite, self.movetime, clock) {
+      (true, _, _) => None,
+      (false, Some(movetime), _) => Some(movetime),
+      // a little held back so the gui never sees a flag fall
+      (false, None, Some(clock)) => Some((clock / self.moves_to_go.unwrap_or(MOVES_TO_GO).max(1) + increment / 2).min(clock.saturating_sub(50))),
+      (false, None, None) => None,
+    };
+    SearchLimits { depth: self.depth.unwrap_or(MAX_DEPTH), nodes: self.nodes, time: time.map(Duration::from_millis) }
+  }
+
+  fn searches_until_stopped(&self) -> bool {
+    self.infinite || (self.depth.is_none() && self.nodes.is_none() && self.movetime.is_none() && self.white_ms.is_none() && self.black_ms.is_none())
+  }
+}
+
+impl<W: Write + Send + 'static> UciEngine<W> {
+  pub fn new(output: W) -> Result<Self, PoolCreationError> {
+    Ok(UciEngine {
+      state: start_position(),
+      limit_strength: false,
+      elo: MAX_ELO,
+      threads: ThreadPool::new(1)?,
+      thinking: None,
+      output: Arc::new(Mutex::new(output)),
+    })
+  }
+
+  fn send(&self, line: &str) {
+    let mut output = self.output.lock().unwrap();
+    let _ = writeln!(output, "{}", line);
+    let _ = output.flush();
+  }
+
+  // false once the gui says quit
+  pub fn command(&mut self, line: &str) -> bool {
+    let line = line.trim();
+    let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+      "uci" => {
+        self.send("id name cheess");
+        self.send("id author cheess");
+        self.send("option name UCI_LimitStrength type check default false");
+        self.send(&format!("option name UCI_Elo type spin default {} min {} max {}", MAX_ELO, MIN_ELO, MAX_ELO));
+        self.send("uciok");
+      },
+      "isready" => self.send("readyok"),
+      "setoption" => self.set_option(arguments),
+      "ucinewgame" => {
+        self.stop();
+        self.state = start_position();
+      },
+      "position" => {
+        self.stop();
+        if let Err(e) = self.set_position(arguments) {
+          self.send(&format!("info string {}", e));
+        }
+      },
+      "go" => self.go(GoOptions::parse(arguments)),
+      "stop" => self.stop(),
+      "quit" => {
+        self.stop();
+        return false;
+      },
+      // debug, register and ponderhit need nothing from this engine
+      _ => {},
+    }
+    true
+  }
+
+  pub fn run(mut self, input: impl BufRead) {
+    for line in input.lines() {
+      let Ok(line) = line else {
+        break;
+      };
+      if !self.command(&line) {
+        return;
+      }
+    }
+    self.stop();
+  }
+
+  // setoption name UCI_Elo value 1500, option names dont care about case
+  fn set_option(&mut self, arguments: &str) {
+    let arguments = arguments.trim().strip_prefix("name ").unwrap_or(arguments);
+    let (name, value) = arguments.split_once(" value ").unwrap_or((arguments, ""));
+    match name.trim().to_lowercase().as_str() {
+      "uci_limitstrength" => self.limit_strength = value.trim().eq_ignore_ascii_case("true"),
+      "uci_elo" => match value.trim().parse::<u16>() {
+        Ok(elo) => self.elo = elo.clamp(MIN_ELO, MAX_ELO),
+        Err(_) => self.send(&format!("info string Invalid UCI_Elo: {}", value.trim())),
+      },
+      _ => self.send(&format!("info string Unknown option: {}", name.trim())),
+    }
+  }
+
+  // position startpos moves e2e4 e7e5, or position fen <fen> moves ...
+  fn set_position(&mut self, arguments: &str) -> Result<(), String> {
+    let (position, moves) = match arguments.split_once("moves") {
+      Some((position, moves)) => (position.trim(), moves),
+      None => (arguments.trim(), ""),
+    };
+    let mut state = match position.split_once(' ') {
+      _ if position == "startpos" => start_position(),
+      Some(("fen", fen)) => {
+        let mut state = from_fen(fen).map_err(|e| e.to_string())?;
+        state.render.silent = true;
+        state
+      },
+      _ => return Err(format!("Unknown position: {}", position)),
+    };
+    for text in moves.split_whitespace() {
+      let (translation, promotion) = parse_long_algebraic(text).ok_or_else(|| format!("Invalid move: {}", text))?;
+      // a promotion without a piece would ask on stdin, which is the gui talking
+      let promotion = promotion.or(queen_promotion(&state.board, translation));
+      let played = state.move_history.len();
+      state.handle_mut(Event::Move { translation, promotion });
+      if state.move_history.len() == played {
+        return Err(format!("Illegal move: {}", text));
+      }
+    }
+    self.state = state;
+    Ok(())
+  }
+
+  fn go(&mut self, options: GoOptions) {
+    self.stop();
+    let state = self.state.clone();
+    let limits = options.limits(state.player_turn == 1);
+    let weakening = self.limit_strength.then(|| Weakening::for_elo(self.elo));
+    let output = self.output.clone();
+    self.thinking = Some(self.threads.spawn(move |token: &CancelToken| {
+      let best_move = match weakening {
+        Some(weakening) => weakening.choose(&state, limits, token, &mut Rng::from_time()),
+        None => {
+          let result = search(&state, limits, token);
+          let pv = line_to_uci(&state.board, &result.pv);
+          let score = match result.mate_in() {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", result.score),
+          };
+          let mut output = output.lock().unwrap();
+          let _ = writeln!(output, "info depth {} score {} nodes {} pv {}", result.depth, score, result.nodes, pv);
+          result.best_move
+        },
+      };
+      // bestmove can only come after stop when the gui asked for an endless search
+      if options.searches_until_stopped() {
+        while !token.is_cancelled() {
+          std::thread::sleep(Duration::from_millis(10));
+        }
+      }
+      let best_move = best_move.map(|translation| move_to_long_algebraic(translation, queen_promotion(&state.board, translation)));
+      let mut output = output.lock().unwrap();
+      let _ = writeln!(output, "bestmove {}", best_move.unwrap_or_else(|| "0000".to_owned()));
+      let _ = output.flush();
+    }));
+  }
+
+  // the search that was running has said its bestmove by the time this returns
+  fn stop(&mut self) {
+    if let Some(thinking) = self.thinking.take() {
+      thinking.cancel();
+      let _ = thinking.join();
+    }
+  }
+}
+
+// nothing gets printed after moves, stdout belongs to the gui
+fn start_position() -> GameState {
+  let mut state = GameState::new();
+  get_legal_move_list(&mut state);
+  state.render.silent = true;
+  state
+}
+
+// each move of a line needs the board it was played on to know if it promotes
+fn line_to_uci(board: &BoardRep, line: &[Move]) -> String {
+  let mut board = board.clone();
+  let mut moves = Vec::new();
+  for translation in line {
+    moves.push(move_to_long_algebraic(*translation, queen_promotion(&board, *translation)));
+    board = make_move(&board, *translation);
+  }
+  moves.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // the output buffer is shared so the test can read what the engine wrote
+  #[derive(Clone, Default)]
+  struct Shared(Arc<Mutex<Vec<u8>>>);
+
+  impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+      self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn plays_through_uci_with_limited_strength() {
+    let written = Shared::default();
+    let mut engine = UciEngine::new(written.clone()).unwrap();
+    for line in ["uci", "setoption name UCI_LimitStrength value true", "setoption name UCI_Elo value 800", "isready", "position startpos moves e2e4 e7e5", "go depth 3"] {
+      assert!(engine.command(line));
+    }
+    assert_eq!(engine.state.san_history, vec!["e4", "e5"]);
+    assert!(engine.limit_strength && engine.elo == 800);
+    assert!(!engine.command("quit"));
+
+    let text = String::from_utf8(written.0.lock().unwrap().clone()).unwrap();
+    assert!(text.contains("option name UCI_Elo type spin") && text.contains("uciok\nreadyok\n"));
+    let best_move = text.lines().last().unwrap().strip_prefix("bestmove ").unwrap();
+    assert!(engine.state.move_list.white.iter().any(|translation| move_to_long_algebraic(*translation, None) == best_move));
+  }
+
+  #[test]
+  fn weaker_settings_pick_worse_moves() {
+    // the queen on d5 is free, full strength always takes it
+    let state = from_fen("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+    let strongest = Weakening::for_elo(MAX_ELO);
+    let weakest = Weakening::for_elo(MIN_ELO);
+    assert!(weakest.depth < strongest.depth && weakest.blunders > 0 && strongest.blunders == 0);
+
+    let mut rng = Rng::seeded(7);
+    let limits = SearchLimits::depth(2);
+    let capture = parse_long_algebraic("d2d5").unwrap().0;
+    let strong = (0..20).filter(|_| strongest.choose(&state, limits, &CancelToken::new(), &mut rng) == Some(capture)).count();
+    let weak = (0..20).filter(|_| weakest.choose(&state, limits, &CancelToken::new(), &mut rng) == Some(capture)).count();
+    assert_eq!(strong, 20);
+    assert!(weak < 20);
+  }
+}
@@ -0,0 +1,364 @@
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+use crate::PieceColour::*;
+use crate::PieceColour;
+use crate::{announce, boardrep_to_bitboard, King, generate_start_board, is_in_check, make_move, standard_game_end, standard_legal_moves};
+use crate::{AntichessRules, AtomicRules, BoardRep, CrazyhouseRules, GameEnd, GameState, Move, PieceSet, PlayerValidMoves};
+use crate::{EMPTY, PAWN, ROOK, KNIGHT, BISHOP, QUEEN, KING};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Variant {
+  #[default]
+  Standard,
+  KingOfTheHill,
+  ThreeCheck,
+  RacingKings,
+  Crazyhouse,
+  Atomic,
+  Antichess,
+  Horde,
+}
+
+/// Hooks a variant can override, anything left alone plays like normal chess.
+pub trait VariantRules: Sync {
+  fn start_board(&self) -> BoardRep {
+    generate_start_board()
+  }
+
+  fn castling_allowed(&self) -> bool {
+    true
+  }
+
+  // whether colour's king is under attack, get_legal_move_list uses this for the check flags
+  fn in_check(&self, board: &BoardRep, colour: PieceColour) -> bool {
+    is_in_check(board, colour)
+  }
+
+  // legal moves for both colours in the current position
+  fn legal_moves(&self, state: &mut GameState) -> PlayerValidMoves {
+    standard_legal_moves(state)
+  }
+
+  // called by take_turn once the board has been updated, before the next move list is generated
+  fn after_move(&self, _state: &mut GameState, _translation: Move, _premove_board: &BoardRep) {}
+
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    standard_game_end(state)
+  }
+
+  // (white, black) cant win any more, a bare king still wins some variants
+  fn insufficient_material(&self, state: &GameState) -> (bool, bool) {
+    GameEnd::insufficient_materials(state)
+  }
+}
+
+pub struct StandardRules;
+pub struct KingOfTheHillRules;
+pub struct ThreeCheckRules;
+pub struct RacingKingsRules;
+pub struct HordeRules;
+
+impl VariantRules for StandardRules {}
+
+// d4 e4 d5 e5
+const HILL: [usize; 4] = [27, 28, 35, 36];
+
+impl VariantRules for KingOfTheHillRules {
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    for index in HILL {
+      if state.board.0[index] != KING {
+        continue;
+      }
+      return match state.board.1[index] {
+        White => {
+          announce(state, "White Wins by reaching the hill");
+          Some(GameEnd::KingOfTheHill(false))
+        },
+        _ => {
+          announce(state, "Black Wins by reaching the hill");
+          Some(GameEnd::KingOfTheHill(true))
+        },
+      };
+    }
+    standard_game_end(state)
+  }
+
+  fn insufficient_material(&self, _state: &GameState) -> (bool, bool) {
+    (false, false)
+  }
+}
+
+impl VariantRules for ThreeCheckRules {
+  fn after_move(&self, state: &mut GameState, translation: Move, premove_board: &BoardRep) {
+    match premove_board.1[usize::from(translation.0)] {
+      White if is_in_check(&state.board, Black) => state.checks_given_by_white += 1,
+      Black if is_in_check(&state.board, White) => state.checks_given_by_black += 1,
+      _ => {},
+    }
+  }
+
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    if state.checks_given_by_white >= 3 {
+      announce(state, "White Wins by three checks");
+      return Some(GameEnd::ThreeCheck(false));
+    }
+    if state.checks_given_by_black >= 3 {
+      announce(state, "Black Wins by three checks");
+      return Some(GameEnd::ThreeCheck(true));
+    }
+    standard_game_end(state)
+  }
+}
+
+impl VariantRules for RacingKingsRules {
+  // 8/8/8/8/8/8/krbnNBRK/qrbnNBRQ
+  fn start_board(&self) -> BoardRep {
+    let mut piece_type = vec![EMPTY; 64];
+    let mut piece_colour = vec![Empty; 64];
+    let first_rank = [QUEEN, ROOK, BISHOP, KNIGHT, KNIGHT, BISHOP, ROOK, QUEEN];
+    let second_rank = [KING, ROOK, BISHOP, KNIGHT, KNIGHT, BISHOP, ROOK, KING];
+
+    for file in 0..8 {
+      let colour = if file < 4 { Black } else { White };
+      piece_type[file] = first_rank[file];
+      piece_type[8 + file] = second_rank[file];
+      piece_colour[file] = colour;
+      piece_colour[8 + file] = colour;
+    }
+    (piece_type, piece_colour)
+  }
+
+  fn castling_allowed(&self) -> bool {
+    false
+  }
+
+  // nobody is ever allowed to be in check, so giving check is illegal too
+  fn legal_moves(&self, state: &mut GameState) -> PlayerValidMoves {
+    let moves = standard_legal_moves(state);
+    let board = &state.board;
+    let gives_no_check = |translation: &Move| {
+      let simulated_board = make_move(board, *translation);
+      !is_in_check(&simulated_board, White) && !is_in_check(&simulated_board, Black)
+    };
+    PlayerValidMoves {
+      white: moves.white.into_iter().filter(gives_no_check).collect(),
+      black: moves.black.into_iter().filter(gives_no_check).collect(),
+      ..Default::default()
+    }
+  }
+
+  // first king to the 8th rank wins, but black gets one more move to draw if white gets there first
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    let on_last_rank = |colour| (56..64).any(|index| state.board.0[index] == KING && state.board.1[index] == colour);
+    let white_finished = on_last_rank(White);
+    let black_finished = on_last_rank(Black);
+    let black_just_moved = state.player_turn == 2;
+
+    if white_finished && black_finished {
+      announce(state, "Draw, both kings reached the 8th rank");
+      return Some(GameEnd::RaceDraw);
+    }
+    if black_finished {
+      announce(state, "Black Wins the race");
+      return Some(GameEnd::RaceFinished(true));
+    }
+    if white_finished && black_just_moved {
+      announce(state, "White Wins the race");
+      return Some(GameEnd::RaceFinished(false));
+    }
+    if white_finished {
+      return None;
+    }
+
+    let no_moves = match state.player_turn {
+      1 => state.move_list.black.is_empty(),
+      _ => state.move_list.white.is_empty(),
+    };
+    if no_moves {
+      announce(state, "Draw by Stalemate");
+      return Some(GameEnd::Stalemate);
+    }
+    standard_game_end(state)
+  }
+
+  fn insufficient_material(&self, _state: &GameState) -> (bool, bool) {
+    (false, false)
+  }
+}
+
+impl VariantRules for HordeRules {
+  // rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP
+  fn start_board(&self) -> BoardRep {
+    let mut board = generate_start_board();
+    for index in 0..40 {
+      let extra_rank = index >= 32 && ![32, 35, 36, 39].contains(&index);
+      if index < 32 || extra_rank {
+        board.0[index] = PAWN;
+        board.1[index] = White;
+      } else {
+        board.0[index] = EMPTY;
+        board.1[index] = Empty;
+      }
+    }
+    board
+  }
+
+  // white has no king so the only way to lose is running out of pawns
+  fn game_end(&self, state: &mut GameState) -> Option<GameEnd> {
+    if !state.board.1.contains(&White) {
+      announce(state, "Black Wins by capturing the horde");
+      return Some(GameEnd::HordeCaptured);
+    }
+    standard_game_end(state)
+  }
+
+  // a lone black king can still eat the horde, and the horde can promote
+  fn insufficient_material(&self, _state: &GameState) -> (bool, bool) {
+    (false, false)
+  }
+}
+
+impl Variant {
+  pub fn rules(&self) -> &'static dyn VariantRules {
+    match self {
+      Variant::Standard => &StandardRules,
+      Variant::KingOfTheHill => &KingOfTheHillRules,
+      Variant::ThreeCheck => &ThreeCheckRules,
+      Variant::RacingKings => &RacingKingsRules,
+      Variant::Crazyhouse => &CrazyhouseRules,
+      Variant::Atomic => &AtomicRules,
+      Variant::Antichess => &AntichessRules,
+      Variant::Horde => &HordeRules,
+    }
+  }
+}
+
+impl FromStr for Variant {
+  type Err = String;
+
+  fn from_str(input: &str) -> Result<Self, Self::Err> {
+    match input.trim().to_lowercase().replace(['-', ' ', '_'], "").as_str() {
+      "" | "standard" | "chess" => Ok(Variant::Standard),
+      "kingofthehill" | "koth" => Ok(Variant::KingOfTheHill),
+      "threecheck" | "3check" => Ok(Variant::ThreeCheck),
+      "racingkings" => Ok(Variant::RacingKings),
+      "crazyhouse" | "zh" => Ok(Variant::Crazyhouse),
+      "atomic" => Ok(Variant::Atomic),
+      "antichess" | "giveaway" | "losers" => Ok(Variant::Antichess),
+      "horde" => Ok(Variant::Horde),
+      _ => Err(format!("Unknown variant: {}", input)),
+    }
+  }
+}
+
+impl GameState {
+  pub fn new_variant(variant: Variant) -> Self {
+    let rules = variant.rules();
+    let mut state = GameState::new();
+    state.variant = variant;
+    state.board = rules.start_board();
+    state.white_pieces = PieceSet::from_board(&state.board, White);
+    state.black_pieces = PieceSet::from_board(&state.board, Black);
+    state.table_states_since_last_capture_or_pawn_move = vec![boardrep_to_bitboard(&state.board)];
+    // horde white has no king to castle with
+    King::check_to_disable_castling(&mut state);
+    if !rules.castling_allowed() {
+      state.white_can_castle_kingside = false;
+      state.white_can_castle_queenside = false;
+      state.black_can_castle_kingside = false;
+      state.black_can_castle_queenside = false;
+    }
+    state
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::square;
+  use crate::{from_fen, get_legal_move_list, take_turn};
+
+  #[test]
+  fn king_of_the_hill() {
+    let mut state = from_fen("4k3/8/8/8/8/4K3/8/8 w - - 0 1").unwrap();
+    state.variant = Variant::KingOfTheHill;
+    get_legal_move_list(&mut state);
+    take_turn(&mut state, (square("e3"), square("e4")));
+    assert!(state.game_over);
+  }
+
+  #[test]
+  fn three_check() {
+    let mut state = from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+    state.variant = Variant::ThreeCheck;
+    get_legal_move_list(&mut state);
+    for (white_move, black_move) in [(("a1", "a8"), ("e8", "e7")), (("a8", "a7"), ("e7", "e6")), (("a7", "a6"), ("e6", "e5"))] {
+      take_turn(&mut state, (square(white_move.0), square(white_move.1)));
+      if state.game_over {
+        break;
+      }
+      take_turn(&mut state, (square(black_move.0), square(black_move.1)));
+    }
+    assert_eq!(state.checks_given_by_white, 3);
+    assert!(state.game_over);
+  }
+
+  #[test]
+  fn racing_kings() {
+    let mut state = GameState::new_variant(Variant::RacingKings);
+    get_legal_move_list(&mut state);
+    assert!(!state.white_can_castle_kingside);
+    assert_eq!(state.board.0[usize::from(square("h2"))], KING);
+
+    // every legal move keeps both kings out of check
+    for translation in state.move_list.white.clone() {
+      let board = make_move(&state.board, translation);
+      assert!(!is_in_check(&board, White) && !is_in_check(&board, Black));
+    }
+
+    // white reaching the 8th rank gives black one more move
+    let mut race = from_fen("8/7K/k7/8/8/8/8/8 w - - 0 1").unwrap();
+    race.variant = Variant::RacingKings;
+    get_legal_move_list(&mut race);
+    take_turn(&mut race, (square("h7"), square("h8")));
+    assert!(!race.game_over);
+    take_turn(&mut race, (square("a6"), square("a7")));
+    assert!(race.game_over);
+  }
+
+  #[test]
+  fn horde() {
+    let mut state = GameState::new_variant(Variant::Horde);
+    assert_eq!(crate::to_fen(&state), "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1");
+    assert_eq!(state.white_pieces.count(PAWN), 36);
+    get_legal_move_list(&mut state);
+    assert!(!state.move_list.white.contains(&(square("a1"), square("a3"))));
+    assert!(state.move_list.white.contains(&(square("b5"), square("b6"))));
+
+    // back rank pawns double step once the way is clear
+    let mut open = from_fen("4k3/8/8/8/8/8/8/P7 w - - 0 1").unwrap();
+    open.variant = Variant::Horde;
+    get_legal_move_list(&mut open);
+    assert!(open.move_list.white.contains(&(square("a1"), square("a3"))));
+    take_turn(&mut open, (square("a1"), square("a2")));
+    take_turn(&mut open, (square("e8"), square("e7")));
+    assert!(!open.game_over);
+  }
+
+  #[test]
+  fn flags_fall_in_every_variant() {
+    let variants = [
+      Variant::Standard, Variant::KingOfTheHill, Variant::ThreeCheck, Variant::RacingKings,
+      Variant::Crazyhouse, Variant::Atomic, Variant::Antichess, Variant::Horde,
+    ];
+    for variant in variants {
+      let mut state = GameState::new_variant(variant);
+      state.render.silent = true;
+      get_legal_move_list(&mut state);
+      assert_eq!(variant.rules().game_end(&mut state), None, "{:?}", variant);
+      state.white_timer = std::time::Duration::ZERO;
+      assert_eq!(variant.rules().game_end(&mut state), Some(GameEnd::TimeOut(true)), "{:?}", variant);
+    }
+  }
+}
@@ -0,0 +1,299 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use tungstenite::Message;
+
+use crate::PieceColour::White;
+use crate::{char_to_piece, piece_to_char, get_legal_move_list, to_fen, Coordinates, DropMove, Event, GameEnd, GameId, GameState, Handler};
+use crate::{flag_fall, Clocks, ThreadPool, TimeControl, Variant};
+use crate::{PAWN, ROOK, KNIGHT, BISHOP, QUEEN};
+
+pub const DEFAULT_WEBSOCKET_PORT: u16 = 9001;
+
+// squares are algebraic ("e2") so browsers dont need to know the board indexing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiRequest {
+  CreateGame {
+    #[serde(default)]
+    variant: Option<String>,
+    // PGN style, eg. "300+3"
+    #[serde(default)]
+    time_control: Option<String>,
+  },
+  Move { game_id: GameId, from: String, to: String, #[serde(default)] promotion: Option<String> },
+  Drop { game_id: GameId, piece: String, square: String },
+  LegalMoves { game_id: GameId },
+  State { game_id: GameId },
+  Resign { game_id: GameId },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApiResponse {
+  State(Snapshot),
+  LegalMoves { game_id: GameId, moves: Vec<String>, drops: Vec<String> },
+  Error { message: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub game_id: GameId,
+  pub fen: String,
+  pub variant: Variant,
+  pub white_to_move: bool,
+  pub white_ms: u64,
+  pub black_ms: u64,
+  pub white_in_check: bool,
+  pub black_in_check: bool,
+  pub game_over: bool,
+  pub result: Option<GameEnd>,
+}
+
+impl Snapshot {
+  // the running clock is counted so the browser doesnt have to
+  pub fn of(game_id: GameId, state: &GameState) -> Self {
+    let clocks = Clocks::live(state);
+    Snapshot {
+      game_id,
+      fen: to_fen(state),
+      variant: state.variant,
+      white_to_move: state.player_turn == 1,
+      white_ms: clocks.white_ms,
+      black_ms: clocks.black_ms,
+      white_in_check: state.white_in_check,
+      black_in_check: state.black_in_check,
+      game_over: state.game_over,
+      result: state.result,
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct ApiGames {
+  next_id: GameId,
+  games: HashMap<GameId, GameState>,
+}
+
+fn error(message: impl Into<String>) -> ApiResponse {
+  ApiResponse::Error { message: message.into() }
+}
+
+fn square(name: &str) -> Result<Coordinates, ApiResponse> {
+  Coordinates::from_algebraic(&name.to_ascii_lowercase())
+    .ok_or_else(|| error(format!("Invalid square: {}", name)))
+}
+
+fn piece(letter: &str, allowed: &[u8]) -> Result<u8, ApiResponse> {
+  let mut letters = letter.chars();
+  match (letters.next().and_then(char_to_piece), letters.next()) {
+    (Some((piece, _)), None) if allowed.contains(&piece) => Ok(piece),
+    _ => Err(error(format!("Invalid piece: {}", letter))),
+  }
+}
+
+impl ApiGames {
+  fn create(&mut self, variant: Option<String>, time_control: Option<String>) -> Result<ApiResponse, ApiResponse> {
+    let variant = variant.as_deref().unwrap_or_default().parse::<Variant>().map_err(error)?;
+    let mut state = GameState::new_variant(variant);
+    if let Some(time_control) = time_control {
+      state.set_time_control(time_control.parse::<TimeControl>().map_err(|e| error(e.to_string()))?);
+    }
+    get_legal_move_list(&mut state);
+    state.clock = std::time::Instant::now();
+
+    self.next_id += 1;
+    let snapshot = Snapshot::of(self.next_id, &state);
+    self.games.insert(self.next_id, state);
+    Ok(ApiResponse::State(snapshot))
+  }
+
+  // feeds the game the same event the dispatcher would
+  fn apply(&mut self, game_id: GameId, event: Event) -> Result<ApiResponse, ApiResponse> {
+    let state = self.games.get_mut(&game_id).ok_or_else(|| error("No such game"))?;
+    if state.game_over {
+      return Err(error("Game is over"));
+    }
+    let moves_played = state.move_history.len();
+    let resigning = matches!(event, Event::Surrender(_));
+    state.handle_mut(event);
+    if !resigning && state.move_history.len() == moves_played {
+      return Err(error("Illegal move"));
+    }
+    Ok(ApiResponse::State(Snapshot::of(game_id, state)))
+  }
+
+  fn play_move(&mut self, game_id: GameId, from: &str, to: &str, promotion: Option<&str>) -> Result<ApiResponse, ApiResponse> {
+    // theres no stdin to ask, promote to a queen unless told otherwise
+    let promotion = match promotion {
+      Some(promotion) => piece(promotion, &[ROOK, KNIGHT, BISHOP, QUEEN])?,
+      None => QUEEN,
+    };
+    self.apply(game_id, Event::Move { translation: (square(from)?, square(to)?), promotion: Some(promotion) })
+  }
+
+  fn play_drop(&mut self, game_id: GameId, letter: &str, name: &str) -> Result<ApiResponse, ApiResponse> {
+    let drop = DropMove { piece: piece(letter, &[PAWN, ROOK, KNIGHT, BISHOP, QUEEN])?, square: square(name)? };
+    self.apply(game_id, Event::Drop(drop))
+  }
+
+  fn legal_moves(&self, game_id: GameId) -> Result<ApiResponse, ApiResponse> {
+    let state = self.games.get(&game_id).ok_or_else(|| error("No such game"))?;
+    let (moves, drops) = match state.player_turn {
+      1 => (&state.move_list.white, &state.move_list.white_drops),
+      _ => (&state.move_list.black, &state.move_list.black_drops),
+    };
+    let mut moves = moves.iter()
+      .map(|(origin, destination)| format!("{}{}", origin.to_algebraic(), destination.to_algebraic()))
+      .collect::<Vec<_>>();
+    moves.sort();
+    let drops = drops.iter()
+      .map(|drop| format!("{}@{}", piece_to_char(drop.piece, White), drop.square.to_algebraic()))
+      .collect();
+    Ok(ApiResponse::LegalMoves { game_id, moves, drops })
+  }
+
+  pub fn handle(&mut self, request: ApiRequest) -> ApiResponse {
+    // nothing ticks between requests, so a flag that fell meanwhile is noticed before anything is answered
+    for state in self.games.values_mut() {
+      flag_fall(state);
+    }
+    let response = match request {
+      ApiRequest::CreateGame { variant, time_control } => self.create(variant, time_control),
+      ApiRequest::Move { game_id, from, to, promotion } => self.play_move(game_id, &from, &to, promotion.as_deref()),
+      ApiRequest::Drop { game_id, piece, square } => self.play_drop(game_id, &piece, &square),
+      ApiRequest::LegalMoves { game_id } => self.legal_moves(game_id),
+      ApiRequest::State { game_id } => {
+        self.games.get(&game_id).map(|state| ApiResponse::State(Snapshot::of(game_id, state))).ok_or_else(|| error("No such game"))
+      },
+      ApiRequest::Resign { game_id } => self.apply(game_id, Event::Surrender(None)),
+    };
+    response.unwrap_or_else(|error| error)
+  }
+}
+
+fn serve_socket(stream: TcpStream, games: Arc<Mutex<ApiGames>>) {
+  let mut socket = match tungstenite::accept(stream) {
+    Ok(socket) => socket,
+    Err(e) => {
+      println!("WebSocket handshake failed: {}", e);
+      return;
+    },
+  };
+
+  loop {
+    let text = match socket.read() {
+      Ok(Message::Text(text)) => text,
+      Ok(Message::Close(_)) | Err(_) => break,
+      // pings are answered by tungstenite itself
+      Ok(_) => continue,
+    };
+    let response = match serde_json::from_str::<ApiRequest>(text.as_str()) {
+      Ok(request) => games.lock().unwrap().handle(request),
+      Err(e) => error(format!("Bad request: {}", e)),
+    };
+    let Ok(json) = serde_json::to_string(&response) else {
+      break;
+    };
+    if socket.send(Message::text(json)).is_err() {
+      break;
+    }
+  }
+}
+
+pub struct WebSocketApi {
+  listener: TcpListener,
+  games: Arc<Mutex<ApiGames>>,
+  threadpool: ThreadPool,
+}
+
+impl WebSocketApi {
+  // each open socket holds a thread
+  pub fn bind(address: impl ToSocketAddrs, max_connections: usize) -> io::Result<Self> {
+    let threadpool = ThreadPool::new(max_connections)
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))?;
+    Ok(WebSocketApi { listener: TcpListener::bind(address)?, games: Arc::new(Mutex::new(ApiGames::default())), threadpool })
+  }
+
+  pub fn local_addr(&self) -> io::Result<SocketAddr> {
+    self.listener.local_addr()
+  }
+
+  pub fn run(&self) {
+    for stream in self.listener.incoming() {
+      match stream {
+        Ok(stream) => {
+          let games = self.games.clone();
+          self.threadpool.execute(move || serve_socket(stream, games));
+        },
+        Err(e) => println!("Connection failed: {}", e),
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+
+  fn request(socket: &mut tungstenite::WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>, json: &str) -> ApiResponse {
+    socket.send(Message::text(json)).unwrap();
+    let Message::Text(text) = socket.read().unwrap() else {
+      panic!("expected a text reply");
+    };
+    serde_json::from_str(text.as_str()).unwrap()
+  }
+
+  #[test]
+  fn browser_round_trip() {
+    let api = Arc::new(WebSocketApi::bind("127.0.0.1:0", 2).unwrap());
+    let address = api.local_addr().unwrap();
+    let running = api.clone();
+    std::thread::spawn(move || running.run());
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{}", address)).unwrap();
+    let ApiResponse::State(created) = request(&mut socket, r#"{"type": "create_game", "time_control": "180+2"}"#) else {
+      panic!("game should be created");
+    };
+    assert!(created.white_ms > 179_000 && created.black_ms == 180_000);
+
+    let game_id = created.game_id;
+    let ApiResponse::LegalMoves { moves, drops, .. } = request(&mut socket, &format!(r#"{{"type": "legal_moves", "game_id": {}}}"#, game_id)) else {
+      panic!("expected legal moves");
+    };
+    assert_eq!(moves.len(), 20);
+    assert!(moves.contains(&"g1f3".to_owned()) && drops.is_empty());
+
+    let reply = request(&mut socket, &format!(r#"{{"type": "move", "game_id": {}, "from": "e2", "to": "e5"}}"#, game_id));
+    assert!(matches!(reply, ApiResponse::Error { .. }));
+    let ApiResponse::State(after) = request(&mut socket, &format!(r#"{{"type": "move", "game_id": {}, "from": "e2", "to": "e4"}}"#, game_id)) else {
+      panic!("e2e4 is legal");
+    };
+    assert!(!after.white_to_move && after.fen.starts_with("rnbqkbnr/pppppppp/8/8/4P3/"));
+
+    let ApiResponse::State(resigned) = request(&mut socket, &format!(r#"{{"type": "resign", "game_id": {}}}"#, game_id)) else {
+      panic!("resigning returns the final state");
+    };
+    assert_eq!(resigned.result, Some(GameEnd::Resignation(false)));
+  }
+
+  #[test]
+  fn flag_falls_between_requests() {
+    let mut games = ApiGames::default();
+    let ApiResponse::State(created) = games.handle(ApiRequest::CreateGame { variant: None, time_control: Some("60".to_owned()) }) else {
+      panic!("game should be created");
+    };
+    games.games.get_mut(&created.game_id).unwrap().white_timer = Duration::ZERO;
+
+    let ApiResponse::State(state) = games.handle(ApiRequest::State { game_id: created.game_id }) else {
+      panic!("the game should still be there");
+    };
+    assert!(state.game_over && state.white_ms == 0);
+    assert_eq!(state.result, Some(GameEnd::TimeOut(true)));
+    let late = games.handle(ApiRequest::Move { game_id: created.game_id, from: "e2".to_owned(), to: "e4".to_owned(), promotion: None });
+    assert!(matches!(late, ApiResponse::Error { .. }));
+  }
+}
@@ -0,0 +1,22 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// stdout belongs to the gui, a game ending mid position mustnt print anything of its own
+#[test]
+fn mated_position_prints_only_protocol_lines() {
+  let mut engine = Command::new(env!("CARGO_BIN_EXE_uci"))
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+    .unwrap();
+  let commands = "uci\nposition startpos moves f2f3 e7e5 g2g4 d8h4\nisready\ngo depth 2\nisready\nquit\n";
+  engine.stdin.take().unwrap().write_all(commands.as_bytes()).unwrap();
+  let output = engine.wait_with_output().unwrap();
+  let text = String::from_utf8(output.stdout).unwrap();
+
+  let protocol = ["id ", "option ", "uciok", "readyok", "info ", "bestmove "];
+  for line in text.lines() {
+    assert!(protocol.iter().any(|prefix| line.starts_with(prefix)), "not a uci line: {:?}", line);
+  }
+  assert!(text.contains("bestmove 0000"));
+}